@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use crate::env::{Env, SystemEnv};
+
+/// Returns the directory for user-specific configuration files, where this crate keeps its
+/// rcfile backups (see [`crate::shell::Shell::rcfile_backup_dir`]).
+///
+/// This is what [`dirs::config_dir`] returns: on Linux and other Unix-like systems except
+/// macOS, `$XDG_CONFIG_HOME` when set to an absolute path and `$HOME/.config` otherwise; on
+/// macOS, `$HOME/Library/Application Support`. Returns `None` if `$HOME` isn't available.
+pub fn config_dir() -> Option<PathBuf> {
+    config_dir_with_env(&SystemEnv)
+}
+
+/// Like [`config_dir`], but reads the environment variables from `env`.
+///
+/// On Windows, where the directory doesn't come from the environment, this is always
+/// [`dirs::config_dir`].
+pub fn config_dir_with_env(env: &dyn Env) -> Option<PathBuf> {
+    if cfg!(windows) {
+        return dirs::config_dir();
+    }
+
+    if cfg!(target_os = "macos") {
+        Some(absolute_var(env, "HOME")?.join("Library/Application Support"))
+    } else {
+        xdg_config_dir_with_env(env)
+    }
+}
+
+/// Returns the XDG directory for user-specific configuration files: `$XDG_CONFIG_HOME` when
+/// set to an absolute path, and `$HOME/.config` otherwise, on every platform.
+///
+/// Unlike [`config_dir`], this isn't `$HOME/Library/Application Support` on macOS. It is
+/// where tools following the XDG conventions everywhere, such as Fish, keep their
+/// configuration. Returns `None` if `$HOME` isn't available.
+pub fn xdg_config_dir() -> Option<PathBuf> {
+    xdg_config_dir_with_env(&SystemEnv)
+}
+
+/// Like [`xdg_config_dir`], but reads the environment variables from `env`.
+pub fn xdg_config_dir_with_env(env: &dyn Env) -> Option<PathBuf> {
+    absolute_var(env, "XDG_CONFIG_HOME")
+        .or_else(|| Some(absolute_var(env, "HOME")?.join(".config")))
+}
+
+/// Returns the directory for user-specific cache files, such as compiled shell configuration.
+///
+/// This is `$XDG_CACHE_HOME` when set to an absolute path. Otherwise it is `%LOCALAPPDATA%` on
+/// Windows and `$HOME/.cache` elsewhere. Returns `None` if neither is available.
+pub fn cache_dir() -> Option<PathBuf> {
+    cache_dir_with_env(&SystemEnv)
+}
+
+/// Like [`cache_dir`], but reads the environment variables from `env`.
+pub fn cache_dir_with_env(env: &dyn Env) -> Option<PathBuf> {
+    let cache_dir = absolute_var(env, "XDG_CACHE_HOME");
+
+    if cfg!(windows) {
+        cache_dir.or_else(|| absolute_var(env, "LOCALAPPDATA"))
+    } else {
+        cache_dir.or_else(|| Some(absolute_var(env, "HOME")?.join(".cache")))
+    }
+}
+
+/// Returns the value of the environment variable `key` if it is an absolute path.
+fn absolute_var(env: &dyn Env, key: &str) -> Option<PathBuf> {
+    env.var_os(key)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
+
+/// Returns the directory for runtime files such as sockets and other per-session state.
+///
+/// This is `$XDG_RUNTIME_DIR` when set to an absolute path. Otherwise, on Linux, it is
+/// `/run/user/<uid>` if that directory exists, which is where systemd-logind and most other
+/// session managers create it. Returns `None` if neither is available.
+pub fn runtime_dir() -> Option<PathBuf> {
+    runtime_dir_with_env(&SystemEnv)
+}
+
+/// Like [`runtime_dir`], but reads `$XDG_RUNTIME_DIR` from `env`.
+///
+/// The `/run/user/<uid>` fallback is still looked up for the current user.
+pub fn runtime_dir_with_env(env: &dyn Env) -> Option<PathBuf> {
+    absolute_var(env, "XDG_RUNTIME_DIR").or_else(fallback_runtime_dir)
+}
+
+#[cfg(target_os = "linux")]
+fn fallback_runtime_dir() -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    // The owner of our own /proc entry is the current user
+    let uid = std::fs::metadata("/proc/self").ok()?.uid();
+    Some(PathBuf::from(format!("/run/user/{uid}"))).filter(|dir| dir.is_dir())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fallback_runtime_dir() -> Option<PathBuf> {
+    None
+}
@@ -0,0 +1,67 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::hash::Hash;
+use std::path::PathBuf;
+
+/// A source of environment variables.
+///
+/// Most functions in this crate that read environment variables have a `_with_env` variant
+/// reading them from an [`Env`] instead, e.g. [`crate::shell::get_home_dir_with_env`]. That
+/// allows describing another environment, such as another user's or a test's, without
+/// mutating the process environment shared by all threads.
+///
+/// [`SystemEnv`] reads the process environment, and a `HashMap` of names to values describes
+/// a fixed one:
+/// ```
+/// use std::collections::HashMap;
+/// use what_the_path::shell::{get_home_dir_with_env, POSIX};
+///
+/// let env = HashMap::from([("HOME", "/home/test")]);
+/// assert_eq!(get_home_dir_with_env(&env).unwrap().to_str(), Some("/home/test"));
+/// assert_eq!(POSIX.get_rcfiles_with_env(&env).unwrap()[0].to_str(), Some("/home/test/.profile"));
+/// ```
+pub trait Env {
+    /// Returns the value of the variable `key`, or `None` if it isn't set.
+    fn var_os(&self, key: &str) -> Option<OsString>;
+
+    /// Like [`Env::var_os`], but also returns `None` if the value isn't valid Unicode.
+    fn var(&self, key: &str) -> Option<String> {
+        self.var_os(key)?.into_string().ok()
+    }
+
+    /// Returns the current user's home directory as the system records it, which
+    /// [`crate::shell::get_home_dir_with_env`] falls back to when `HOME` isn't set.
+    ///
+    /// The default returns `None`, so an environment without `HOME` has no home directory.
+    fn fallback_home_dir(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// An [`Env`] reading the environment of the current process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemEnv;
+
+impl Env for SystemEnv {
+    fn var_os(&self, key: &str) -> Option<OsString> {
+        std::env::var_os(key)
+    }
+
+    /// Returns [`dirs::home_dir`], which looks the user up with `getpwuid_r` on Unix-like
+    /// systems (and so also finds NSS and LDAP accounts) and asks for the profile folder on
+    /// Windows.
+    fn fallback_home_dir(&self) -> Option<PathBuf> {
+        dirs::home_dir().filter(|home| !home.as_os_str().is_empty())
+    }
+}
+
+impl<K, V> Env for HashMap<K, V>
+where
+    K: Borrow<str> + Hash + Eq,
+    V: AsRef<OsStr>,
+{
+    fn var_os(&self, key: &str) -> Option<OsString> {
+        self.get(key).map(|value| value.as_ref().to_os_string())
+    }
+}
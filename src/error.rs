@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -31,4 +33,111 @@ pub enum ShellError {
 
     #[error("Home environment and ZDOTDIR variables are empty")]
     EmptyHomeAndZdotdir,
-}
\ No newline at end of file
+
+    #[error("PATH would be too long ({0} characters)")]
+    PathTooLong(usize),
+
+    #[error("{shell} does not support {operation}")]
+    UnsupportedOperation {
+        shell: &'static str,
+        operation: &'static str,
+    },
+
+    #[error("Managed block `{0}` has no end marker")]
+    UnterminatedManagedBlock(String),
+
+    #[error("`{}` can't be a PATH entry", .0.display())]
+    InvalidPathEntry(PathBuf),
+
+    #[error("`{}` is a symlink", .0.display())]
+    RcFileIsSymlink(PathBuf),
+
+    #[error(
+        "$HOME ({}) differs from the home directory in /etc/passwd ({})",
+        .env.display(),
+        .passwd.display()
+    )]
+    MultipleHomesConflict { env: PathBuf, passwd: PathBuf },
+
+    #[error("`{}` is not a valid fish_variables file", .0.display())]
+    CorruptFishVariables(PathBuf),
+
+    #[error("`{}` is UTF-16 encoded, which requires the `utf16` feature", .0.display())]
+    Utf16Unsupported(PathBuf),
+
+    #[error("Only a single line can be appended to an rcfile")]
+    MultilineNotAllowed,
+
+    #[error("`{}` has no backup {1}", .0.display())]
+    NoSuchBackup(PathBuf, usize),
+
+    #[error(
+        "No writable {shell} rcfile, tried: {}",
+        .tried.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    NoWritableRcfile {
+        shell: &'static str,
+        tried: Vec<PathBuf>,
+    },
+}
+
+impl ShellError {
+    /// Returns a suggestion for resolving the error, for CLIs to print below the error
+    /// message, or `None` if there's nothing specific to suggest.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            ShellError::NoShellVar | ShellError::DetectionFailed(_) => {
+                Some("set SHELL to the path of your shell, or pick the shell explicitly")
+            }
+            ShellError::NoHomeDir | ShellError::EmptyHomeEnvVar => {
+                Some("set HOME or pass an explicit base directory")
+            }
+            ShellError::EmptyZdotdir | ShellError::EmptyHomeAndZdotdir => {
+                Some("set HOME, or unset ZDOTDIR if it is empty")
+            }
+            ShellError::RcFileError(err) => match err.kind() {
+                std::io::ErrorKind::NotFound => {
+                    Some("create the rcfile first, or use a function that creates it")
+                }
+                std::io::ErrorKind::PermissionDenied => {
+                    Some("check the rcfile's permissions and ownership, or rerun with access to it")
+                }
+                _ => None,
+            },
+            ShellError::UnsupportedPlatform => {
+                Some("on Windows, edit the user PATH through the system settings instead")
+            }
+            ShellError::CommandFailed => Some("make sure the shell is installed and on PATH"),
+            ShellError::PathTooLong(_) => Some("remove unused or duplicate PATH entries"),
+            ShellError::UnterminatedManagedBlock(_) => {
+                Some("restore the block's end marker or remove the block by hand")
+            }
+            ShellError::InvalidPathEntry(_) => {
+                Some("use a directory whose path doesn't contain the PATH separator")
+            }
+            ShellError::RcFileIsSymlink(_) => {
+                Some("edit the file the symlink points to, or allow following symlinks")
+            }
+            ShellError::MultipleHomesConflict { .. } => {
+                Some("make HOME match the home directory in /etc/passwd")
+            }
+            ShellError::CorruptFishVariables(_) => {
+                Some("run fish once to rewrite its fish_variables file")
+            }
+            ShellError::Utf16Unsupported(_) => {
+                Some("save the file as UTF-8, or enable the `utf16` feature")
+            }
+            ShellError::MultilineNotAllowed => {
+                Some("append the lines one at a time, or as a managed block")
+            }
+            ShellError::NoSuchBackup(..) => Some("restore a more recent backup"),
+            ShellError::NoWritableRcfile { .. } => {
+                Some("check the rcfiles' permissions and ownership, or rerun with access to them")
+            }
+            ShellError::UnsupportedOperation { .. } => {
+                Some("edit the shell's rcfile instead, e.g. with `Shell::write_path_export`")
+            }
+            ShellError::InvalidUtf8Output => None,
+        }
+    }
+}
@@ -1,4 +1,14 @@
+/// Emits a `tracing` debug event when the `tracing` feature is enabled, and nothing otherwise.
+macro_rules! trace_event {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)+);
+    };
+}
+
 pub mod shell;
 pub mod error;
-
-
+pub mod path;
+pub mod report;
+pub mod dirs;
+pub mod env;
@@ -0,0 +1,190 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::ShellError;
+
+/// Returns the character separating PATH entries on this platform: `;` on Windows and `:`
+/// elsewhere.
+///
+/// This is the separator [`std::env::split_paths`] and [`std::env::join_paths`] use, which the
+/// functions in this crate go through. Shell syntax is another matter: sh-family rcfiles
+/// separate entries with `:` even on Windows (e.g. under Git Bash).
+pub const fn path_separator() -> char {
+    if cfg!(windows) {
+        ';'
+    } else {
+        ':'
+    }
+}
+
+/// Where a directory added to PATH goes relative to the existing entries.
+///
+/// Commands are looked up in PATH order, so the placement decides which of two executables
+/// with the same name runs:
+/// - `Prepend` puts the directory first, so its executables shadow any system or previously
+///   installed ones. This is what installers usually want, but also means a stray executable
+///   in the directory (e.g. a `ls`) takes over a common command.
+/// - `Append` puts the directory last, so it only provides commands that aren't found anywhere
+///   else and never changes what an existing command name runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// Search the directory before the existing entries.
+    #[default]
+    Prepend,
+    /// Search the directory after the existing entries.
+    Append,
+}
+
+/// Maximum PATH length, in UTF-16 code units, that is safe to persist on Windows.
+///
+/// The hard limit for an environment variable is 32767 units, but the user PATH registry
+/// value is silently truncated by many tools (including the Environment Variables dialog)
+/// beyond 2047 units. Windows counts these units as characters, so a character outside the
+/// Basic Multilingual Plane takes up two of them.
+pub const WINDOWS_PATH_LIMIT: usize = 2047;
+
+/// Returns whether `new_value` is too long to be used as PATH on this platform.
+///
+/// Only Windows imposes a practical limit; on other platforms this always returns `false`.
+pub fn path_would_exceed_limit(new_value: &OsStr) -> bool {
+    cfg!(windows) && exceeds_windows_path_limit(new_value)
+}
+
+/// Returns whether `value` is longer than [`WINDOWS_PATH_LIMIT`] UTF-16 code units,
+/// whatever the current platform.
+pub fn exceeds_windows_path_limit(value: &OsStr) -> bool {
+    utf16_len(value) > WINDOWS_PATH_LIMIT
+}
+
+/// Returns the length of `value` in UTF-16 code units, the unit Windows measures
+/// environment variables in.
+fn utf16_len(value: &OsStr) -> usize {
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        value.encode_wide().count()
+    }
+    #[cfg(not(windows))]
+    {
+        value.to_string_lossy().encode_utf16().count()
+    }
+}
+
+/// Returns `path_value` with its empty entries removed.
+///
+/// Shells treat an empty PATH entry (from a leading, trailing or doubled separator, such as
+/// `:/usr/bin`, `/usr/bin:` or `/usr/bin::/bin`) as the current directory. That makes any
+/// executable in whatever directory the user happens to be in runnable by name, which is an
+/// easy way to end up running a planted binary, so the PATH helpers in this module never
+/// produce such entries.
+pub fn sanitize_path(path_value: &OsStr) -> OsString {
+    let mut new_value = OsString::new();
+
+    for entry in split_entries(path_value) {
+        if !new_value.is_empty() {
+            new_value.push(path_separator().encode_utf8(&mut [0; 4]));
+        }
+        new_value.push(&entry);
+    }
+
+    new_value
+}
+
+/// Returns `path_value` with `dir` added to it, in front of or after the existing entries
+/// depending on `placement`.
+///
+/// `path_value` is sanitized first (see [`sanitize_path`]).
+///
+/// # Errors
+/// - `ShellError::InvalidPathEntry` if `dir` can't be part of PATH, e.g. because it contains the
+///   path separator
+/// - `ShellError::PathTooLong` if the resulting value would exceed the platform's PATH length
+///   limit (see [`path_would_exceed_limit`])
+pub fn add_to_path(
+    path_value: &OsStr,
+    dir: &Path,
+    placement: Placement,
+) -> Result<OsString, ShellError> {
+    let mut entries = split_entries(path_value);
+    match placement {
+        Placement::Prepend => entries.insert(0, dir.to_path_buf()),
+        Placement::Append => entries.push(dir.to_path_buf()),
+    }
+
+    let new_value = join(entries)?;
+    if path_would_exceed_limit(&new_value) {
+        let length = utf16_len(&new_value);
+        return Err(ShellError::PathTooLong(length));
+    }
+
+    Ok(new_value)
+}
+
+/// Returns a [`Command`] for `program` whose PATH is the current one with `extra_dir` added
+/// (see [`add_to_path`]).
+///
+/// `program` itself is looked up in the modified PATH, so it may live in `extra_dir`.
+///
+/// # Errors
+/// Same as [`add_to_path`].
+pub fn command_with_path(
+    program: &str,
+    extra_dir: &Path,
+    placement: Placement,
+) -> Result<Command, ShellError> {
+    let path_value = std::env::var_os("PATH").unwrap_or_default();
+    let new_value = add_to_path(&path_value, extra_dir, placement)?;
+
+    let mut command = Command::new(program);
+    command.env("PATH", new_value);
+    Ok(command)
+}
+
+/// Returns `path_value` with every occurrence of `dir` removed.
+///
+/// Empty entries are dropped as well (see [`sanitize_path`]).
+///
+/// # Errors
+/// Returns `ShellError::InvalidPathEntry` if one of the remaining entries can't be part of PATH.
+pub fn remove_from_path(path_value: &OsStr, dir: &Path) -> Result<OsString, ShellError> {
+    let mut entries = split_entries(path_value);
+    entries.retain(|entry| entry != dir);
+    join(entries)
+}
+
+/// Returns `path_value` with repeated entries removed, keeping the first occurrence of each.
+///
+/// Empty entries are dropped as well (see [`sanitize_path`]).
+///
+/// # Errors
+/// Returns `ShellError::InvalidPathEntry` if one of the entries can't be part of PATH.
+pub fn dedup_path(path_value: &OsStr) -> Result<OsString, ShellError> {
+    let mut entries: Vec<PathBuf> = Vec::new();
+    for entry in split_entries(path_value) {
+        if !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+
+    join(entries)
+}
+
+/// Returns the non-empty entries of `path_value`.
+fn split_entries(path_value: &OsStr) -> Vec<PathBuf> {
+    std::env::split_paths(path_value)
+        .filter(|entry| !entry.as_os_str().is_empty())
+        .collect()
+}
+
+/// Joins `entries` into a PATH value, reporting the first entry that can't be part of one.
+fn join(entries: Vec<PathBuf>) -> Result<OsString, ShellError> {
+    std::env::join_paths(&entries).map_err(|_| {
+        let invalid = entries
+            .iter()
+            .find(|entry| std::env::join_paths([entry]).is_err())
+            .cloned()
+            .unwrap_or_default();
+        ShellError::InvalidPathEntry(invalid)
+    })
+}
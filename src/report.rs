@@ -0,0 +1,53 @@
+use std::collections::HashSet;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::env::{Env, SystemEnv};
+use crate::shell::{exists_in_path_canonical_within, EnvDetection, Shell};
+
+/// A one-shot summary of PATH health, as returned by [`path_report`].
+#[derive(Debug)]
+pub struct PathReport {
+    /// The detected shell, or `None` if detection failed.
+    pub shell: Option<Shell>,
+    /// The detected shell's rcfiles that exist.
+    pub rcfiles: Vec<PathBuf>,
+    /// Whether the target directory is on PATH.
+    pub target_on_path: bool,
+    /// PATH entries that appear more than once, in order of their repeated occurrence.
+    pub duplicate_entries: Vec<PathBuf>,
+}
+
+/// Builds a [`PathReport`] for `target` from the current environment.
+///
+/// This bundles [`Shell::detect`], [`Shell::get_existing_rcfiles`],
+/// [`exists_in_path_canonical_within`] and the entries [`crate::path::dedup_path`] would remove.
+pub fn path_report(target: &Path) -> PathReport {
+    path_report_with_env(target, &SystemEnv)
+}
+
+/// Like [`path_report`], but detects the shell with an [`EnvDetection`] and reads PATH and the
+/// variables locating the rcfiles from `env`.
+pub fn path_report_with_env(target: &Path, env: &dyn Env) -> PathReport {
+    let shell = Shell::detect_with(&EnvDetection::new(env))
+        .ok()
+        .map(|(shell, _)| shell);
+    let rcfiles = shell
+        .as_ref()
+        .and_then(|shell| shell.get_existing_rcfiles_with_env(env).ok())
+        .unwrap_or_default();
+
+    let path_value = env.var_os("PATH").unwrap_or_default();
+    let mut seen = HashSet::new();
+    let duplicate_entries = env::split_paths(&path_value)
+        .filter(|entry| !entry.as_os_str().is_empty())
+        .filter(|entry| !seen.insert(entry.clone()))
+        .collect();
+
+    PathReport {
+        shell,
+        rcfiles,
+        target_on_path: exists_in_path_canonical_within(target, &path_value),
+        duplicate_entries,
+    }
+}
@@ -1,13 +1,17 @@
+use std::collections::HashMap;
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::path::{Path, PathBuf};
-use std::process::Command;
-
-use dirs::{config_dir, home_dir};
+use std::process::{Child, Command};
+use std::sync::Mutex;
 
+use crate::dirs::{config_dir_with_env, xdg_config_dir_with_env};
+use crate::env::{Env, SystemEnv};
 use crate::error::ShellError;
+use crate::path::{path_separator, Placement};
 
-#[derive(Debug)]
-/// Represents different types of Unix shells supported by this library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents the different types of shells supported by this library.
 ///
 /// This enum provides variants for common Unix shells (POSIX, Zsh, Bash, Fish)
 /// along with their specific configuration handling.
@@ -15,15 +19,17 @@ use crate::error::ShellError;
 /// # Examples
 ///
 /// ```
-/// use what_the_path::Shell;
+/// use what_the_path::shell::Shell;
 ///
 /// // Detect current shell
-/// if let Some(shell) = Shell::detect() {
+/// if let Ok(shell) = Shell::detect_by_shell_var() {
 ///     match shell {
 ///         Shell::Zsh(_) => println!("Using Zsh"),
 ///         Shell::Bash(_) => println!("Using Bash"),
 ///         Shell::Fish(_) => println!("Using Fish"),
 ///         Shell::POSIX(_) => println!("Using POSIX shell"),
+///         Shell::PowerShell(_) => println!("Using PowerShell"),
+///         Shell::WindowsPowerShell(_) => println!("Using Windows PowerShell"),
 ///     }
 /// }
 /// ```
@@ -34,219 +40,4687 @@ use crate::error::ShellError;
 /// * `Zsh` - Z shell
 /// * `Bash` - Bourne Again Shell
 /// * `Fish` - Friendly Interactive Shell
+/// * `PowerShell` - PowerShell 7 and later (`pwsh`), on any platform
+/// * `WindowsPowerShell` - Windows PowerShell 5.1 (`powershell.exe`), built into Windows
 ///
 pub enum Shell {
     POSIX(POSIX),
     Zsh(Zsh),
     Bash(Bash),
     Fish(Fish),
+    PowerShell(PowerShell),
+    WindowsPowerShell(WindowsPowerShell),
 }
 
 impl Shell {
     /// Detects the current shell by examining the `SHELL` environment variable.
     ///
     /// This function attempts to identify the shell type based on the `SHELL` environment variable.
-    /// It will return `None` on Windows systems as the `SHELL` variable is not typically used.
+    /// Windows doesn't set `SHELL` (only Git Bash, MSYS2 and Cygwin do), so there PowerShell is
+    /// detected instead when it is unset, see [`Shell::detect_verbose`].
     ///
     /// # Returns
-    /// - `Some(Shell)` containing the detected shell type if:
-    ///   - Running on a non-Windows system
-    ///   - The `SHELL` environment variable exists and contains a recognized shell name
-    /// - `None` if:
-    ///   - Running on Windows
-    ///   - The `SHELL` environment variable does not exist
+    /// - `Ok(Shell)` containing the detected shell type if the `SHELL` environment variable
+    ///   exists and contains a recognized shell name
+    /// - `Err(ShellError::NoShellVar)` if the `SHELL` environment variable does not exist
+    /// - `Err(ShellError::UnsupportedPlatform)` on Windows if `SHELL` is unset and no
+    ///   PowerShell could be found either
     ///
     /// # Shell Detection
+    /// Only the file name of the shell path is inspected, so `/usr/local/bin/bash` and
+    /// `/usr/bin/bash5` are Bash while `/opt/bin/bash-completion-helper` is not.
     /// The following shells are recognized (in order):
     /// - Zsh
     /// - Bash
     /// - Fish
+    /// - Known shells that aren't supported, such as Nushell (`nu`), xonsh or tcsh, make
+    ///   detection fail with `ShellError::DetectionFailed` naming the shell, rather than
+    ///   editing their rcfiles with POSIX syntax
     /// - Any other shell is assumed to be POSIX-compliant
     pub fn detect_by_shell_var() -> Result<Shell, ShellError> {
+        Shell::detect_by_shell_var_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::detect_by_shell_var`], but reads `$SHELL` from `env`.
+    pub fn detect_by_shell_var_with_env(env: &dyn Env) -> Result<Shell, ShellError> {
+        if cfg!(windows) && env.var_os("SHELL").is_none() {
+            return detect_windows_powershell(&EnvDetection::new(env))
+                .map(|(shell, _)| shell)
+                .ok_or(ShellError::UnsupportedPlatform);
+        }
+
+        Ok(Shell::detect_by_shell_var_raw_with_env(env)?.0)
+    }
+
+    /// Like [`Shell::detect_by_shell_var`], but also returns the unmodified value of `$SHELL`
+    /// the shell was detected from, e.g. for logging.
+    ///
+    /// This requires `$SHELL` to be set, so unlike [`Shell::detect_by_shell_var`] it doesn't
+    /// fall back to detecting PowerShell on Windows.
+    pub fn detect_by_shell_var_raw() -> Result<(Shell, String), ShellError> {
+        Shell::detect_by_shell_var_raw_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::detect_by_shell_var_raw`], but reads `$SHELL` from `env`.
+    pub fn detect_by_shell_var_raw_with_env(env: &dyn Env) -> Result<(Shell, String), ShellError> {
+        let shell = env.var("SHELL").ok_or(ShellError::NoShellVar)?;
+        let detected = classify_shell_name(&shell)?;
+        trace_event!(shell, ?detected, "detected shell from $SHELL");
+
+        Ok((detected, shell))
+    }
+
+    /// Like [`Shell::detect_by_shell_var`], but only trusts `$SHELL` if it is an absolute path
+    /// to an existing executable.
+    ///
+    /// This keeps a relative `$SHELL` (resolved against whatever the current directory is)
+    /// or one naming a file that doesn't exist from steering which rcfiles get edited.
+    ///
+    /// # Errors
+    /// Returns `ShellError::DetectionFailed` if `$SHELL` doesn't meet these requirements, and
+    /// otherwise fails like [`Shell::detect_by_shell_var`].
+    pub fn detect_by_shell_var_strict() -> Result<Shell, ShellError> {
+        Shell::detect_by_shell_var_strict_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::detect_by_shell_var_strict`], but reads `$SHELL` from `env`.
+    pub fn detect_by_shell_var_strict_with_env(env: &dyn Env) -> Result<Shell, ShellError> {
         if cfg!(windows) {
             return Err(ShellError::UnsupportedPlatform);
         }
 
-        let shell = env::var("SHELL").map_err(|_| ShellError::NoShellVar)?;
+        let shell = env.var("SHELL").ok_or(ShellError::NoShellVar)?;
+        if !Path::new(&shell).is_absolute() {
+            return Err(ShellError::DetectionFailed(format!(
+                "$SHELL is not an absolute path: {shell}"
+            )));
+        }
+        if !is_executable(Path::new(&shell)) {
+            return Err(ShellError::DetectionFailed(format!(
+                "$SHELL is not an executable file: {shell}"
+            )));
+        }
+
+        let detected = classify_shell_name(&shell)?;
+        trace_event!(shell, ?detected, "detected shell from trusted $SHELL");
+
+        Ok(detected)
+    }
+
+    /// Like [`Shell::detect_by_shell_var`], but falls back to [`Shell::posix_default`] instead
+    /// of failing.
+    pub fn detect_by_shell_var_or_default() -> Shell {
+        Shell::detect_by_shell_var_or_default_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::detect_by_shell_var_or_default`], but reads `$SHELL` from `env`.
+    pub fn detect_by_shell_var_or_default_with_env(env: &dyn Env) -> Shell {
+        Shell::detect_by_shell_var_with_env(env).unwrap_or_else(|_| Shell::posix_default())
+    }
+
+    /// Returns whether `self` and `other` are the same shell family, e.g. a POSIX shell
+    /// detected from `sh` and one detected from `dash`.
+    ///
+    /// The per-shell structs carry no data, so this currently agrees with `==`. Unlike `==`, it
+    /// will keep comparing only the family should a variant gain details such as a version.
+    pub fn eq_semantic(&self, other: &Shell) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    /// Returns the shell to assume when nothing better is known: POSIX, since every
+    /// Unix-like system has a POSIX-compliant `sh`.
+    pub fn posix_default() -> Shell {
+        Shell::POSIX(POSIX)
+    }
+
+    /// Detects the shell from the `SHELL` entry of `env` instead of the process environment.
+    ///
+    /// This applies the same logic as [`Shell::detect_by_shell_var`], which is useful when
+    /// working with another process's captured environment and for tests. See
+    /// [`Shell::detect_by_shell_var_with_env`] for other kinds of [`Env`].
+    pub fn detect_from_env_map(env: &HashMap<String, String>) -> Result<Shell, ShellError> {
+        Shell::detect_by_shell_var_with_env(env)
+    }
 
-        match shell.as_str() {
-            path if path.contains("zsh") => Ok(Shell::Zsh(Zsh)),
-            path if path.contains("bash") => Ok(Shell::Bash(Bash)),
-            path if path.contains("fish") => Ok(Shell::Fish(Fish)),
-            _ => Ok(Shell::POSIX(POSIX)),
+    /// Classifies `shell_path`, e.g. a value read from a config file, the way
+    /// [`Shell::detect_by_shell_var`] classifies `$SHELL`.
+    ///
+    /// Only the final path component is inspected, so `/usr/bin/fish` and `fish` are both Fish,
+    /// and any unrecognized shell is assumed to be POSIX-compliant. Unlike the `FromStr`
+    /// implementation, this accepts paths and versioned names.
+    ///
+    /// # Errors
+    /// Returns `ShellError::DetectionFailed` if `shell_path` is empty or names a known but
+    /// unsupported shell.
+    pub fn detect_from_str(shell_path: &str) -> Result<Shell, ShellError> {
+        let shell_path = shell_path.trim();
+        if shell_path.is_empty() {
+            return Err(ShellError::DetectionFailed("empty shell path".to_string()));
         }
+
+        let detected = classify_shell_name(shell_path)?;
+        trace_event!(shell_path, ?detected, "classified shell path");
+
+        Ok(detected)
     }
 
-    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
-        match self {
-            Shell::Fish(fish) => fish.get_rcfiles(),
-            Shell::Zsh(zsh) => zsh.get_rcfiles(),
-            Shell::Bash(bash) => bash.get_rcfiles(),
-            Shell::POSIX(posix) => posix.get_rcfiles(),
+    /// Parses a shell name typed by a user, such as a CLI's `--shell` argument.
+    ///
+    /// Besides the binary names the `FromStr` implementation accepts, this ignores case,
+    /// surrounding whitespace, a `.exe` suffix and any `-`, `_` or space, and accepts these
+    /// aliases:
+    /// - POSIX: `posix`, `dash`, `ash`
+    /// - Bash: `bourneagain`, `bashshell`
+    /// - Zsh: `zshell`, `zshshell`
+    /// - Fish: `fishshell`
+    /// - PowerShell: `powershellcore`, `pwshcore`, `ps7`
+    /// - Windows PowerShell: `windowspowershell`, `ps`
+    ///
+    /// Note that `powershell` is Windows PowerShell, like its binary. Returns `None` for any
+    /// other name.
+    pub fn from_name_lenient(name: &str) -> Option<Shell> {
+        let name = name.trim().to_ascii_lowercase();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        let name: String = name
+            .chars()
+            .filter(|c| !matches!(c, '-' | '_' | ' '))
+            .collect();
+
+        if let Ok(shell) = name.parse() {
+            return Some(shell);
         }
+        SHELL_NAME_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map(|(_, shell)| shell.clone())
     }
-}
 
-#[derive(Debug)]
-pub struct POSIX;
+    /// Detects the current shell.
+    ///
+    /// This is the recommended entry point for detection. See [`Shell::detect_verbose`] for
+    /// the sources consulted.
+    pub fn detect() -> Result<Shell, ShellError> {
+        Ok(Shell::detect_verbose()?.0)
+    }
 
-impl POSIX {
-    pub fn does_exist(&self) -> bool {
-        true
+    /// Like [`Shell::detect`], but only detects once per process and then returns the same
+    /// result until [`invalidate_detection_cache`] is called.
+    ///
+    /// Only the running process's shell, as found by [`SystemDetection`], is cached. Detection
+    /// from another [`DetectionProvider`], such as an [`EnvDetection`], describes some other
+    /// environment, so [`Shell::detect_with`] never caches it. Failures are not cached either.
+    pub fn detect_cached() -> Result<Shell, ShellError> {
+        let mut cache = DETECTION_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        let pid = std::process::id();
+        if let Some((cached_pid, shell)) = cache.as_ref() {
+            if *cached_pid == pid {
+                trace_event!(?shell, "using cached shell");
+                return Ok(shell.clone());
+            }
+        }
+
+        let (shell, _) = Shell::detect_with(&SystemDetection)?;
+        *cache = Some((pid, shell.clone()));
+        Ok(shell)
     }
-    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
-        let dir = home_dir().ok_or(ShellError::NoHomeDir)?;
-        Ok(vec![dir.join(".profile")])
+
+    /// Detects the current shell and reports where it was found.
+    ///
+    /// Setting the `WHAT_THE_PATH_SHELL` environment variable (see [`SHELL_OVERRIDE_VAR`]) to
+    /// a shell name [`Shell::from_name_lenient`] recognizes, e.g. `fish`, skips detection
+    /// altogether and returns that shell with [`DetectionSource::Override`]. This lets users
+    /// and tests force a shell without changing `$SHELL`; other values are ignored.
+    ///
+    /// On Windows a `SHELL` variable (as set by Git Bash, MSYS2 and Cygwin) is classified as
+    /// on any other platform. Without one, the session is assumed to be PowerShell's: the
+    /// edition is picked from `PSModulePath` (see [`powershell_for_module_path`]) and
+    /// otherwise from the installed `pwsh` or `powershell` binaries, in that order.
+    ///
+    /// Elsewhere the sources are consulted in order:
+    /// 1. The parent process, if it is a known shell
+    /// 2. The `SHELL` environment variable. When its file name isn't a known shell's but
+    ///    starts with an installed one's followed by a non-alphanumeric character (e.g.
+    ///    `/usr/bin/zsh-static`), that shell is picked, with the same priority as in step 4.
+    ///    Otherwise an unknown `$SHELL` is assumed POSIX.
+    /// 3. The current user's login shell in `/etc/passwd`
+    /// 4. The installed shells, preferring Bash, then Zsh, then Fish
+    /// 5. `/bin/bash` or `/bin/sh`, whichever exists first, as found in minimal containers
+    ///    that have none of the above
+    /// 6. Otherwise POSIX is assumed; [`most_likely_shell`] can make a better guess from the
+    ///    installed shells
+    ///
+    /// # Errors
+    /// Returns `ShellError::DetectionFailed` if the first source that names a shell names a
+    /// known but unsupported one (see [`Shell::detect_by_shell_var`]), and
+    /// `ShellError::UnsupportedPlatform` on Windows if neither `SHELL` nor PowerShell is found.
+    pub fn detect_verbose() -> Result<(Shell, DetectionSource), ShellError> {
+        Shell::detect_with(&SystemDetection)
     }
-    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
-        vec![base_dir.as_ref().join(".profile")]
+
+    /// Like [`Shell::detect`], but returns [`Shell::default_for_os`] instead of assuming POSIX
+    /// when nothing could be detected, or failing on an unsupported platform.
+    pub fn detect_with_fallback() -> Shell {
+        match Shell::detect_verbose() {
+            Ok((shell, source)) if source != DetectionSource::Fallback => shell,
+            _ => Shell::default_for_os(),
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct Zsh;
+    /// Returns the shell new user accounts get by default on the current OS.
+    ///
+    /// - macOS: Zsh, the default since macOS Catalina
+    /// - Other Unix-like systems: Bash, which most Linux distributions default to
+    /// - Windows: Windows PowerShell, which ships with every Windows version
+    /// - Anything else: POSIX
+    pub fn default_for_os() -> Shell {
+        if cfg!(target_os = "macos") {
+            Shell::Zsh(Zsh)
+        } else if cfg!(unix) {
+            Shell::Bash(Bash)
+        } else if cfg!(windows) {
+            Shell::WindowsPowerShell(WindowsPowerShell)
+        } else {
+            Shell::posix_default()
+        }
+    }
 
-impl Zsh {
-    pub fn does_exist(&self) -> bool {
-        matches!(env::var("SHELL"), Ok(v) if v.contains("zsh"))
-            || Command::new("zsh").output().is_ok()
+    /// Like [`Shell::detect_verbose`], but with the information supplied by `provider`.
+    ///
+    /// The result only depends on what `provider` reports. The parent process comes first,
+    /// so a provider describing an environment other than the running process's should
+    /// report none, as [`EnvDetection`] does, or the live parent process would win over its
+    /// `$SHELL`.
+    pub fn detect_with(
+        provider: &dyn DetectionProvider,
+    ) -> Result<(Shell, DetectionSource), ShellError> {
+        let (shell, source) = Shell::detect_from_sources(provider)?;
+        trace_event!(?shell, ?source, "detected shell");
+
+        Ok((shell, source))
     }
 
-    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
-        let mut rc_files = Vec::new();
+    /// Consults the sources of [`Shell::detect_with`] in order.
+    fn detect_from_sources(
+        provider: &dyn DetectionProvider,
+    ) -> Result<(Shell, DetectionSource), ShellError> {
+        if let Some(name) = provider.shell_override() {
+            if let Some(shell) = Shell::from_name_lenient(&name) {
+                trace_event!(name, ?shell, "shell forced by override");
+                return Ok((shell, DetectionSource::Override));
+            }
+        }
 
-        // Try ZDOTDIR
-        if let Ok(output) = std::process::Command::new("zsh")
-            .args(["-c", "echo -n $ZDOTDIR"])
-            .output()
-        {
-            if !output.stdout.is_empty() {
-                if let Ok(zdotdir) = String::from_utf8(output.stdout) {
-                    let path = PathBuf::from(zdotdir.trim()).join(".zshenv");
-                    if path.exists() {
-                        rc_files.push(path);
-                    }
+        if cfg!(windows) {
+            if let Some(shell) = provider.shell_var() {
+                return Ok((classify_shell_name(&shell)?, DetectionSource::ShellVar));
+            }
+            return detect_windows_powershell(provider).ok_or(ShellError::UnsupportedPlatform);
+        }
+
+        if let Some(parent) = provider.parent_process() {
+            if let Some(shell) = recognize_shell_name(&parent) {
+                return Ok((shell, DetectionSource::ParentProcess));
+            }
+            if let Some(name) = unsupported_shell_name(&parent) {
+                return Err(unsupported_shell(name, &parent));
+            }
+        }
+        if let Some(shell) = provider.shell_var() {
+            if recognize_shell_name(&shell).is_none() && unsupported_shell_name(&shell).is_none() {
+                let stem = shell_stem(&shell);
+                let installed = provider.installed_shells();
+                let mentions = |name: &str| {
+                    stem.strip_prefix(name)
+                        .is_some_and(|rest| !rest.starts_with(char::is_alphanumeric))
+                };
+                if let Some(mentioned) = preferred_installed(&installed, mentions) {
+                    return Ok((mentioned, DetectionSource::InstalledShell));
                 }
             }
+            return Ok((classify_shell_name(&shell)?, DetectionSource::ShellVar));
+        }
+        if let Some(shell) = provider.login_shell() {
+            return Ok((classify_shell_name(&shell)?, DetectionSource::LoginShell));
+        }
+        if let Some(shell) = preferred_installed(&provider.installed_shells(), |_| true) {
+            return Ok((shell, DetectionSource::InstalledShell));
+        }
+        if provider.file_exists(Path::new("/bin/bash")) {
+            return Ok((Shell::Bash(Bash), DetectionSource::SystemShell));
+        }
+        if provider.file_exists(Path::new("/bin/sh")) {
+            return Ok((Shell::posix_default(), DetectionSource::SystemShell));
         }
 
-        // Try HOME
-        if let Ok(home) = std::env::var("HOME") {
-            let path = PathBuf::from(home).join(".zshenv");
-            if path.exists() {
-                rc_files.push(path);
+        Ok((Shell::posix_default(), DetectionSource::Fallback))
+    }
+
+    /// Returns the single rcfile that should be edited to persist a PATH change for
+    /// interactive sessions of this shell.
+    ///
+    /// - POSIX: `~/.profile`
+    /// - Bash: `~/.bashrc`, which interactive non-login shells (most terminal emulators) read
+    /// - Zsh: `.zshenv` in `$ZDOTDIR` (or `$HOME`), which every zsh instance reads. A
+    ///   `ZDOTDIR` only assigned in `~/.zshenv` doesn't count, since zsh has already read
+    ///   that file by then.
+    /// - Fish: `<config>/fish/config.fish`
+    /// - PowerShell and Windows PowerShell: `Microsoft.PowerShell_profile.ps1` (`$PROFILE`) in
+    ///   [`Shell::config_home`]
+    ///
+    /// The file is not required to exist yet.
+    pub fn rc_file_for_path_edit(&self) -> Result<PathBuf, ShellError> {
+        self.rc_file_for_path_edit_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::rc_file_for_path_edit`], but reads the environment variables from `env`.
+    pub fn rc_file_for_path_edit_with_env(&self, env: &dyn Env) -> Result<PathBuf, ShellError> {
+        let config_home = match self {
+            Shell::Zsh(_) => Zsh::startup_dir(env)?,
+            _ => self.config_home_with_env(env)?,
+        };
+        let rcfile = match self {
+            Shell::POSIX(_) => ".profile",
+            Shell::Bash(_) => ".bashrc",
+            Shell::Zsh(_) => ".zshenv",
+            Shell::Fish(_) => "config.fish",
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => POWERSHELL_PROFILE,
+        };
+        Ok(config_home.join(rcfile))
+    }
+
+    /// Returns the machine-local include of this shell's interactive rcfile, if that rcfile
+    /// sources it.
+    ///
+    /// Many dotfile frameworks end e.g. `.zshrc` with `[ -f ~/.zshrc.local ] && source
+    /// ~/.zshrc.local`, leaving the `.local` file out of the managed dotfiles for
+    /// machine-specific configuration. That makes it the best place for a tool's PATH edits,
+    /// which then don't conflict with the user's synced rcfiles.
+    ///
+    /// - POSIX: `~/.profile.local`, sourced from `~/.profile`
+    /// - Bash: `~/.bashrc.local`, sourced from `~/.bashrc`
+    /// - Zsh: `.zshrc.local`, sourced from `.zshrc`, both in [`Shell::config_home`]
+    /// - Fish: `None`, machine-specific configuration goes in `conf.d` instead
+    /// - PowerShell and Windows PowerShell: `None`, there is no such convention
+    ///
+    /// The include is found by scanning the rcfile for a `source` or `.` of it, with the path
+    /// written literally or starting with `~` or `$HOME`. The local file may not exist yet.
+    pub fn local_rcfile(&self) -> Option<PathBuf> {
+        self.local_rcfile_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::local_rcfile`], but reads the environment variables from `env`.
+    pub fn local_rcfile_with_env(&self, env: &dyn Env) -> Option<PathBuf> {
+        let rcfile = match self {
+            Shell::POSIX(_) => ".profile",
+            Shell::Bash(_) => ".bashrc",
+            Shell::Zsh(_) => ".zshrc",
+            Shell::Fish(_) | Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => return None,
+        };
+        let config_home = self.config_home_with_env(env).ok()?;
+        let home = get_home_dir_with_env(env).ok()?;
+        let (_, content) = read_rcfile(&config_home.join(rcfile)).ok()?;
+
+        let local = config_home.join(format!("{rcfile}.local"));
+        sourced_files(&content, &home)
+            .contains(&local)
+            .then_some(local)
+    }
+
+    /// Returns the rcfile to edit instead of a dotfile in `$HOME` when the user keeps this
+    /// shell's configuration under the XDG config directory (`$XDG_CONFIG_HOME`, or
+    /// `~/.config`), or `None` if they don't.
+    ///
+    /// - POSIX and Bash: the first file under the XDG config directory that `~/.profile`
+    ///   (respectively `~/.bashrc`) sources, e.g. `~/.config/bash/bashrc`
+    /// - Zsh: `.zshenv` in `$ZDOTDIR` if the environment points it under the XDG config
+    ///   directory, or `.zshrc` there if `~/.zshenv` does (zsh has already read `~/.zshenv`
+    ///   itself by then)
+    /// - Fish: `config.fish` (see [`Fish::config_fish_path`]), fish keeping its configuration
+    ///   there already
+    /// - PowerShell: `$PROFILE`, which lives in the XDG config directory except on Windows
+    /// - Windows PowerShell: `None`
+    pub fn xdg_rcfile(&self) -> Option<PathBuf> {
+        self.xdg_rcfile_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::xdg_rcfile`], but reads the environment variables from `env`.
+    pub fn xdg_rcfile_with_env(&self, env: &dyn Env) -> Option<PathBuf> {
+        let xdg_config = xdg_config_dir_with_env(env)?;
+        match self {
+            Shell::POSIX(_) => xdg_sourced_file(".profile", &xdg_config, env),
+            Shell::Bash(_) => xdg_sourced_file(".bashrc", &xdg_config, env),
+            Shell::Zsh(zsh) => {
+                let startup_dir = Zsh::startup_dir(env).ok()?;
+                if startup_dir.starts_with(&xdg_config) {
+                    return Some(startup_dir.join(".zshenv"));
+                }
+                zsh.zdotdir_offline_with_env(env)
+                    .filter(|zdotdir| zdotdir.starts_with(&xdg_config))
+                    .map(|zdotdir| zdotdir.join(".zshrc"))
             }
+            Shell::Fish(fish) => fish.config_fish_path_with_env(env).ok(),
+            Shell::PowerShell(_) if !cfg!(windows) => self.rc_file_for_path_edit_with_env(env).ok(),
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => None,
         }
+    }
 
-        if rc_files.is_empty() {
-            Err(ShellError::EmptyHomeAndZdotdir)
-        } else {
-            Ok(rc_files)
+    /// Returns whether a persistent PATH change for this shell must (also) go in a file only
+    /// read by login shells to reach every session.
+    ///
+    /// - POSIX: `true`, `sh` only reads `~/.profile`, and only as a login shell
+    /// - Bash: `true`, interactive shells read `~/.bashrc` but login shells (ssh sessions,
+    ///   text consoles, macOS terminals) only read `~/.bash_profile` or `~/.profile`, which
+    ///   don't source `~/.bashrc` on every system. Display managers typically only read
+    ///   `~/.profile` too, so graphical applications wouldn't see the change otherwise.
+    /// - Zsh: `false`, every zsh instance reads `.zshenv`, login or not
+    /// - Fish: `false`, `config.fish` is read by every fish instance
+    /// - PowerShell and Windows PowerShell: `false`, they have no login mode and read their
+    ///   profiles in every session
+    ///
+    /// Where this is `true`, [`Shell::write_path_export`] writes the login file along with
+    /// [`Shell::rc_file_for_path_edit`].
+    pub fn path_needs_login_file(&self) -> bool {
+        match self {
+            Shell::POSIX(_) | Shell::Bash(_) => true,
+            Shell::Zsh(_) | Shell::Fish(_) | Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => {
+                false
+            }
         }
     }
-    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
-        vec![base_dir.as_ref().join(".zshenv")]
+
+    /// Returns the extension, without the dot, of the files this shell sources from its
+    /// drop-in directory, or `None` if it has none.
+    ///
+    /// - POSIX and Bash: `sh`, for `/etc/profile.d`, which login shells source through
+    ///   `/etc/profile`
+    /// - Fish: `fish`, for `conf.d` (see [`Fish::write_dropin`])
+    /// - Zsh, PowerShell and Windows PowerShell: `None`. Zsh doesn't read `/etc/profile`; only
+    ///   some distributions make it do so from `/etc/zprofile`.
+    pub fn dropin_extension(&self) -> Option<&'static str> {
+        match self {
+            Shell::POSIX(_) | Shell::Bash(_) => Some("sh"),
+            Shell::Fish(_) => Some("fish"),
+            Shell::Zsh(_) | Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => None,
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct Bash;
+    /// Returns the name of a drop-in file called `stem`, e.g. `mytool.fish` for Fish, with
+    /// the extension the shell requires for it to be sourced (see
+    /// [`Shell::dropin_extension`]). Returns `None` for shells without a drop-in directory.
+    pub fn dropin_filename(&self, stem: &str) -> Option<String> {
+        Some(format!("{stem}.{}", self.dropin_extension()?))
+    }
 
-impl Bash {
-    pub fn does_exist(&self) -> bool {
-        matches!(env::var("SHELL"), Ok(v) if v.contains("bash"))
-            || Command::new("bash").output().is_ok()
+    /// Persists `dir` on PATH in one call: adds the line from [`Shell::path_add_line`] to the
+    /// rcfile from [`Shell::rc_file_for_path_edit`], and returns that rcfile.
+    ///
+    /// When the shell also needs a login file for the change to reach every session (see
+    /// [`Shell::path_needs_login_file`]), the line is added to the first file of
+    /// [`Shell::init_order`] as well, unless that already is the rcfile or, for Bash, sources
+    /// it (see [`Bash::profile_chain`]). For Bash that is `~/.bash_profile`, `~/.bash_login`
+    /// or `~/.profile` next to `~/.bashrc`. When Bash has none of these, a `~/.bash_profile`
+    /// is created that sources `~/.profile` and `~/.bashrc`, if they exist, rather than
+    /// holding the line itself.
+    ///
+    /// The files and their directories are created if needed (see [`append_to_rcfile_create`]),
+    /// and nothing is written to a file that already has the line, so running an installer
+    /// again doesn't duplicate it.
+    ///
+    /// # Errors
+    /// Returns `ShellError::NoWritableRcfile` if a file to write is read-only, or is missing
+    /// and can't be created.
+    pub fn write_path_export(
+        &self,
+        dir: &Path,
+        placement: Placement,
+    ) -> Result<PathBuf, ShellError> {
+        self.write_path_export_with_env(dir, placement, &SystemEnv)
     }
 
-    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
-        let dir = home_dir().ok_or(ShellError::NoHomeDir)?;
-        let rcfiles = [".bash_profile", ".bash_login", ".bashrc"]
-            .iter()
-            .map(|rc| dir.join(rc))
+    /// Like [`Shell::write_path_export`], but picks the rcfile with
+    /// [`Shell::rc_file_for_path_edit_with_env`].
+    pub fn write_path_export_with_env(
+        &self,
+        dir: &Path,
+        placement: Placement,
+        env: &dyn Env,
+    ) -> Result<PathBuf, ShellError> {
+        let rcfile = self.rc_file_for_path_edit_with_env(env)?;
+        let line = self.path_add_line(dir, placement);
+
+        let mut targets = vec![rcfile.clone()];
+        let mut new_bash_profile = None;
+        if self.path_needs_login_file() {
+            let login = self.init_order_with_env(env)?.into_iter().next();
+            // Whether a login shell already reads the rcfile, even if it doesn't exist yet
+            let reaches_rcfile = |login: &PathBuf| {
+                let (Shell::Bash(bash), Ok(home)) = (self, get_home_dir_with_env(env)) else {
+                    return false;
+                };
+                let chain = bash.profile_chain_with_env(env).unwrap_or_default();
+                chain.contains(login)
+                    && chain.iter().any(|file| {
+                        std::fs::read_to_string(file)
+                            .is_ok_and(|content| sourced_files(&content, &home).contains(&rcfile))
+                    })
+            };
+            match login.filter(|login| *login != rcfile) {
+                // A new ~/.bash_profile would hide ~/.profile from bash, so it sources that
+                // and ~/.bashrc instead of getting the line itself
+                Some(login) if matches!(self, Shell::Bash(_)) && !login.exists() => {
+                    new_bash_profile = Some(login);
+                }
+                Some(login) if !reaches_rcfile(&login) => targets.push(login),
+                _ => {}
+            }
+        }
+
+        let mut all_targets = targets.iter().chain(&new_bash_profile);
+        if let Some(target) = all_targets.find(|target| !self.rcfile_writable(target)) {
+            return Err(self.no_writable_rcfile(vec![target.clone()]));
+        }
+        if let Some(bash_profile) = &new_bash_profile {
+            if let Some(parent) = bash_profile.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let content = format!("{}{BASH_PROFILE_CHAIN}", self.rcfile_template());
+            write_atomic(bash_profile, content.as_bytes())?;
+        }
+        for target in &targets {
+            append_to_rcfile_create_if_missing(target, &line)?;
+        }
+
+        Ok(rcfile)
+    }
+
+    /// Appends `line` to every existing rcfile of this shell (see
+    /// [`Shell::get_existing_rcfiles`]) that doesn't contain it yet, e.g. to reach every
+    /// session regardless of which file it reads.
+    ///
+    /// Read-only rcfiles are skipped. Returns the rcfiles that were written to or already
+    /// had the line, with what happened to each.
+    ///
+    /// # Errors
+    /// Returns `ShellError::NoWritableRcfile` listing the existing rcfiles if none of them
+    /// could be written, including when there are none at all.
+    pub fn append_to_all_existing(
+        &self,
+        line: &str,
+    ) -> Result<Vec<(PathBuf, AppendOutcome)>, ShellError> {
+        self.append_to_all_existing_with_env(line, &SystemEnv)
+    }
+
+    /// Like [`Shell::append_to_all_existing`], but finds the rcfiles with
+    /// [`Shell::get_existing_rcfiles_with_env`].
+    pub fn append_to_all_existing_with_env(
+        &self,
+        line: &str,
+        env: &dyn Env,
+    ) -> Result<Vec<(PathBuf, AppendOutcome)>, ShellError> {
+        let rcfiles: Vec<PathBuf> = self
+            .get_existing_rcfiles_with_env(env)?
+            .into_iter()
+            .filter(|rcfile| rcfile.is_file())
             .collect();
-        Ok(rcfiles)
+
+        let mut outcomes = Vec::new();
+        for rcfile in &rcfiles {
+            if !self.rcfile_writable(rcfile) {
+                continue;
+            }
+            match append_to_rcfile_if_missing(rcfile, line) {
+                Ok(outcome) => outcomes.push((rcfile.clone(), outcome)),
+                Err(ShellError::RcFileError(err))
+                    if err.kind() == std::io::ErrorKind::PermissionDenied => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        if outcomes.is_empty() {
+            return Err(self.no_writable_rcfile(rcfiles));
+        }
+        Ok(outcomes)
     }
 
-    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
-        [".bash_profile", ".bash_login", ".bashrc"]
-            .iter()
-            .map(|rc| base_dir.as_ref().join(rc))
-            .collect()
+    fn no_writable_rcfile(&self, tried: Vec<PathBuf>) -> ShellError {
+        ShellError::NoWritableRcfile {
+            shell: self.binary_name(),
+            tried,
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct Fish;
+    /// Returns the directory this shell's rcfiles live in.
+    ///
+    /// - POSIX and Bash: `$HOME`
+    /// - Zsh: `$ZDOTDIR` if known (see [`Zsh::zdotdir_offline`]), otherwise `$HOME`
+    /// - Fish: `fish` in [`crate::dirs::xdg_config_dir`], usually `~/.config/fish`, on macOS too
+    /// - PowerShell: see [`PowerShell::profile_dir`]
+    /// - Windows PowerShell: see [`WindowsPowerShell::profile_dir`]
+    pub fn config_home(&self) -> Result<PathBuf, ShellError> {
+        self.config_home_with_env(&SystemEnv)
+    }
 
-impl Fish {
-    pub fn does_exist(&self) -> bool {
-        matches!(env::var("SHELL"), Ok(v) if v.contains("fish"))
-            || Command::new("fish").output().is_ok()
+    /// Like [`Shell::config_home`], but reads the environment variables from `env`.
+    pub fn config_home_with_env(&self, env: &dyn Env) -> Result<PathBuf, ShellError> {
+        match self {
+            Shell::POSIX(_) | Shell::Bash(_) => get_home_dir_with_env(env),
+            Shell::Zsh(zsh) => match zsh.zdotdir_offline_with_env(env) {
+                Some(zdotdir) => Ok(zdotdir),
+                None => get_home_dir_with_env(env),
+            },
+            Shell::Fish(_) => Ok(xdg_config_dir_with_env(env)
+                .ok_or(ShellError::NoHomeDir)?
+                .join("fish")),
+            Shell::PowerShell(pwsh) => pwsh.profile_dir_with_env(env),
+            Shell::WindowsPowerShell(powershell) => powershell.profile_dir_with_env(env),
+        }
     }
 
-    /// Returns the configuration directory path for Fish shell
+    /// Returns the directory [`backup_rcfile`] keeps rcfile backups in,
+    /// `what-the-path/backups` in the user's configuration directory (see
+    /// [`crate::dirs::config_dir`]).
     ///
-    /// This function attempts to locate the Fish shell's configuration directory
-    /// by joining the user's home directory with the Fish config path.
+    /// The directory is shared by all shells and is not required to exist yet.
+    pub fn rcfile_backup_dir() -> Result<PathBuf, ShellError> {
+        Shell::rcfile_backup_dir_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::rcfile_backup_dir`], but reads the environment variables from `env`.
+    pub fn rcfile_backup_dir_with_env(env: &dyn Env) -> Result<PathBuf, ShellError> {
+        Ok(config_dir_with_env(env)
+            .ok_or(ShellError::NoHomeDir)?
+            .join("what-the-path")
+            .join("backups"))
+    }
+
+    /// Detects the current shell without spawning any process or touching the filesystem.
     ///
-    /// # Returns
-    /// - `Some(Vec<PathBuf>)` containing the path to Fish's conf.d directory
-    /// - `None` if the home directory cannot be determined
+    /// Detection only relies on environment variables, which makes it safe to use in
+    /// sandboxes that forbid spawning subprocesses. In order, it honors
+    /// [`SHELL_OVERRIDE_VAR`], classifies `$SHELL` like [`Shell::detect_by_shell_var`], and
+    /// on Windows picks the PowerShell edition from `PSModulePath` (see
+    /// [`powershell_for_module_path`]). Unlike [`Shell::detect`], it never looks for
+    /// installed shells on PATH, the parent process or `/etc/passwd`.
     ///
-    /// # Important
-    /// Note that this function returns a directory path (`conf.d`), not individual
-    /// file paths. You'll need to enumerate the directory contents to access
-    /// specific configuration files.
+    /// # Errors
+    /// Returns `ShellError::DetectionFailed` if `$SHELL` names a known but unsupported
+    /// shell, and otherwise `ShellError::UnsupportedPlatform` on Windows and
+    /// `ShellError::NoShellVar` elsewhere if none of these variables identify a shell.
+    pub fn detect_offline() -> Result<Shell, ShellError> {
+        Shell::detect_offline_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::detect_offline`], but reads the environment variables from `env`.
+    pub fn detect_offline_with_env(env: &dyn Env) -> Result<Shell, ShellError> {
+        let forced = env
+            .var(SHELL_OVERRIDE_VAR)
+            .and_then(|name| Shell::from_name_lenient(&name));
+        if let Some(shell) = forced {
+            return Ok(shell);
+        }
+        if let Some(shell) = env.var("SHELL").filter(|shell| !shell.is_empty()) {
+            return classify_shell_name(&shell);
+        }
+
+        if cfg!(windows) {
+            env.var("PSModulePath")
+                .as_deref()
+                .and_then(powershell_for_module_path)
+                .ok_or(ShellError::UnsupportedPlatform)
+        } else {
+            Err(ShellError::NoShellVar)
+        }
+    }
+
+    /// Returns whether [`Shell::get_rcfiles`] spawns the shell to locate its rcfiles.
     ///
-    /// # Example
-    /// ```
-    /// if let Some(paths) = get_rcfiles() {
-    ///     // paths[0] points to ~/.config/fish/conf.d directory
-    ///     // not to specific .fish files
-    /// }
-    /// ```
-    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
-        let mut paths = vec![];
+    /// Use [`Shell::get_rcfiles_offline`] when subprocesses aren't allowed.
+    pub fn requires_subprocess_for_detection(&self) -> bool {
+        matches!(self, Shell::Zsh(_))
+    }
 
-        if let Some(path) = config_dir() {
-            paths.push(path.join("fish/conf.d"));
+    /// Like [`Shell::does_exist`], but reads `$SHELL` and PATH from `env`.
+    pub fn does_exist_with_env(&self, env: &dyn Env) -> bool {
+        match self {
+            Shell::Fish(fish) => fish.does_exist_with_env(env),
+            Shell::Zsh(zsh) => zsh.does_exist_with_env(env),
+            Shell::Bash(bash) => bash.does_exist_with_env(env),
+            Shell::POSIX(posix) => posix.does_exist_with_env(env),
+            Shell::PowerShell(pwsh) => pwsh.does_exist_with_env(env),
+            Shell::WindowsPowerShell(powershell) => powershell.does_exist_with_env(env),
         }
+    }
 
-        Ok(paths)
+    /// Like [`Shell::get_rcfiles`], but never spawns a process.
+    pub fn get_rcfiles_offline(&self) -> Result<Vec<PathBuf>, ShellError> {
+        match self {
+            Shell::Zsh(zsh) => zsh.get_rcfiles_offline(),
+            _ => self.get_rcfiles(),
+        }
     }
 
-    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
-        vec![base_dir.as_ref().join(".config/fish/conf.d")]
+    /// Returns whether this shell's binary can be found in one of the directories of `path`.
+    ///
+    /// This is the check [`Shell::does_exist`] performs against the live PATH, and allows
+    /// checking a hypothetical PATH without touching the environment.
+    pub fn binary_exists_in(&self, path: &OsStr) -> bool {
+        binary_in(self.binary_name(), path)
     }
-}
 
-pub fn exists_in_path(path: impl AsRef<Path>) -> bool {
-    matches!(env::var("PATH"), Ok(paths) if paths.contains(path.as_ref().to_str().unwrap()))
-}
+    /// Returns every shell supported by this library.
+    pub fn all() -> Vec<Shell> {
+        vec![
+            Shell::POSIX(POSIX),
+            Shell::Zsh(Zsh),
+            Shell::Bash(Bash),
+            Shell::Fish(Fish),
+            Shell::PowerShell(PowerShell),
+            Shell::WindowsPowerShell(WindowsPowerShell),
+        ]
+    }
 
-pub fn append_to_rcfile(rcfile: PathBuf, line: &str) -> std::io::Result<()> {
-    use std::fs::OpenOptions;
-    use std::io::Write;
+    /// Returns whether this shell is installed, judging by `$SHELL` and the live PATH.
+    ///
+    /// This only searches PATH for the shell's binary and never runs it, so it is safe to use
+    /// where subprocesses aren't allowed.
+    pub fn does_exist(&self) -> bool {
+        match self {
+            Shell::Fish(fish) => fish.does_exist(),
+            Shell::Zsh(zsh) => zsh.does_exist(),
+            Shell::Bash(bash) => bash.does_exist(),
+            Shell::POSIX(posix) => posix.does_exist(),
+            Shell::PowerShell(pwsh) => pwsh.does_exist(),
+            Shell::WindowsPowerShell(powershell) => powershell.does_exist(),
+        }
+    }
 
-    let mut file = OpenOptions::new().append(true).open(rcfile)?;
-    writeln!(file, "{}", line)
-}
+    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
+        let rcfiles = match self {
+            Shell::Fish(fish) => fish.get_rcfiles(),
+            Shell::Zsh(zsh) => zsh.get_rcfiles(),
+            Shell::Bash(bash) => bash.get_rcfiles(),
+            Shell::POSIX(posix) => posix.get_rcfiles(),
+            Shell::PowerShell(pwsh) => pwsh.get_rcfiles(),
+            Shell::WindowsPowerShell(powershell) => powershell.get_rcfiles(),
+        }?;
+        self.trace_rcfiles(&rcfiles);
 
-pub fn remove_from_rcfile(rcfile: PathBuf, line: &str) -> std::io::Result<()> {
-    let line_bytes = line.as_bytes();
+        Ok(rcfiles)
+    }
 
-    let file = std::fs::read_to_string(&rcfile)?;
-    let file_bytes = file.as_bytes();
+    /// Like [`Shell::get_rcfiles`], but reads the environment variables from `env`.
+    ///
+    /// This never spawns a process: for Zsh, `ZDOTDIR` is found like
+    /// [`Zsh::get_rcfiles_offline`] does.
+    pub fn get_rcfiles_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let rcfiles = match self {
+            Shell::Fish(fish) => fish.get_rcfiles_with_env(env),
+            Shell::Zsh(zsh) => zsh.get_rcfiles_with_env(env),
+            Shell::Bash(bash) => bash.get_rcfiles_with_env(env),
+            Shell::POSIX(posix) => posix.get_rcfiles_with_env(env),
+            Shell::PowerShell(pwsh) => pwsh.get_rcfiles_with_env(env),
+            Shell::WindowsPowerShell(powershell) => powershell.get_rcfiles_with_env(env),
+        }?;
+        self.trace_rcfiles(&rcfiles);
 
-    if let Some(idx) = file_bytes
-        .windows(line_bytes.len())
-        .position(|w| w == line_bytes)
-    {
-        let mut new_bytes = file_bytes[..idx].to_vec();
-        new_bytes.extend(&file_bytes[idx + line_bytes.len()..]);
-        let content = String::from_utf8(new_bytes).unwrap();
-        std::fs::write(&rcfile, content)?;
+        Ok(rcfiles)
     }
 
-    Ok(())
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn trace_rcfiles(&self, rcfiles: &[PathBuf]) {
+        #[cfg(feature = "tracing")]
+        for rcfile in rcfiles {
+            tracing::debug!(
+                shell = self.binary_name(),
+                rcfile = %rcfile.display(),
+                exists = rcfile.exists(),
+                "found rcfile candidate"
+            );
+        }
+    }
+
+    /// Like [`Shell::get_rcfiles`], but only returns the rcfiles that exist.
+    pub fn get_existing_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.get_existing_rcfiles_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::get_existing_rcfiles`], but reads the environment variables from `env`.
+    pub fn get_existing_rcfiles_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let mut rcfiles = self.all_rcfile_candidates_with_env(env)?;
+        rcfiles.retain(|rcfile| rcfile.exists());
+        Ok(rcfiles)
+    }
+
+    /// Returns every existing rcfile of this shell (see [`Shell::get_existing_rcfiles`])
+    /// paired with its contents, for scanning them all in one pass.
+    ///
+    /// A directory among the rcfiles, such as fish's `conf.d`, contributes the files directly
+    /// in it, in name order. Files that can't be read (e.g. for lack of permission or because
+    /// they aren't text) are skipped.
+    pub fn iter_rcfiles_with_contents(&self) -> Result<Vec<(PathBuf, String)>, ShellError> {
+        self.iter_rcfiles_with_contents_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::iter_rcfiles_with_contents`], but reads the environment variables from
+    /// `env`.
+    pub fn iter_rcfiles_with_contents_with_env(
+        &self,
+        env: &dyn Env,
+    ) -> Result<Vec<(PathBuf, String)>, ShellError> {
+        let mut files = Vec::new();
+        for rcfile in self.get_existing_rcfiles_with_env(env)? {
+            if rcfile.is_dir() {
+                let Ok(entries) = std::fs::read_dir(&rcfile) else {
+                    continue;
+                };
+                let mut dir_files: Vec<PathBuf> = entries
+                    .filter_map(|entry| Some(entry.ok()?.path()))
+                    .filter(|path| path.is_file())
+                    .collect();
+                dir_files.sort();
+                files.extend(dir_files);
+            } else {
+                files.push(rcfile);
+            }
+        }
+
+        Ok(files
+            .into_iter()
+            .filter_map(|file| {
+                let (_, content) = read_rcfile(&file).ok()?;
+                Some((file, content))
+            })
+            .collect())
+    }
+
+    /// Returns every rcfile path this shell may read, whether it exists or not.
+    ///
+    /// - POSIX: `~/.profile`
+    /// - Bash: `~/.bash_profile`, `~/.bash_login` and `~/.bashrc`
+    /// - Zsh: `.zshenv` in `$ZDOTDIR` if known (see [`Zsh::zdotdir_offline`]), then in `$HOME`
+    /// - Fish: the `conf.d` directory and `config.fish`, see [`Fish::get_rcfiles`]
+    /// - PowerShell and Windows PowerShell: `profile.ps1` and `Microsoft.PowerShell_profile.ps1`,
+    ///   see [`PowerShell::get_rcfiles`]
+    ///
+    /// Unlike [`Shell::get_rcfiles`], which only lists the Zsh files that exist, the result
+    /// doesn't depend on the files present, and no process is spawned.
+    pub fn all_rcfile_candidates(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.all_rcfile_candidates_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::all_rcfile_candidates`], but reads the environment variables from `env`.
+    pub fn all_rcfile_candidates_with_env(
+        &self,
+        env: &dyn Env,
+    ) -> Result<Vec<PathBuf>, ShellError> {
+        match self {
+            Shell::Zsh(zsh) => {
+                let mut rcfiles: Vec<PathBuf> = zsh
+                    .zdotdir_offline_with_env(env)
+                    .map(|zdotdir| zdotdir.join(".zshenv"))
+                    .into_iter()
+                    .collect();
+                let home_zshenv = get_home_dir_with_env(env)?.join(".zshenv");
+                if !rcfiles.contains(&home_zshenv) {
+                    rcfiles.push(home_zshenv);
+                }
+                Ok(rcfiles)
+            }
+            Shell::Fish(_)
+            | Shell::Bash(_)
+            | Shell::POSIX(_)
+            | Shell::PowerShell(_)
+            | Shell::WindowsPowerShell(_) => self.get_rcfiles_with_env(env),
+        }
+    }
+
+    pub fn get_rcfiles_from_base(&self, base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
+        match self {
+            Shell::Fish(_) => Fish::get_rcfiles_from_base(base_dir),
+            Shell::Zsh(_) => Zsh::get_rcfiles_from_base(base_dir),
+            Shell::Bash(_) => Bash::get_rcfiles_from_base(base_dir),
+            Shell::POSIX(_) => POSIX::get_rcfiles_from_base(base_dir),
+            Shell::PowerShell(_) => PowerShell::get_rcfiles_from_base(base_dir),
+            Shell::WindowsPowerShell(_) => WindowsPowerShell::get_rcfiles_from_base(base_dir),
+        }
+    }
+
+    /// Returns the file named by the shell's startup-file environment variable, if set.
+    ///
+    /// - Bash: `$BASH_ENV`, read by non-interactive shells such as `bash script.sh`. Scripts
+    ///   read no other rcfile, so editing this file is the only way to affect their PATH
+    ///   (short of the environment they are started from).
+    /// - POSIX: `$ENV`, read by interactive `sh` instances, including non-login ones that
+    ///   never read `~/.profile`.
+    /// - Other shells have no such variable and return `None`.
+    pub fn script_env_file(&self) -> Option<PathBuf> {
+        self.script_env_file_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::script_env_file`], but reads the variable from `env`.
+    pub fn script_env_file_with_env(&self, env: &dyn Env) -> Option<PathBuf> {
+        let var = match self {
+            Shell::Bash(_) => "BASH_ENV",
+            Shell::POSIX(_) => "ENV",
+            Shell::Zsh(_) | Shell::Fish(_) | Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => {
+                return None
+            }
+        };
+
+        env.var_os(var)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Like [`Shell::get_rcfiles`], but also includes [`Shell::script_env_file`] when set.
+    ///
+    /// See [`Shell::get_rcfiles_with_env`] and [`Shell::script_env_file_with_env`] to read the
+    /// variables from another environment.
+    pub fn get_rcfiles_with_script_env(&self) -> Result<Vec<PathBuf>, ShellError> {
+        let mut rcfiles = self.get_rcfiles()?;
+        if let Some(env_file) = self.script_env_file() {
+            if !rcfiles.contains(&env_file) {
+                rcfiles.push(env_file);
+            }
+        }
+        Ok(rcfiles)
+    }
+
+    /// Like [`Shell::get_rcfiles_with_script_env`], but reads the environment variables from
+    /// `env`, as [`Shell::get_rcfiles_with_env`] does.
+    pub fn get_rcfiles_with_script_env_with_env(
+        &self,
+        env: &dyn Env,
+    ) -> Result<Vec<PathBuf>, ShellError> {
+        let mut rcfiles = self.get_rcfiles_with_env(env)?;
+        if let Some(env_file) = self.script_env_file_with_env(env) {
+            if !rcfiles.contains(&env_file) {
+                rcfiles.push(env_file);
+            }
+        }
+        Ok(rcfiles)
+    }
+
+    /// Returns the user rcfiles a login, interactive session of this shell reads, in the order
+    /// it reads them.
+    ///
+    /// - POSIX: `~/.profile`, then [`Shell::script_env_file`] (`$ENV`) when set
+    /// - Bash: the first existing of `~/.bash_profile`, `~/.bash_login` and `~/.profile`
+    ///   (`~/.bash_profile` if none exist). Login shells don't read `~/.bashrc` on their own,
+    ///   it only takes effect if that file sources it.
+    /// - Zsh: `.zshenv`, `.zprofile`, `.zshrc` and `.zlogin` in [`Shell::config_home`]
+    /// - Fish: the `.fish` files in `conf.d` in name order, then `config.fish`
+    /// - PowerShell and Windows PowerShell: `profile.ps1`, then `Microsoft.PowerShell_profile.ps1`
+    ///   in [`Shell::config_home`]
+    ///
+    /// System-wide files (such as `/etc/profile`), which are read before these, are not
+    /// included. Unlike [`Shell::get_rcfiles`], this never spawns a process.
+    pub fn init_order(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.init_order_with_env(&SystemEnv)
+    }
+
+    /// Like [`Shell::init_order`], but reads the environment variables from `env`.
+    pub fn init_order_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let config_home = self.config_home_with_env(env)?;
+        let rcfiles = match self {
+            Shell::POSIX(_) => {
+                let mut rcfiles = vec![config_home.join(".profile")];
+                rcfiles.extend(self.script_env_file_with_env(env));
+                rcfiles
+            }
+            Shell::Bash(_) => {
+                let candidates =
+                    [".bash_profile", ".bash_login", ".profile"].map(|rc| config_home.join(rc));
+                let read = candidates
+                    .iter()
+                    .find(|rcfile| rcfile.exists())
+                    .unwrap_or(&candidates[0]);
+                vec![read.clone()]
+            }
+            Shell::Zsh(_) => {
+                let mut rcfiles = vec![Zsh::startup_dir(env)?.join(".zshenv")];
+                rcfiles.extend(
+                    [".zprofile", ".zshrc", ".zlogin"]
+                        .iter()
+                        .map(|rc| config_home.join(rc)),
+                );
+                rcfiles
+            }
+            Shell::Fish(_) => {
+                let mut rcfiles: Vec<PathBuf> = std::fs::read_dir(config_home.join("conf.d"))
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension() == self.dropin_extension().map(OsStr::new))
+                    .collect();
+                rcfiles.sort();
+                rcfiles.push(config_home.join("config.fish"));
+                rcfiles
+            }
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => powershell_profiles(&config_home),
+        };
+        Ok(rcfiles)
+    }
+
+    /// Returns the directories this shell persists on PATH outside of any rcfile.
+    ///
+    /// Only Fish has such a mechanism (the `fish_user_paths` universal variable, see
+    /// [`Fish::user_paths`]); other shells return `ShellError::UnsupportedOperation`.
+    pub fn user_paths(&self) -> Result<Vec<PathBuf>, ShellError> {
+        match self {
+            Shell::Fish(fish) => fish.user_paths(),
+            _ => Err(self.unsupported("user_paths")),
+        }
+    }
+
+    /// Persists `dir` on PATH outside of any rcfile. See [`Shell::user_paths`].
+    pub fn add_user_path(&self, dir: &Path) -> Result<(), ShellError> {
+        match self {
+            Shell::Fish(fish) => fish.add_user_path(dir),
+            _ => Err(self.unsupported("add_user_path")),
+        }
+    }
+
+    /// Removes `dir` from the directories persisted by [`Shell::add_user_path`].
+    pub fn remove_user_path(&self, dir: &Path) -> Result<(), ShellError> {
+        match self {
+            Shell::Fish(fish) => fish.remove_user_path(dir),
+            _ => Err(self.unsupported("remove_user_path")),
+        }
+    }
+
+    /// Returns the contents to start a new rcfile of this shell with: a header comment
+    /// saying what reads the file.
+    ///
+    /// No other boilerplate is included, since anything else (such as returning early from
+    /// non-interactive shells) could keep lines appended later from taking effect.
+    pub fn rcfile_template(&self) -> &'static str {
+        match self {
+            Shell::POSIX(_) => "# Read by POSIX-compliant login shells.\n\n",
+            Shell::Bash(_) => {
+                "# Read by bash at startup, see the INVOCATION section of bash(1).\n\n"
+            }
+            Shell::Zsh(_) => {
+                "# Read by zsh at startup, see the STARTUP/SHUTDOWN FILES section of zsh(1).\n\n"
+            }
+            Shell::Fish(_) => "# Read by fish at startup, see `help config`.\n\n",
+            Shell::PowerShell(_) => "# Read by PowerShell at startup, see about_Profiles.\n\n",
+            Shell::WindowsPowerShell(_) => {
+                "# Read by Windows PowerShell at startup, see about_Profiles.\n\n"
+            }
+        }
+    }
+
+    /// Reads `path` and returns a few cheap statistics about it, see [`RcFileStats`].
+    ///
+    /// PATH exports are recognized in any supported shell's syntax, as in
+    /// [`classify_rcfile_lines`].
+    pub fn rcfile_stats(&self, path: &Path) -> Result<RcFileStats, ShellError> {
+        let bytes = std::fs::metadata(path)?.len();
+        let (_, content) = read_rcfile(path)?;
+
+        Ok(RcFileStats {
+            lines: content.lines().count(),
+            bytes,
+            path_exports: content.lines().filter(|line| is_path_export(line)).count(),
+            ends_with_newline: content.ends_with('\n'),
+        })
+    }
+
+    /// Returns whether `path` can be edited, or created if it doesn't exist yet, e.g. to fail
+    /// fast or ask for elevated permissions before an edit.
+    ///
+    /// An existing file must not be read-only and must open for writing. A missing file is
+    /// created along with its missing parent directories (see [`append_to_rcfile_create`]),
+    /// so its nearest existing ancestor must be a directory that isn't read-only.
+    pub fn rcfile_writable(&self, path: &Path) -> bool {
+        match std::fs::metadata(path) {
+            Ok(metadata) => {
+                !metadata.permissions().readonly()
+                    && std::fs::OpenOptions::new().append(true).open(path).is_ok()
+            }
+            Err(_) => path
+                .ancestors()
+                .skip(1)
+                .find_map(|ancestor| std::fs::metadata(ancestor).ok())
+                .is_some_and(|ancestor| ancestor.is_dir() && !ancestor.permissions().readonly()),
+        }
+    }
+
+    /// Returns whether `dir` is already registered on PATH through this shell's own mechanism.
+    ///
+    /// - Fish: `dir` is in the `fish_user_paths` universal variable (see [`Fish::user_paths`])
+    /// - Other shells: `dir` is on the live PATH (see [`exists_in_path_canonical`])
+    pub fn dir_registered(&self, dir: &Path) -> Result<bool, ShellError> {
+        self.dir_registered_with_env(dir, &SystemEnv)
+    }
+
+    /// Like [`Shell::dir_registered`], but looks at the PATH in `env`, or for Fish at the
+    /// universal variables under the `HOME` and `XDG_CONFIG_HOME` of `env`.
+    pub fn dir_registered_with_env(&self, dir: &Path, env: &dyn Env) -> Result<bool, ShellError> {
+        match self {
+            Shell::Fish(fish) => Ok(fish
+                .user_paths_with_env(env)?
+                .iter()
+                .any(|path| path == dir)),
+            Shell::Zsh(_)
+            | Shell::Bash(_)
+            | Shell::POSIX(_)
+            | Shell::PowerShell(_)
+            | Shell::WindowsPowerShell(_) => Ok(exists_in_path_canonical_within(
+                dir,
+                &env.var_os("PATH").unwrap_or_default(),
+            )),
+        }
+    }
+
+    fn unsupported(&self, operation: &'static str) -> ShellError {
+        ShellError::UnsupportedOperation {
+            shell: self.binary_name(),
+            operation,
+        }
+    }
+
+    /// Returns the name of the binary used to launch this shell.
+    pub fn binary_name(&self) -> &'static str {
+        match self {
+            Shell::Fish(_) => "fish",
+            Shell::Zsh(_) => "zsh",
+            Shell::Bash(_) => "bash",
+            Shell::POSIX(_) => "sh",
+            Shell::PowerShell(_) => "pwsh",
+            Shell::WindowsPowerShell(_) => "powershell",
+        }
+    }
+
+    /// Wraps `body` in start and end markers named `name`, written as comments.
+    ///
+    /// The markers let a tool find and remove the lines it added later on, e.g. for `mytool`:
+    /// ```text
+    /// # >>> mytool >>>
+    /// export PATH="/opt/mytool/bin:$PATH"
+    /// # <<< mytool <<<
+    /// ```
+    pub fn managed_block(&self, name: &str, body: &str) -> String {
+        let prefix = COMMENT_PREFIX;
+        format!(
+            "{prefix} >>> {name} >>>\n{}\n{prefix} <<< {name} <<<",
+            body.trim_end_matches('\n')
+        )
+    }
+
+    /// Appends `body` to `rcfile` as a managed block named `name`. See [`Shell::managed_block`].
+    pub fn append_managed_block(
+        &self,
+        rcfile: &Path,
+        name: &str,
+        body: &str,
+    ) -> Result<(), ShellError> {
+        if !self.rcfile_writable(rcfile) {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied).into());
+        }
+        append_text(rcfile.to_path_buf(), &self.managed_block(name, body))
+    }
+
+    /// Removes every managed block named `name` from `rcfile`, along with its markers.
+    ///
+    /// Everything between the markers is removed, even if it was edited since the block was
+    /// added. Returns the number of blocks removed.
+    ///
+    /// # Errors
+    /// Returns `ShellError::UnterminatedManagedBlock` without modifying the file if a start
+    /// marker has no matching end marker, rather than guessing where the block ends.
+    pub fn remove_managed_block(&self, rcfile: &Path, name: &str) -> Result<usize, ShellError> {
+        let prefix = COMMENT_PREFIX;
+        let start = format!("{prefix} >>> {name} >>>");
+        let end = format!("{prefix} <<< {name} <<<");
+
+        let mut removed = 0;
+        let mut unterminated = false;
+        modify_rcfile(rcfile, |file| {
+            let mut content = String::with_capacity(file.len());
+            let mut in_block = false;
+            for line in file.split_inclusive('\n') {
+                if !in_block && line.trim() == start {
+                    in_block = true;
+                } else if in_block && line.trim() == end {
+                    in_block = false;
+                    removed += 1;
+                } else if !in_block {
+                    content.push_str(line);
+                }
+            }
+
+            if in_block {
+                unterminated = true;
+                file
+            } else {
+                content
+            }
+        })?;
+
+        if unterminated {
+            return Err(ShellError::UnterminatedManagedBlock(name.to_string()));
+        }
+
+        Ok(removed)
+    }
+
+    /// Appends a trailing `annotation` comment to `line`, e.g.
+    /// `export PATH="/opt/mytool/bin:$PATH" # added by mytool`.
+    pub fn annotated_line(&self, line: &str, annotation: &str) -> String {
+        format!("{line} {COMMENT_PREFIX} {annotation}")
+    }
+
+    /// Appends `line` to `rcfile` with a trailing `annotation` comment.
+    /// See [`Shell::annotated_line`].
+    pub fn append_to_rcfile_annotated(
+        &self,
+        rcfile: &Path,
+        line: &str,
+        annotation: &str,
+    ) -> Result<(), ShellError> {
+        if !self.rcfile_writable(rcfile) {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied).into());
+        }
+        append_to_rcfile(rcfile.to_path_buf(), &self.annotated_line(line, annotation))
+    }
+
+    /// Removes every line of `rcfile` carrying the trailing `annotation` comment, whatever
+    /// the rest of the line contains.
+    ///
+    /// This lets an uninstaller find the lines it added even if the PATH they set has
+    /// changed since. Returns the number of lines removed.
+    pub fn remove_annotated_lines(
+        &self,
+        rcfile: &Path,
+        annotation: &str,
+    ) -> Result<usize, ShellError> {
+        let suffix = format!("{COMMENT_PREFIX} {annotation}");
+
+        let mut removed = 0;
+        modify_rcfile(rcfile, |file| {
+            file.split_inclusive('\n')
+                .filter(|line| {
+                    let annotated = line.trim_end().ends_with(&suffix);
+                    removed += usize::from(annotated);
+                    !annotated
+                })
+                .collect()
+        })?;
+
+        Ok(removed)
+    }
+
+    /// Removes `line` from `rcfile`, but only where it is managed by `marker`: annotated with it
+    /// (see [`Shell::annotated_line`]) or inside the managed block of that name (see
+    /// [`Shell::managed_block`]).
+    ///
+    /// Matching lines the user wrote themselves are kept; use [`remove_from_rcfile`] to remove
+    /// those too. Lines are compared ignoring surrounding whitespace, and the markers of a
+    /// block are kept even if it ends up empty. Returns the number of lines removed.
+    ///
+    /// # Errors
+    /// Returns `ShellError::UnterminatedManagedBlock` without modifying the file if the
+    /// `marker` block has no end marker.
+    pub fn remove_managed_line(
+        &self,
+        rcfile: &Path,
+        line: &str,
+        marker: &str,
+    ) -> Result<usize, ShellError> {
+        let prefix = COMMENT_PREFIX;
+        let start = format!("{prefix} >>> {marker} >>>");
+        let end = format!("{prefix} <<< {marker} <<<");
+        let line = line.trim();
+        let annotated = self.annotated_line(line, marker);
+
+        let mut removed = 0;
+        let mut unterminated = false;
+        modify_rcfile(rcfile, |file| {
+            let mut content = String::with_capacity(file.len());
+            let mut in_block = false;
+            for existing in file.split_inclusive('\n') {
+                let trimmed = existing.trim();
+                if trimmed == start {
+                    in_block = true;
+                } else if trimmed == end {
+                    in_block = false;
+                }
+
+                if trimmed == annotated || (in_block && trimmed == line) {
+                    removed += 1;
+                } else {
+                    content.push_str(existing);
+                }
+            }
+
+            if in_block {
+                unterminated = true;
+                file
+            } else {
+                content
+            }
+        })?;
+
+        if unterminated {
+            return Err(ShellError::UnterminatedManagedBlock(marker.to_string()));
+        }
+
+        Ok(removed)
+    }
+
+    /// Returns the template of a line that sets and exports an environment variable in this
+    /// shell's syntax, with `{name}` and `{value}` placeholders.
+    ///
+    /// - POSIX, Bash and Zsh: `export {name}="{value}"`
+    /// - Fish: `set -gx {name} {value}`
+    /// - PowerShell and Windows PowerShell: `$env:{name} = "{value}"`
+    ///
+    /// The value is inserted as-is, so it must already be escaped for the template's quoting:
+    /// double quotes for the sh family and PowerShell, none for fish.
+    ///
+    /// [`Shell::path_add_line`] and [`Shell::path_add_lines`] build their lines from this
+    /// template for every shell but Fish, whose PATH lines use `fish_add_path` instead, as it
+    /// skips directories that are already on PATH.
+    pub fn export_template(&self) -> &'static str {
+        match self {
+            Shell::Fish(_) => "set -gx {name} {value}",
+            Shell::Zsh(_) | Shell::Bash(_) | Shell::POSIX(_) => "export {name}=\"{value}\"",
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => "$env:{name} = \"{value}\"",
+        }
+    }
+
+    /// Fills [`Shell::export_template`] with `name` and the already escaped `value`.
+    fn export_line(&self, name: &str, value: &str) -> String {
+        self.export_template()
+            .replace("{name}", name)
+            .replace("{value}", value)
+    }
+
+    /// Generates a line that adds `dir` to PATH in this shell's syntax.
+    ///
+    /// `placement` decides whether the directory is searched before or after the existing
+    /// PATH entries, see [`Placement`] for what that means for shadowing.
+    pub fn path_add_line(&self, dir: &Path, placement: Placement) -> String {
+        let dir = dir.to_string_lossy();
+        let var = PATH_VAR;
+        match (self, placement) {
+            (Shell::Fish(_), Placement::Prepend) => format!("fish_add_path {}", fish_quote(&dir)),
+            (Shell::Fish(_), Placement::Append) => {
+                format!("fish_add_path --append {}", fish_quote(&dir))
+            }
+            (Shell::Zsh(_) | Shell::Bash(_) | Shell::POSIX(_), Placement::Prepend) => {
+                self.export_line(var, &format!("{}:${var}", double_quote_escape(&dir)))
+            }
+            (Shell::Zsh(_) | Shell::Bash(_) | Shell::POSIX(_), Placement::Append) => {
+                self.export_line(var, &format!("${var}:{}", double_quote_escape(&dir)))
+            }
+            (Shell::PowerShell(_) | Shell::WindowsPowerShell(_), Placement::Prepend) => self
+                .export_line(
+                    var,
+                    &format!(
+                        "{}{}${{env:{var}}}",
+                        powershell_escape(&dir),
+                        path_separator()
+                    ),
+                ),
+            (Shell::PowerShell(_) | Shell::WindowsPowerShell(_), Placement::Append) => self
+                .export_line(
+                    var,
+                    &format!(
+                        "${{env:{var}}}{}{}",
+                        path_separator(),
+                        powershell_escape(&dir)
+                    ),
+                ),
+        }
+    }
+
+    /// Returns the directories `line` adds to PATH, in this shell's syntax, or `None` if the
+    /// line isn't a PATH export.
+    ///
+    /// These are the exports that [`Shell::path_add_line`] and its relatives write, along
+    /// with the common hand-written forms:
+    /// - POSIX and Bash: `PATH=...` and `PATH+=...` assignments, with or without `export`
+    /// - Zsh: the same, plus assignments to the `path` array such as `path+=(/a /b)`
+    /// - Fish: `fish_add_path` and `set PATH`/`set fish_user_paths`
+    /// - PowerShell and Windows PowerShell: `$env:PATH = ...` and `$env:PATH += ...`
+    ///
+    /// References to the existing PATH such as `$PATH` aren't included, so a line that only
+    /// rearranges PATH returns an empty list.
+    pub fn parse_path_export(&self, line: &str) -> Option<Vec<PathBuf>> {
+        match self {
+            Shell::POSIX(_) | Shell::Bash(_) => sh_path_export_dirs(line),
+            Shell::Zsh(_) => sh_path_export_dirs(line).or_else(|| zsh_path_array_dirs(line)),
+            Shell::Fish(_) => fish_path_export_dirs(line),
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => powershell_path_export_dirs(line),
+        }
+    }
+
+    /// Generates a line that removes every occurrence of `dir` from PATH in this shell's syntax,
+    /// the counterpart of [`Shell::path_add_line`] for deactivation scripts.
+    ///
+    /// - POSIX and Bash: rebuilds PATH without `dir` in a subshell, splitting on `:` with
+    ///   globbing disabled
+    /// - Zsh: filters the `path` array
+    /// - Fish: erases the matching elements of `PATH`
+    /// - PowerShell and Windows PowerShell: splits `$env:PATH` and joins it back without `dir`
+    pub fn path_remove_line(&self, dir: &Path) -> String {
+        let dir = dir.to_string_lossy();
+        let var = PATH_VAR;
+        match self {
+            Shell::Fish(_) => format!(
+                "while set -l index (contains -i -- {} ${var}); set -e {var}[$index]; end",
+                fish_quote(&dir)
+            ),
+            Shell::Zsh(_) => format!("path=(${{path:#\"{}\"}})", double_quote_escape(&dir)),
+            Shell::Bash(_) | Shell::POSIX(_) => {
+                let rebuilt = format!(
+                    "$(set -f; IFS=:; p=; for d in ${var}; do [ \"$d\" = \"{}\" ] || p=\"${{p:+$p:}}$d\"; done; printf '%s' \"$p\")",
+                    double_quote_escape(&dir)
+                );
+                self.export_line(var, &rebuilt)
+            }
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => {
+                let separator = path_separator();
+                format!(
+                    "$env:{var} = ($env:{var} -split '{separator}' | Where-Object {{ $_ -ne {} }}) -join '{separator}'",
+                    self.quote(&dir)
+                )
+            }
+        }
+    }
+
+    /// Like [`Shell::path_add_line`], but adds all of `dirs` in a single line.
+    ///
+    /// The directories keep their relative order on PATH, whatever the `placement`.
+    /// Returns an empty string if `dirs` is empty.
+    pub fn path_add_lines(&self, dirs: &[PathBuf], placement: Placement) -> String {
+        if dirs.is_empty() {
+            return String::new();
+        }
+
+        let var = PATH_VAR;
+        match self {
+            Shell::Fish(_) => {
+                let dirs: Vec<String> = dirs
+                    .iter()
+                    .map(|dir| fish_quote(&dir.to_string_lossy()))
+                    .collect();
+                let append = match placement {
+                    Placement::Prepend => "",
+                    Placement::Append => "--append ",
+                };
+                format!("fish_add_path {append}{}", dirs.join(" "))
+            }
+            Shell::Zsh(_) | Shell::Bash(_) | Shell::POSIX(_) => {
+                let dirs: Vec<String> = dirs
+                    .iter()
+                    .map(|dir| double_quote_escape(&dir.to_string_lossy()))
+                    .collect();
+                let dirs = dirs.join(":");
+                match placement {
+                    Placement::Prepend => self.export_line(var, &format!("{dirs}:${var}")),
+                    Placement::Append => self.export_line(var, &format!("${var}:{dirs}")),
+                }
+            }
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => {
+                let separator = path_separator().to_string();
+                let dirs: Vec<String> = dirs
+                    .iter()
+                    .map(|dir| powershell_escape(&dir.to_string_lossy()))
+                    .collect();
+                let dirs = dirs.join(&separator);
+                match placement {
+                    Placement::Prepend => {
+                        self.export_line(var, &format!("{dirs}{separator}${{env:{var}}}"))
+                    }
+                    Placement::Append => {
+                        self.export_line(var, &format!("${{env:{var}}}{separator}{dirs}"))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Generates a line that sources `script` in this shell's syntax.
+    pub fn source_line(&self, script: &Path) -> String {
+        let script = script.to_string_lossy();
+        match self {
+            Shell::Fish(_) => format!("source {}", fish_quote(&script)),
+            Shell::Zsh(_) | Shell::Bash(_) | Shell::POSIX(_) => {
+                format!(". \"{}\"", double_quote_escape(&script))
+            }
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => {
+                format!(". {}", self.quote(&script))
+            }
+        }
+    }
+
+    /// Returns what to tell the user after `edited`, one of this shell's rcfiles, was edited
+    /// for the change to take effect.
+    ///
+    /// Rcfiles only apply to sessions started after the edit, so the message suggests
+    /// sourcing `edited` (with [`Shell::source_line`]) in the current session or opening a new
+    /// one. Login files (`.profile`, `.bash_profile`, `.bash_login`, `.zprofile` and `.zlogin`)
+    /// are only read by login shells, so for those a new login is suggested instead of a new
+    /// terminal.
+    pub fn activation_instructions(&self, edited: &Path) -> String {
+        let login_file = match self {
+            Shell::POSIX(_) | Shell::Bash(_) | Shell::Zsh(_) => edited
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    matches!(
+                        name,
+                        ".profile" | ".bash_profile" | ".bash_login" | ".zprofile" | ".zlogin"
+                    )
+                }),
+            Shell::Fish(_) | Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => false,
+        };
+        let new_session = if login_file {
+            "log out and back in"
+        } else {
+            "open a new terminal"
+        };
+
+        format!(
+            "Run `{}` to update this session, or {new_session} for the change to take effect.",
+            self.source_line(edited)
+        )
+    }
+
+    /// Quotes `s` so this shell reads it back as a single literal word.
+    ///
+    /// The sh family gets POSIX single quotes, where nothing is special except the quote
+    /// itself. Fish leaves plain words (such as most paths) as they are and single-quotes
+    /// anything else, escaping quotes and backslashes. PowerShell gets single quotes too, with
+    /// quotes doubled.
+    pub fn quote(&self, s: &str) -> String {
+        match self {
+            Shell::Fish(_) => fish_quote(s),
+            Shell::Zsh(_) | Shell::Bash(_) | Shell::POSIX(_) => {
+                format!("'{}'", s.replace('\'', r"'\''"))
+            }
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => {
+                format!("'{}'", s.replace('\'', "''"))
+            }
+        }
+    }
+
+    /// Generates the full block an installer typically adds to an rcfile: the PATH
+    /// additions for `dirs`, guarded against duplicates, followed by an optional line
+    /// sourcing `env_script`.
+    ///
+    /// `dirs` are added according to `placement` (see [`Placement`]) and keep their relative
+    /// order. The result is ready to be passed to [`Shell::append_managed_block`].
+    pub fn install_snippet(
+        &self,
+        dirs: &[PathBuf],
+        placement: Placement,
+        env_script: Option<&Path>,
+    ) -> String {
+        let mut lines = Vec::new();
+
+        match self {
+            // fish_add_path already skips directories that are on PATH
+            Shell::Fish(_) if !dirs.is_empty() => {
+                let dirs: Vec<String> = dirs
+                    .iter()
+                    .map(|dir| fish_quote(&dir.to_string_lossy()))
+                    .collect();
+                let append = match placement {
+                    Placement::Prepend => "",
+                    Placement::Append => "--append ",
+                };
+                lines.push(format!("fish_add_path {append}{}", dirs.join(" ")));
+            }
+            Shell::Fish(_) => {}
+            Shell::Zsh(_) | Shell::Bash(_) | Shell::POSIX(_) => {
+                // Prepend in reverse so that the first directory still ends up first
+                let ordered: Vec<&PathBuf> = match placement {
+                    Placement::Prepend => dirs.iter().rev().collect(),
+                    Placement::Append => dirs.iter().collect(),
+                };
+                for dir in ordered {
+                    let escaped = double_quote_escape(&dir.to_string_lossy());
+                    lines.push(format!("case \":${{{PATH_VAR}}}:\" in"));
+                    lines.push(format!("    *:\"{escaped}\":*) ;;"));
+                    lines.push(format!("    *) {} ;;", self.path_add_line(dir, placement)));
+                    lines.push("esac".to_string());
+                }
+            }
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => {
+                let ordered: Vec<&PathBuf> = match placement {
+                    Placement::Prepend => dirs.iter().rev().collect(),
+                    Placement::Append => dirs.iter().collect(),
+                };
+                for dir in ordered {
+                    lines.push(format!(
+                        "if (($env:{PATH_VAR} -split '{}') -notcontains {}) {{",
+                        path_separator(),
+                        self.quote(&dir.to_string_lossy())
+                    ));
+                    lines.push(format!("    {}", self.path_add_line(dir, placement)));
+                    lines.push("}".to_string());
+                }
+            }
+        }
+
+        if let Some(script) = env_script {
+            lines.push(self.source_line(script));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Builds a `Command` that launches this shell, inheriting the current environment.
+    ///
+    /// When `interactive` is `true` the shell is started with `-i`, so it reads its
+    /// interactive rcfiles and picks up any PATH changes made to them. PowerShell reads its
+    /// profiles unless told otherwise, so it is started with `-NoProfile` when `interactive`
+    /// is `false` instead.
+    pub fn command(&self, interactive: bool) -> Command {
+        let mut command = Command::new(self.binary_name());
+        match self {
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => {
+                if !interactive {
+                    command.arg("-NoProfile");
+                }
+            }
+            Shell::Fish(_) | Shell::Zsh(_) | Shell::Bash(_) | Shell::POSIX(_) => {
+                if interactive {
+                    command.arg("-i");
+                }
+            }
+        }
+        command
+    }
+
+    /// Returns the value of the environment or shell variable `name` as this shell sees it
+    /// after reading its rcfiles, or `None` if it is unset there.
+    ///
+    /// This spawns an interactive instance of the shell (see [`Shell::command`]), so it
+    /// reflects assignments such as `export GOPATH=...` in the rcfiles. A POSIX `sh` only
+    /// reads the file named by `$ENV` this way. Names that can't be shell variables, such as
+    /// `A-B`, are never set.
+    ///
+    /// # Errors
+    /// - `ShellError::CommandFailed` if the shell isn't installed or can't be started
+    /// - `ShellError::InvalidUtf8Output` if the output isn't valid UTF-8
+    pub fn resolve_var(&self, name: &str) -> Result<Option<String>, ShellError> {
+        self.resolve_var_with_env(name, &SystemEnv)
+    }
+
+    /// Like [`Shell::resolve_var`], but starts the shell with the variables locating its
+    /// rcfiles taken from `env`: `HOME`, `ZDOTDIR`, `XDG_CONFIG_HOME`, `ENV`, `BASH_ENV` and
+    /// `PATH`. Those unset in `env` are removed from the shell's environment, and any other
+    /// variable is inherited from the current process.
+    pub fn resolve_var_with_env(
+        &self,
+        name: &str,
+        env: &dyn Env,
+    ) -> Result<Option<String>, ShellError> {
+        // Separates the value from anything the rcfiles print themselves
+        const MARKER: &str = "__what_the_path_value__:";
+
+        let is_name = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_name {
+            return Ok(None);
+        }
+        if !self.does_exist_with_env(env) {
+            return Err(ShellError::CommandFailed);
+        }
+
+        let script = match self {
+            Shell::Fish(_) => format!("if set -q {name}; printf '{MARKER}%s' \"${name}\"; end"),
+            Shell::Zsh(_) | Shell::Bash(_) | Shell::POSIX(_) => {
+                format!("if [ -n \"${{{name}+x}}\" ]; then printf '{MARKER}%s' \"${name}\"; fi")
+            }
+            Shell::PowerShell(_) | Shell::WindowsPowerShell(_) => format!(
+                "$value = Get-Variable -Name {name} -ValueOnly -ErrorAction Ignore; \
+                 if ($null -eq $value) {{ $value = [Environment]::GetEnvironmentVariable('{name}') }}; \
+                 if ($null -ne $value) {{ [Console]::Out.Write('{MARKER}' + $value) }}"
+            ),
+        };
+        let mut command = self.command(true);
+        for key in [
+            "HOME",
+            "ZDOTDIR",
+            "XDG_CONFIG_HOME",
+            "ENV",
+            "BASH_ENV",
+            "PATH",
+        ] {
+            match env.var_os(key) {
+                Some(value) => command.env(key, value),
+                None => command.env_remove(key),
+            };
+        }
+        let output = command
+            .args(["-c", &script])
+            .stdin(std::process::Stdio::null())
+            .output()
+            .map_err(|_| ShellError::CommandFailed)?;
+        let stdout = String::from_utf8(output.stdout).map_err(|_| ShellError::InvalidUtf8Output)?;
+
+        Ok(stdout
+            .rfind(MARKER)
+            .map(|index| stdout[index + MARKER.len()..].to_string()))
+    }
+
+    /// Launches this shell as a child process.
+    ///
+    /// This is useful for dropping the user into a fresh shell after modifying PATH so the
+    /// changes take effect immediately.
+    ///
+    /// # Errors
+    /// Returns `ShellError::CommandFailed` if the shell binary could not be started.
+    pub fn spawn(&self, interactive: bool) -> Result<Child, ShellError> {
+        self.spawn_with_args(interactive, &[])
+    }
+
+    /// Like [`Shell::spawn`], but passes `args` to the shell after its own flags, e.g.
+    /// `["-c", "exit 0"]` to run a command instead of reading commands from stdin.
+    pub fn spawn_with_args(&self, interactive: bool, args: &[&str]) -> Result<Child, ShellError> {
+        self.command(interactive)
+            .args(args)
+            .spawn()
+            .map_err(|_| ShellError::CommandFailed)
+    }
+}
+
+impl std::str::FromStr for Shell {
+    type Err = ShellError;
+
+    /// Parses a shell's exact binary name (see [`Shell::binary_name`]): `sh`, `zsh`, `bash`,
+    /// `fish`, `pwsh` or `powershell`. Use [`Shell::detect_from_str`] to classify a path or a
+    /// versioned name, and [`Shell::from_name_lenient`] for names typed by users.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Shell::all()
+            .into_iter()
+            .find(|shell| shell.binary_name() == name)
+            .ok_or_else(|| ShellError::DetectionFailed(format!("unknown shell name: {name}")))
+    }
+}
+
+/// Returns the value of PATH in `env`, or an empty value if it isn't set.
+fn path_in(env: &dyn Env) -> OsString {
+    env.var_os("PATH").unwrap_or_default()
+}
+
+/// Returns whether the shell whose binary is `binary` is installed, judging by `$SHELL` and
+/// PATH in `env`.
+///
+/// `$SHELL` counts when its file name is exactly the binary's (plus the platform's executable
+/// suffix, if any), so `pwsh` doesn't make `sh` installed.
+fn shell_installed(binary: &str, env: &dyn Env) -> bool {
+    let file_name = format!("{binary}{}", env::consts::EXE_SUFFIX);
+    let is_binary = |shell: String| {
+        Path::new(&shell)
+            .file_name()
+            .is_some_and(|name| name == binary || name == file_name.as_str())
+    };
+
+    env.var("SHELL").is_some_and(is_binary) || binary_in(binary, &path_in(env))
+}
+
+/// Returns whether an executable file named `binary` exists in one of the directories of
+/// `path`.
+fn binary_in(binary: &str, path: &OsStr) -> bool {
+    let file_name = format!("{binary}{}", env::consts::EXE_SUFFIX);
+    env::split_paths(path).any(|dir| is_executable(&dir.join(&file_name)))
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+/// Escapes `s` for use inside a double-quoted PowerShell string, where the backtick is the
+/// escape character.
+fn powershell_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '"' | '`' | '$') {
+            escaped.push('`');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes `s` for use inside a double-quoted sh-family string.
+fn double_quote_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Quotes `s` as a single fish argument, leaving plain paths unquoted.
+fn fish_quote(s: &str) -> String {
+    let is_plain = !s.is_empty()
+        && s.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | '+' | ':' | ',')
+        });
+
+    if is_plain {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+}
+
+/// Classifies a shell path or name, assuming any unrecognized shell is POSIX-compliant.
+/// See [`recognize_shell_name`].
+///
+/// Returns `ShellError::DetectionFailed` for a known shell this crate doesn't support (see
+/// [`unsupported_shell_name`]), whose rcfiles POSIX syntax would break.
+fn classify_shell_name(shell: &str) -> Result<Shell, ShellError> {
+    if let Some(shell) = recognize_shell_name(shell) {
+        return Ok(shell);
+    }
+    if let Some(name) = unsupported_shell_name(shell) {
+        return Err(unsupported_shell(name, shell));
+    }
+
+    Ok(Shell::POSIX(POSIX))
+}
+
+fn unsupported_shell(name: &str, shell: &str) -> ShellError {
+    ShellError::DetectionFailed(format!("{name} is not a supported shell: {shell}"))
+}
+
+/// Names of common POSIX-compliant shells.
+const POSIX_SHELL_NAMES: [&str; 6] = ["sh", "dash", "ash", "ksh", "mksh", "posh"];
+
+/// The aliases [`Shell::from_name_lenient`] accepts on top of the binary names.
+const SHELL_NAME_ALIASES: [(&str, Shell); 13] = [
+    ("posix", Shell::POSIX(POSIX)),
+    ("dash", Shell::POSIX(POSIX)),
+    ("ash", Shell::POSIX(POSIX)),
+    ("bourneagain", Shell::Bash(Bash)),
+    ("bashshell", Shell::Bash(Bash)),
+    ("zshell", Shell::Zsh(Zsh)),
+    ("zshshell", Shell::Zsh(Zsh)),
+    ("fishshell", Shell::Fish(Fish)),
+    ("powershellcore", Shell::PowerShell(PowerShell)),
+    ("pwshcore", Shell::PowerShell(PowerShell)),
+    ("ps7", Shell::PowerShell(PowerShell)),
+    (
+        "windowspowershell",
+        Shell::WindowsPowerShell(WindowsPowerShell),
+    ),
+    ("ps", Shell::WindowsPowerShell(WindowsPowerShell)),
+];
+
+/// Names of known shells that aren't POSIX-compliant and aren't supported.
+const UNSUPPORTED_SHELL_NAMES: [&str; 7] = ["nu", "xonsh", "elvish", "csh", "tcsh", "ion", "murex"];
+
+/// Returns the name of the known but unsupported shell `shell` refers to, judging by its stem
+/// like [`recognize_shell_name`] does.
+fn unsupported_shell_name(shell: &str) -> Option<&'static str> {
+    let stem = shell_stem(shell);
+    UNSUPPORTED_SHELL_NAMES
+        .into_iter()
+        .find(|name| stem_is(stem, name))
+}
+
+/// Returns the stem of the final path component of `shell`, without the leading dash login
+/// shells are conventionally started with in argv[0] (`-bash`).
+fn shell_stem(shell: &str) -> &str {
+    let stem = Path::new(shell)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(shell);
+    stem.strip_prefix('-').unwrap_or(stem)
+}
+
+/// Returns whether `stem` is `name`, optionally followed by a version suffix.
+fn stem_is(stem: &str, name: &str) -> bool {
+    stem.strip_prefix(name).is_some_and(|rest| {
+        rest.chars()
+            .all(|c| c.is_ascii_digit() || c == '.' || c == '-')
+    })
+}
+
+/// Recognizes a shell path or name by the stem of its final path component.
+///
+/// A stem matches a known shell when it is the shell's name optionally followed by a version
+/// suffix made of digits, dots and dashes (`bash5`, `zsh-5.9`). A leading dash, as used for
+/// login shells, is ignored. Names registered with [`register_shell_alias`] are recognized
+/// the same way, after the built-in ones. Returns `None` for anything that isn't a known shell.
+fn recognize_shell_name(shell: &str) -> Option<Shell> {
+    let stem = shell_stem(shell);
+    let is = |name: &str| stem_is(stem, name);
+
+    if is("zsh") {
+        Some(Shell::Zsh(Zsh))
+    } else if is("bash") {
+        Some(Shell::Bash(Bash))
+    } else if is("fish") {
+        Some(Shell::Fish(Fish))
+    } else if is("pwsh") {
+        Some(Shell::PowerShell(PowerShell))
+    } else if is("powershell") {
+        Some(Shell::WindowsPowerShell(WindowsPowerShell))
+    } else if POSIX_SHELL_NAMES.into_iter().any(is) {
+        Some(Shell::POSIX(POSIX))
+    } else {
+        SHELL_ALIASES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|(name, _)| is(name))
+            .map(|(_, shell)| shell.clone())
+    }
+}
+
+/// Returns the first of Bash, Zsh and Fish that is in `installed` and whose binary name
+/// satisfies `matches`.
+fn preferred_installed(installed: &[Shell], matches: impl Fn(&str) -> bool) -> Option<Shell> {
+    [Shell::Bash(Bash), Shell::Zsh(Zsh), Shell::Fish(Fish)]
+        .into_iter()
+        .find(|shell| matches(shell.binary_name()) && installed.contains(shell))
+}
+
+/// Custom shell names registered with [`register_shell_alias`].
+static SHELL_ALIASES: Mutex<Vec<(String, Shell)>> = Mutex::new(Vec::new());
+
+/// Makes detection recognize the shell named `name` as `shell`, for niche shells that behave
+/// like one of the supported ones.
+///
+/// The alias applies to every detection source until it is forgotten, e.g. after
+/// `register_shell_alias("mysh", Shell::POSIX(POSIX))` a `$SHELL` of `/bin/mysh` is
+/// recognized instead of merely falling back to POSIX. Registering a name again replaces its
+/// mapping, and [`unregister_shell_alias`] or [`clear_shell_aliases`] forget it. Built-in
+/// names such as `bash` can't be overridden.
+pub fn register_shell_alias(name: &str, shell: Shell) {
+    let mut aliases = SHELL_ALIASES.lock().unwrap_or_else(|e| e.into_inner());
+    aliases.retain(|(existing, _)| existing != name);
+    aliases.push((name.to_string(), shell));
+    drop(aliases);
+
+    invalidate_detection_cache();
+}
+
+/// Forgets the alias registered for `name` with [`register_shell_alias`].
+///
+/// Returns whether `name` was registered.
+pub fn unregister_shell_alias(name: &str) -> bool {
+    let mut aliases = SHELL_ALIASES.lock().unwrap_or_else(|e| e.into_inner());
+    let count = aliases.len();
+    aliases.retain(|(existing, _)| existing != name);
+    let removed = aliases.len() != count;
+    drop(aliases);
+
+    if removed {
+        invalidate_detection_cache();
+    }
+    removed
+}
+
+/// Forgets every alias registered with [`register_shell_alias`].
+pub fn clear_shell_aliases() {
+    SHELL_ALIASES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+
+    invalidate_detection_cache();
+}
+
+/// The result of [`Shell::detect_cached`], along with the process it was detected in.
+static DETECTION_CACHE: Mutex<Option<(u32, Shell)>> = Mutex::new(None);
+
+/// Forgets the shell remembered by [`Shell::detect_cached`], so the next call detects it
+/// again.
+///
+/// This is for programs that change `$SHELL` or their other detection inputs while running,
+/// such as test harnesses and REPLs.
+pub fn invalidate_detection_cache() {
+    *DETECTION_CACHE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Where [`Shell::detect_verbose`] found the shell it reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectionSource {
+    /// The parent process is a known shell.
+    ParentProcess,
+    /// The `SHELL` environment variable.
+    ShellVar,
+    /// The current user's login shell in `/etc/passwd`.
+    LoginShell,
+    /// One of the installed shells, picked by priority.
+    InstalledShell,
+    /// The shell installed at `/bin/bash` or `/bin/sh`.
+    SystemShell,
+    /// Nothing could be detected and POSIX was assumed.
+    Fallback,
+    /// The `PSModulePath` environment variable, on Windows.
+    PsModulePath,
+    /// The `WHAT_THE_PATH_SHELL` override, see [`SHELL_OVERRIDE_VAR`].
+    Override,
+}
+
+/// The environment variable forcing the shell [`Shell::detect`] returns, for users and tests
+/// that need a specific shell regardless of `$SHELL`.
+pub const SHELL_OVERRIDE_VAR: &str = "WHAT_THE_PATH_SHELL";
+
+/// Supplies the information [`Shell::detect_with`] bases its decision on.
+///
+/// [`SystemDetection`] reads it from the running system; tests and tools inspecting another
+/// environment can provide their own.
+pub trait DetectionProvider {
+    /// Name or path of the parent process, e.g. `-zsh` or `/usr/bin/fish`.
+    ///
+    /// This wins over every other source but the override, so only the running system's
+    /// provider should report the live parent process.
+    fn parent_process(&self) -> Option<String>;
+
+    /// Value of the `SHELL` environment variable.
+    fn shell_var(&self) -> Option<String>;
+
+    /// Login shell recorded for the current user in `/etc/passwd`.
+    fn login_shell(&self) -> Option<String>;
+
+    /// The shells installed on the system.
+    ///
+    /// Defaults to [`installed_shells`].
+    fn installed_shells(&self) -> Vec<Shell> {
+        installed_shells()
+    }
+
+    /// Whether a file exists at `path`, used to look for the system's shell binaries.
+    ///
+    /// Defaults to checking the running system.
+    fn file_exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    /// Value of the `PSModulePath` environment variable, used to tell the PowerShell editions
+    /// apart on Windows.
+    ///
+    /// Defaults to `None`.
+    fn ps_module_path(&self) -> Option<String> {
+        None
+    }
+
+    /// Value of the [`SHELL_OVERRIDE_VAR`] environment variable.
+    ///
+    /// Defaults to `None`.
+    fn shell_override(&self) -> Option<String> {
+        None
+    }
+}
+
+/// A [`DetectionProvider`] reading the running system.
+///
+/// The parent process is only inspected on Linux, through `/proc`. The current user is
+/// taken from `$USER`, falling back to `$LOGNAME`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemDetection;
+
+/// Like [`SystemDetection`], but reads the environment variables (`$SHELL`, `$USER`,
+/// `$LOGNAME`, `PSModulePath`, [`SHELL_OVERRIDE_VAR`] and PATH) from an [`Env`].
+///
+/// The env describes another environment than the running process's, so the live parent
+/// process isn't consulted: detection only depends on `env`, `/etc/passwd` and the files on
+/// disk.
+pub struct EnvDetection<'a> {
+    env: &'a dyn Env,
+}
+
+impl<'a> EnvDetection<'a> {
+    pub fn new(env: &'a dyn Env) -> Self {
+        EnvDetection { env }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn parent_process_name() -> Option<String> {
+    let ppid = std::os::unix::process::parent_id();
+    let comm = std::fs::read_to_string(format!("/proc/{ppid}/comm")).ok()?;
+    Some(comm.trim().to_string()).filter(|comm| !comm.is_empty())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn parent_process_name() -> Option<String> {
+    None
+}
+
+impl DetectionProvider for SystemDetection {
+    fn parent_process(&self) -> Option<String> {
+        parent_process_name()
+    }
+
+    fn shell_var(&self) -> Option<String> {
+        EnvDetection::new(&SystemEnv).shell_var()
+    }
+
+    fn login_shell(&self) -> Option<String> {
+        EnvDetection::new(&SystemEnv).login_shell()
+    }
+
+    fn ps_module_path(&self) -> Option<String> {
+        EnvDetection::new(&SystemEnv).ps_module_path()
+    }
+
+    fn shell_override(&self) -> Option<String> {
+        EnvDetection::new(&SystemEnv).shell_override()
+    }
+}
+
+impl DetectionProvider for EnvDetection<'_> {
+    fn parent_process(&self) -> Option<String> {
+        None
+    }
+
+    fn shell_var(&self) -> Option<String> {
+        self.env.var("SHELL").filter(|shell| !shell.is_empty())
+    }
+
+    fn login_shell(&self) -> Option<String> {
+        let user = current_user(self.env)?;
+        let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+        parse_passwd_shell(&passwd, &user)
+    }
+
+    fn installed_shells(&self) -> Vec<Shell> {
+        installed_shells_with_env(self.env)
+    }
+
+    fn ps_module_path(&self) -> Option<String> {
+        self.env.var("PSModulePath")
+    }
+
+    fn shell_override(&self) -> Option<String> {
+        self.env.var(SHELL_OVERRIDE_VAR)
+    }
+}
+
+/// Picks the PowerShell edition of a Windows session without `$SHELL`, see
+/// [`Shell::detect_verbose`].
+fn detect_windows_powershell(provider: &dyn DetectionProvider) -> Option<(Shell, DetectionSource)> {
+    if let Some(shell) = provider
+        .ps_module_path()
+        .as_deref()
+        .and_then(powershell_for_module_path)
+    {
+        return Some((shell, DetectionSource::PsModulePath));
+    }
+
+    let installed = provider.installed_shells();
+    [
+        Shell::PowerShell(PowerShell),
+        Shell::WindowsPowerShell(WindowsPowerShell),
+    ]
+    .into_iter()
+    .find(|shell| installed.contains(shell))
+    .map(|shell| (shell, DetectionSource::InstalledShell))
+}
+
+/// Returns the current user's name from `$USER`, falling back to `$LOGNAME`.
+fn current_user(env: &dyn Env) -> Option<String> {
+    env.var("USER").or_else(|| env.var("LOGNAME"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct POSIX;
+
+impl POSIX {
+    pub fn does_exist(&self) -> bool {
+        true
+    }
+    pub fn does_exist_with_env(&self, _env: &dyn Env) -> bool {
+        true
+    }
+    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.get_rcfiles_with_env(&SystemEnv)
+    }
+    pub fn get_rcfiles_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let dir = get_home_dir_with_env(env)?;
+        Ok(vec![dir.join(".profile")])
+    }
+    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
+        vec![base_dir.as_ref().join(".profile")]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zsh;
+
+impl Zsh {
+    pub fn does_exist(&self) -> bool {
+        self.does_exist_with_env(&SystemEnv)
+    }
+
+    pub fn does_exist_with_env(&self, env: &dyn Env) -> bool {
+        shell_installed("zsh", env)
+    }
+
+    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
+        // Ask zsh for ZDOTDIR, since it may be set by zsh's own startup files
+        let zdotdir = std::process::Command::new("zsh")
+            .args(["-c", "echo -n $ZDOTDIR"])
+            .output()
+            .ok()
+            .and_then(|output| String::from_utf8(output.stdout).ok());
+
+        Self::zshenv_files(zdotdir, &SystemEnv)
+    }
+
+    /// Like [`Zsh::get_rcfiles`], but finds `ZDOTDIR` with [`Zsh::zdotdir_offline`] instead
+    /// of spawning zsh.
+    pub fn get_rcfiles_offline(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.get_rcfiles_with_env(&SystemEnv)
+    }
+
+    /// Like [`Zsh::get_rcfiles_offline`], but reads `ZDOTDIR` and `HOME` from `env`.
+    pub fn get_rcfiles_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let zdotdir = self
+            .zdotdir_offline_with_env(env)
+            .map(|zdotdir| zdotdir.to_string_lossy().into_owned());
+        Self::zshenv_files(zdotdir, env)
+    }
+
+    /// Returns `ZDOTDIR` without spawning zsh: from the environment if set, otherwise from
+    /// an assignment in `~/.zshenv`, where it is commonly set.
+    ///
+    /// Only simple assignments such as `export ZDOTDIR="$HOME/.config/zsh"` are understood:
+    /// the value may start with `~`, `$HOME` or `${HOME}` but must otherwise be literal.
+    /// Conditional or computed assignments are missed.
+    pub fn zdotdir_offline(&self) -> Option<PathBuf> {
+        self.zdotdir_offline_with_env(&SystemEnv)
+    }
+
+    /// Like [`Zsh::zdotdir_offline`], but reads `ZDOTDIR` and `HOME` from `env`.
+    pub fn zdotdir_offline_with_env(&self, env: &dyn Env) -> Option<PathBuf> {
+        if let Some(zdotdir) = env.var_os("ZDOTDIR").filter(|zdotdir| !zdotdir.is_empty()) {
+            return Some(PathBuf::from(zdotdir));
+        }
+
+        let home = get_home_dir_with_env(env).ok()?;
+        let zshenv = std::fs::read_to_string(home.join(".zshenv")).ok()?;
+        parse_zdotdir_assignment(&zshenv, &home)
+    }
+
+    /// Returns the directory zsh reads `.zshenv` from: `$ZDOTDIR` as set in the environment,
+    /// otherwise `$HOME`.
+    fn startup_dir(env: &dyn Env) -> Result<PathBuf, ShellError> {
+        match env.var_os("ZDOTDIR") {
+            Some(zdotdir) if !zdotdir.is_empty() => Ok(PathBuf::from(zdotdir)),
+            _ => get_home_dir_with_env(env),
+        }
+    }
+
+    fn zshenv_files(zdotdir: Option<String>, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let mut rc_files = Vec::new();
+
+        // Try ZDOTDIR
+        if let Some(zdotdir) = zdotdir.filter(|zdotdir| !zdotdir.trim().is_empty()) {
+            let path = PathBuf::from(zdotdir.trim()).join(".zshenv");
+            if path.exists() {
+                rc_files.push(path);
+            }
+        }
+
+        // Try HOME
+        if let Some(home) = env.var_os("HOME") {
+            let path = PathBuf::from(home).join(".zshenv");
+            if path.exists() {
+                rc_files.push(path);
+            }
+        }
+
+        if rc_files.is_empty() {
+            Err(ShellError::EmptyHomeAndZdotdir)
+        } else {
+            Ok(rc_files)
+        }
+    }
+
+    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
+        vec![base_dir.as_ref().join(".zshenv")]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bash;
+
+impl Bash {
+    pub fn does_exist(&self) -> bool {
+        self.does_exist_with_env(&SystemEnv)
+    }
+
+    pub fn does_exist_with_env(&self, env: &dyn Env) -> bool {
+        shell_installed("bash", env)
+    }
+
+    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.get_rcfiles_with_env(&SystemEnv)
+    }
+
+    pub fn get_rcfiles_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let dir = get_home_dir_with_env(env)?;
+        let rcfiles = [".bash_profile", ".bash_login", ".bashrc"]
+            .iter()
+            .map(|rc| dir.join(rc))
+            .collect();
+        Ok(rcfiles)
+    }
+
+    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
+        [".bash_profile", ".bash_login", ".bashrc"]
+            .iter()
+            .map(|rc| base_dir.as_ref().join(rc))
+            .collect()
+    }
+
+    /// Returns the chain of profile files a login shell reads, in the order they are
+    /// sourced.
+    ///
+    /// The chain starts at the file bash reads itself (see [`Shell::init_order`]) and
+    /// follows `source` and `.` commands into `~/.bash_profile`, `~/.bash_login`,
+    /// `~/.profile` and `~/.bashrc`. Distributions commonly have these source each other, so
+    /// a PATH edit only needs to go into one of them: the first file of the chain is read by
+    /// every login shell. Only files that exist are included.
+    pub fn profile_chain(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.profile_chain_with_env(&SystemEnv)
+    }
+
+    /// Like [`Bash::profile_chain`], but reads the environment variables from `env`.
+    pub fn profile_chain_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let home = get_home_dir_with_env(env)?;
+        let candidates =
+            [".bash_profile", ".bash_login", ".profile", ".bashrc"].map(|rc| home.join(rc));
+
+        let mut chain = Vec::new();
+        let mut pending: Vec<PathBuf> = Shell::Bash(Bash).init_order_with_env(env)?;
+        while let Some(rcfile) = pending.pop() {
+            if chain.contains(&rcfile) {
+                continue;
+            }
+            let Ok(content) = std::fs::read_to_string(&rcfile) else {
+                continue;
+            };
+
+            let sourced = sourced_files(&content, &home);
+            chain.push(rcfile);
+            // Visit the sourced files depth-first, in the order they are sourced
+            pending.extend(
+                sourced
+                    .into_iter()
+                    .filter(|sourced| candidates.contains(sourced))
+                    .rev(),
+            );
+        }
+
+        Ok(chain)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fish;
+
+impl Fish {
+    pub fn does_exist(&self) -> bool {
+        self.does_exist_with_env(&SystemEnv)
+    }
+
+    pub fn does_exist_with_env(&self, env: &dyn Env) -> bool {
+        shell_installed("fish", env)
+    }
+
+    /// Returns the locations Fish reads its configuration from.
+    ///
+    /// The list holds the `conf.d` directory, whose `.fish` files are all sourced at
+    /// startup, followed by the `config.fish` file, which is read after them. `config.fish`
+    /// can be edited like the other shells' rcfiles, while `conf.d` is a directory to drop
+    /// whole files into.
+    ///
+    /// Both are located in `fish` under the user's config directory, usually
+    /// `$XDG_CONFIG_HOME` or `~/.config`. The list is empty if that can't be determined.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use what_the_path::shell::Fish;
+    ///
+    /// let rcfiles = Fish.get_rcfiles().unwrap();
+    /// // rcfiles[0] is the ~/.config/fish/conf.d directory,
+    /// // rcfiles[1] is ~/.config/fish/config.fish
+    /// ```
+    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.get_rcfiles_with_env(&SystemEnv)
+    }
+
+    /// Like [`Fish::get_rcfiles`], but reads the environment variables from `env`.
+    pub fn get_rcfiles_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let mut paths = vec![];
+
+        if let Some(path) = xdg_config_dir_with_env(env) {
+            paths.push(path.join("fish/conf.d"));
+            paths.push(path.join("fish/config.fish"));
+        }
+
+        Ok(paths)
+    }
+
+    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
+        vec![
+            base_dir.as_ref().join(".config/fish/conf.d"),
+            base_dir.as_ref().join(".config/fish/config.fish"),
+        ]
+    }
+
+    /// Returns the path of Fish's main `config.fish` file.
+    ///
+    /// This is the file among [`Fish::get_rcfiles`] that takes single lines, as opposed to
+    /// the `conf.d` directory. The file may not exist yet, see [`append_to_rcfile_create`].
+    pub fn config_fish_path(&self) -> Result<PathBuf, ShellError> {
+        self.config_fish_path_with_env(&SystemEnv)
+    }
+
+    /// Like [`Fish::config_fish_path`], but reads the environment variables from `env`.
+    pub fn config_fish_path_with_env(&self, env: &dyn Env) -> Result<PathBuf, ShellError> {
+        Ok(Shell::Fish(Fish)
+            .config_home_with_env(env)?
+            .join("config.fish"))
+    }
+
+    /// Writes `content` to the drop-in file called `stem` in Fish's `conf.d` directory
+    /// (`<config>/fish/conf.d/<stem>.fish`, see [`Shell::dropin_filename`]), and returns its
+    /// path.
+    ///
+    /// Fish sources every such file at startup, before `config.fish`, so a tool can keep its
+    /// PATH setup (e.g. from [`Shell::install_snippet`]) in a file of its own instead of
+    /// editing the user's `config.fish`. The directory is created if needed and an existing
+    /// file is replaced atomically.
+    pub fn write_dropin(&self, stem: &str, content: &str) -> Result<PathBuf, ShellError> {
+        self.write_dropin_with_env(stem, content, &SystemEnv)
+    }
+
+    /// Like [`Fish::write_dropin`], but reads the environment variables from `env`.
+    pub fn write_dropin_with_env(
+        &self,
+        stem: &str,
+        content: &str,
+        env: &dyn Env,
+    ) -> Result<PathBuf, ShellError> {
+        let fish = Shell::Fish(Fish);
+        let file_name = fish
+            .dropin_filename(stem)
+            .filter(|_| !stem.is_empty() && !stem.contains(['/', '\\']))
+            .ok_or_else(|| ShellError::InvalidPathEntry(PathBuf::from(stem)))?;
+        let conf_d = fish.config_home_with_env(env)?.join("conf.d");
+        std::fs::create_dir_all(&conf_d)?;
+
+        let dropin = conf_d.join(file_name);
+        let mut content = content.to_string();
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        write_atomic(&dropin, content.as_bytes())?;
+        trace_event!(dropin = %dropin.display(), "wrote fish drop-in");
+
+        Ok(dropin)
+    }
+
+    /// Returns the directories in the `fish_user_paths` universal variable.
+    ///
+    /// Fish prepends these to PATH in every session, so they are a way to persist PATH
+    /// changes without editing any config file. The variable is read with
+    /// [`fish_universal_var`], so fish doesn't need to be installed; an unset variable has
+    /// no directories.
+    pub fn user_paths(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.user_paths_with_env(&SystemEnv)
+    }
+
+    /// Like [`Fish::user_paths`], but looks for fish's universal variables in the
+    /// `HOME` and `XDG_CONFIG_HOME` of `env`.
+    pub fn user_paths_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        let paths = fish_universal_var_with_env("fish_user_paths", env)?.unwrap_or_default();
+        Ok(paths.into_iter().map(PathBuf::from).collect())
+    }
+
+    /// Adds `dir` to the `fish_user_paths` universal variable, unless it's already there.
+    pub fn add_user_path(&self, dir: &Path) -> Result<(), ShellError> {
+        self.add_user_path_with_env(dir, &SystemEnv)
+    }
+
+    /// Like [`Fish::add_user_path`], but runs fish with the `HOME` and `XDG_CONFIG_HOME` of
+    /// `env`.
+    pub fn add_user_path_with_env(&self, dir: &Path, env: &dyn Env) -> Result<(), ShellError> {
+        let dir = fish_quote(&dir.to_string_lossy());
+        Self::run(
+            &format!("contains -- {dir} $fish_user_paths; or set -Ua fish_user_paths {dir}"),
+            env,
+        )?;
+        Ok(())
+    }
+
+    /// Removes `dir` from the `fish_user_paths` universal variable.
+    pub fn remove_user_path(&self, dir: &Path) -> Result<(), ShellError> {
+        self.remove_user_path_with_env(dir, &SystemEnv)
+    }
+
+    /// Like [`Fish::remove_user_path`], but runs fish with the `HOME` and `XDG_CONFIG_HOME` of
+    /// `env`.
+    pub fn remove_user_path_with_env(&self, dir: &Path, env: &dyn Env) -> Result<(), ShellError> {
+        let dir = fish_quote(&dir.to_string_lossy());
+        Self::run(
+            &format!(
+                "while set -l index (contains -i -- {dir} $fish_user_paths); set -Ue fish_user_paths[$index]; end"
+            ),
+            env,
+        )?;
+        Ok(())
+    }
+
+    /// Runs `script` with `fish -c`, returning its standard output.
+    ///
+    /// `HOME` and `XDG_CONFIG_HOME` are taken from `env`, so fish reads and writes the
+    /// universal variables of the configuration `env` describes.
+    fn run(script: &str, env: &dyn Env) -> Result<String, ShellError> {
+        let mut command = Command::new("fish");
+        command.args(["-c", script]);
+        for var in ["HOME", "XDG_CONFIG_HOME"] {
+            match env.var_os(var) {
+                Some(value) => command.env(var, value),
+                None => command.env_remove(var),
+            };
+        }
+
+        let output = command.output().map_err(|_| ShellError::CommandFailed)?;
+        if !output.status.success() {
+            return Err(ShellError::CommandFailed);
+        }
+
+        String::from_utf8(output.stdout).map_err(|_| ShellError::InvalidUtf8Output)
+    }
+}
+
+/// The file name of PowerShell's current-user, current-host profile, `$PROFILE`.
+const POWERSHELL_PROFILE: &str = "Microsoft.PowerShell_profile.ps1";
+
+/// The file name of PowerShell's current-user profile for all hosts, read before
+/// [`POWERSHELL_PROFILE`].
+const POWERSHELL_ALL_HOSTS_PROFILE: &str = "profile.ps1";
+
+/// Returns the profile files read from `dir`, in the order PowerShell reads them.
+fn powershell_profiles(dir: &Path) -> Vec<PathBuf> {
+    [POWERSHELL_ALL_HOSTS_PROFILE, POWERSHELL_PROFILE]
+        .iter()
+        .map(|profile| dir.join(profile))
+        .collect()
+}
+
+/// Returns the first file under `xdg_config` that the dotfile `rcfile` in `$HOME` sources.
+fn xdg_sourced_file(rcfile: &str, xdg_config: &Path, env: &dyn Env) -> Option<PathBuf> {
+    let home = get_home_dir_with_env(env).ok()?;
+    let (_, content) = read_rcfile(&home.join(rcfile)).ok()?;
+    sourced_files(&content, &home)
+        .into_iter()
+        .find(|sourced| sourced.starts_with(xdg_config))
+}
+
+/// Returns the user's Documents folder, where both PowerShell editions keep their profiles on
+/// Windows: `Documents` in `%USERPROFILE%`, or in `$HOME` if that isn't set.
+///
+/// A Documents folder moved elsewhere (e.g. into OneDrive) isn't followed.
+fn documents_dir(env: &dyn Env) -> Result<PathBuf, ShellError> {
+    let home = match env.var_os("USERPROFILE").filter(|home| !home.is_empty()) {
+        Some(home) => PathBuf::from(home),
+        None => get_home_dir_with_env(env)?,
+    };
+    Ok(home.join("Documents"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerShell;
+
+impl PowerShell {
+    pub fn does_exist(&self) -> bool {
+        self.does_exist_with_env(&SystemEnv)
+    }
+
+    pub fn does_exist_with_env(&self, env: &dyn Env) -> bool {
+        shell_installed("pwsh", env)
+    }
+
+    /// Returns the directory PowerShell reads the current user's profiles from:
+    /// `Documents\PowerShell` on Windows (see [`Shell::config_home`]), and `powershell` in
+    /// `$XDG_CONFIG_HOME` (or `~/.config`) elsewhere, macOS included.
+    pub fn profile_dir(&self) -> Result<PathBuf, ShellError> {
+        self.profile_dir_with_env(&SystemEnv)
+    }
+
+    /// Like [`PowerShell::profile_dir`], but reads the environment variables from `env`.
+    pub fn profile_dir_with_env(&self, env: &dyn Env) -> Result<PathBuf, ShellError> {
+        if cfg!(windows) {
+            return Ok(documents_dir(env)?.join("PowerShell"));
+        }
+
+        let config = xdg_config_dir_with_env(env).ok_or(ShellError::NoHomeDir)?;
+        Ok(config.join("powershell"))
+    }
+
+    /// Returns the current user's profiles, `profile.ps1` (read by every PowerShell host)
+    /// followed by `Microsoft.PowerShell_profile.ps1` (`$PROFILE`, read by the console host).
+    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.get_rcfiles_with_env(&SystemEnv)
+    }
+
+    /// Like [`PowerShell::get_rcfiles`], but reads the environment variables from `env`.
+    pub fn get_rcfiles_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        Ok(powershell_profiles(&self.profile_dir_with_env(env)?))
+    }
+
+    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
+        let dir = if cfg!(windows) {
+            "Documents/PowerShell"
+        } else {
+            ".config/powershell"
+        };
+        powershell_profiles(&base_dir.as_ref().join(dir))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowsPowerShell;
+
+impl WindowsPowerShell {
+    pub fn does_exist(&self) -> bool {
+        self.does_exist_with_env(&SystemEnv)
+    }
+
+    pub fn does_exist_with_env(&self, env: &dyn Env) -> bool {
+        shell_installed("powershell", env)
+    }
+
+    /// Returns the directory Windows PowerShell reads the current user's profiles from,
+    /// `Documents\WindowsPowerShell`.
+    pub fn profile_dir(&self) -> Result<PathBuf, ShellError> {
+        self.profile_dir_with_env(&SystemEnv)
+    }
+
+    /// Like [`WindowsPowerShell::profile_dir`], but reads the environment variables from
+    /// `env`.
+    pub fn profile_dir_with_env(&self, env: &dyn Env) -> Result<PathBuf, ShellError> {
+        Ok(documents_dir(env)?.join("WindowsPowerShell"))
+    }
+
+    /// Returns the current user's profiles, like [`PowerShell::get_rcfiles`] but in
+    /// [`WindowsPowerShell::profile_dir`].
+    pub fn get_rcfiles(&self) -> Result<Vec<PathBuf>, ShellError> {
+        self.get_rcfiles_with_env(&SystemEnv)
+    }
+
+    /// Like [`WindowsPowerShell::get_rcfiles`], but reads the environment variables from
+    /// `env`.
+    pub fn get_rcfiles_with_env(&self, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+        Ok(powershell_profiles(&self.profile_dir_with_env(env)?))
+    }
+
+    pub fn get_rcfiles_from_base(base_dir: impl AsRef<Path>) -> Vec<PathBuf> {
+        powershell_profiles(&base_dir.as_ref().join("Documents/WindowsPowerShell"))
+    }
+}
+
+/// Returns the PowerShell edition whose modules `ps_module_path`, a value of the
+/// `PSModulePath` environment variable, lists first.
+///
+/// Each edition puts its own module directories in front of `PSModulePath` for the processes
+/// it starts, e.g. `C:\Program Files\PowerShell\7\Modules` for PowerShell 7 and
+/// `Documents\WindowsPowerShell\Modules` for Windows PowerShell, which tells which edition a
+/// process was started from. Note that Windows sets a default `PSModulePath` listing
+/// Windows PowerShell's directories for every process. Returns `None` if no entry belongs to
+/// either edition.
+pub fn powershell_for_module_path(ps_module_path: &str) -> Option<Shell> {
+    ps_module_path
+        .split([';', ':'])
+        .map(|entry| entry.replace('/', "\\").to_ascii_lowercase())
+        .find_map(|entry| {
+            if entry.contains("\\windowspowershell\\") || entry.ends_with("\\windowspowershell") {
+                Some(Shell::WindowsPowerShell(WindowsPowerShell))
+            } else if entry.contains("\\powershell\\") || entry.ends_with("\\powershell") {
+                Some(Shell::PowerShell(PowerShell))
+            } else {
+                None
+            }
+        })
+}
+
+/// Returns the value of the fish universal variable `name`, as a list of its elements.
+///
+/// Universal variables (such as `fish_user_paths`) are read straight from the
+/// `fish_variables` file in fish's config directory, so fish doesn't need to be installed.
+/// Returns `Ok(None)` if the file or the variable doesn't exist.
+///
+/// # Errors
+/// - `ShellError::CorruptFishVariables` if the file isn't in the format fish writes
+/// - `ShellError::RcFileError` if the file exists but can't be read
+pub fn fish_universal_var(name: &str) -> Result<Option<Vec<String>>, ShellError> {
+    fish_universal_var_with_env(name, &SystemEnv)
+}
+
+/// Like [`fish_universal_var`], but reads the environment variables from `env`.
+pub fn fish_universal_var_with_env(
+    name: &str,
+    env: &dyn Env,
+) -> Result<Option<Vec<String>>, ShellError> {
+    let file = Shell::Fish(Fish)
+        .config_home_with_env(env)?
+        .join("fish_variables");
+    fish_universal_var_from(&file, name)
+}
+
+/// Like [`fish_universal_var`], but reads the `fish_variables` file at `file`.
+pub fn fish_universal_var_from(file: &Path, name: &str) -> Result<Option<Vec<String>>, ShellError> {
+    let content = match std::fs::read(file) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let corrupt = || ShellError::CorruptFishVariables(file.to_path_buf());
+    let content = String::from_utf8(content).map_err(|_| corrupt())?;
+
+    let mut versioned = false;
+    let mut value = None;
+    for line in content.lines() {
+        if let Some(comment) = line.strip_prefix('#') {
+            match comment.trim().strip_prefix("VERSION:") {
+                Some(version) if version.trim() == "3.0" => versioned = true,
+                Some(_) => return Err(corrupt()),
+                None => {}
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Lines look like `SETUVAR [--export] [--path] name:value`
+        let mut rest = line.strip_prefix("SETUVAR ").ok_or_else(corrupt)?;
+        while let Some(flag) = rest.strip_prefix("--") {
+            let (_, after) = flag.split_once(' ').ok_or_else(corrupt)?;
+            rest = after;
+        }
+        let (var, encoded) = rest.split_once(':').ok_or_else(corrupt)?;
+        if var.is_empty() || var.contains(char::is_whitespace) {
+            return Err(corrupt());
+        }
+        if var == name {
+            value = Some(decode_fish_var(encoded).ok_or_else(corrupt)?);
+        }
+    }
+
+    if !versioned {
+        return Err(corrupt());
+    }
+    Ok(value)
+}
+
+/// Decodes a `fish_variables` value into its elements.
+///
+/// Fish escapes anything but printable ASCII as `\xHH`, separates elements with `\x1e` and
+/// writes an empty list as a single `\x1d`. Returns `None` on a malformed escape sequence.
+fn decode_fish_var(encoded: &str) -> Option<Vec<String>> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut chars = encoded.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next()? {
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                if hex.len() != 2 {
+                    return None;
+                }
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+            }
+            'n' => bytes.push(b'\n'),
+            't' => bytes.push(b'\t'),
+            'r' => bytes.push(b'\r'),
+            escaped @ ('\\' | '\'' | '"' | ' ' | '$' | '*' | '?' | '~' | '#' | '(' | ')' | '{'
+            | '}' | '[' | ']' | '<' | '>' | '&' | '|' | ';' | '%') => bytes.push(escaped as u8),
+            _ => return None,
+        }
+    }
+
+    if bytes == [0x1d] {
+        return Some(vec![]);
+    }
+    bytes
+        .split(|&byte| byte == 0x1e)
+        .map(|element| String::from_utf8(element.to_vec()).ok())
+        .collect()
+}
+
+/// Returns the shells from [`Shell::all`] that are installed on this system.
+pub fn installed_shells() -> Vec<Shell> {
+    installed_shells_with_env(&SystemEnv)
+}
+
+/// Like [`installed_shells`], but judges by `$SHELL` and PATH in `env`.
+pub fn installed_shells_with_env(env: &dyn Env) -> Vec<Shell> {
+    Shell::all()
+        .into_iter()
+        .filter(|shell| shell.does_exist_with_env(env))
+        .collect()
+}
+
+/// Returns the shell an rcfile belongs to, judging by its file name.
+///
+/// This is the inverse of [`Shell::get_rcfiles`] and [`Shell::init_order`]: `.zshrc` and
+/// the other zsh startup files map to Zsh, `.bashrc`, `.bash_profile` and friends to Bash,
+/// `config.fish` and any other `.fish` file to Fish, and `.profile` to POSIX. A `.ps1` file
+/// maps to Windows PowerShell when it is in a `WindowsPowerShell` directory and to PowerShell
+/// otherwise. Returns `None` for any other file.
+pub fn shell_for_rcfile(path: &Path) -> Option<Shell> {
+    let name = path.file_name()?.to_str()?;
+    match name {
+        ".zshenv" | ".zprofile" | ".zshrc" | ".zlogin" | ".zlogout" => Some(Shell::Zsh(Zsh)),
+        ".bashrc" | ".bash_profile" | ".bash_login" | ".bash_logout" => Some(Shell::Bash(Bash)),
+        ".profile" => Some(Shell::POSIX(POSIX)),
+        _ if path.extension().is_some_and(|ext| ext == "fish") => Some(Shell::Fish(Fish)),
+        _ if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("ps1")) =>
+        {
+            let in_windows_powershell = path
+                .parent()
+                .and_then(Path::file_name)
+                .is_some_and(|dir| dir.eq_ignore_ascii_case("WindowsPowerShell"));
+            if in_windows_powershell {
+                Some(Shell::WindowsPowerShell(WindowsPowerShell))
+            } else {
+                Some(Shell::PowerShell(PowerShell))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Guesses the user's shell when [`Shell::detect`] has nothing to go on, e.g. in a container
+/// without `$SHELL` or a parent shell.
+///
+/// See [`most_likely_shell_from`]; this uses the system's `/etc/shells` and the live PATH.
+pub fn most_likely_shell() -> Shell {
+    let etc_shells = std::fs::read_to_string("/etc/shells").unwrap_or_default();
+    most_likely_shell_from(&etc_shells, &path_in(&SystemEnv))
+}
+
+/// Picks the most likely shell among those listed in `etc_shells` (the contents of an
+/// `/etc/shells` file) whose binary is found in `path`.
+///
+/// Bash is preferred, being the most common default, followed by Zsh and Fish. When
+/// `etc_shells` lists none of them (or is empty, as for a missing file), any of them on
+/// `path` qualifies. POSIX is returned if no candidate is installed.
+pub fn most_likely_shell_from(etc_shells: &str, path: &OsStr) -> Shell {
+    let listed: Vec<Shell> = etc_shells
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(recognize_shell_name)
+        .collect();
+    let is_listed = |shell: &Shell| listed.is_empty() || listed.contains(shell);
+
+    [Shell::Bash(Bash), Shell::Zsh(Zsh), Shell::Fish(Fish)]
+        .into_iter()
+        .find(|shell| is_listed(shell) && shell.binary_exists_in(path))
+        .unwrap_or_else(Shell::posix_default)
+}
+
+/// Returns every existing rcfile of every installed shell, tagged with its shell.
+///
+/// This is handy for reporting where a tool has added itself. Shells whose rcfiles can't
+/// be determined are skipped.
+pub fn all_rcfiles() -> Vec<(Shell, PathBuf)> {
+    installed_shells()
+        .into_iter()
+        .flat_map(|shell| {
+            let rcfiles = shell.get_rcfiles().unwrap_or_default();
+            tag_existing(shell, rcfiles)
+        })
+        .collect()
+}
+
+/// Like [`all_rcfiles`], but for the given `shells` with rcfiles looked up under `base_dir`.
+pub fn all_rcfiles_from_base(
+    shells: &[Shell],
+    base_dir: impl AsRef<Path>,
+) -> Vec<(Shell, PathBuf)> {
+    shells
+        .iter()
+        .flat_map(|shell| {
+            let rcfiles = shell.get_rcfiles_from_base(base_dir.as_ref());
+            tag_existing(shell.clone(), rcfiles)
+        })
+        .collect()
+}
+
+fn tag_existing(shell: Shell, rcfiles: Vec<PathBuf>) -> Vec<(Shell, PathBuf)> {
+    rcfiles
+        .into_iter()
+        .filter(|rcfile| rcfile.exists())
+        .map(|rcfile| (shell.clone(), rcfile))
+        .collect()
+}
+
+/// Returns the rcfile to edit for persisting a PATH change in the current shell.
+///
+/// This combines [`Shell::detect`] with [`Shell::rc_file_for_path_edit`], so edits only
+/// go to the file the user's shell actually reads.
+pub fn current_shell_primary_rcfile() -> Result<PathBuf, ShellError> {
+    Shell::detect()?.rc_file_for_path_edit()
+}
+
+/// Like [`current_shell_primary_rcfile`], but detects the shell with an [`EnvDetection`] and
+/// locates its rcfile from the variables in `env`.
+pub fn current_shell_primary_rcfile_with_env(env: &dyn Env) -> Result<PathBuf, ShellError> {
+    let (shell, _) = Shell::detect_with(&EnvDetection::new(env))?;
+    shell.rc_file_for_path_edit_with_env(env)
+}
+
+/// Returns the current user's home directory as given by the `HOME` environment variable.
+///
+/// When `HOME` isn't set, the home directory is looked up in the user database instead (see
+/// [`dirs::home_dir`]), which also covers users from NSS or LDAP that have no `/etc/passwd`
+/// entry.
+///
+/// # Errors
+/// - `ShellError::NoHomeDir` if `HOME` is not set and the user has no home directory
+/// - `ShellError::EmptyHomeEnvVar` if `HOME` is set but empty
+pub fn get_home_dir() -> Result<PathBuf, ShellError> {
+    get_home_dir_with_env(&SystemEnv)
+}
+
+/// Like [`get_home_dir`], but reads `HOME` from `env`, falling back to
+/// [`Env::fallback_home_dir`].
+pub fn get_home_dir_with_env(env: &dyn Env) -> Result<PathBuf, ShellError> {
+    match env.var_os("HOME") {
+        Some(home) if home.is_empty() => Err(ShellError::EmptyHomeEnvVar),
+        Some(home) => Ok(PathBuf::from(home)),
+        None => env.fallback_home_dir().ok_or(ShellError::NoHomeDir),
+    }
+}
+
+/// Like [`get_home_dir`], but also checks `$HOME` against the current user's home directory
+/// in `/etc/passwd`.
+///
+/// The two disagree e.g. under `sudo` with a preserved environment, where editing the
+/// rcfiles in `$HOME` would change another user's dotfiles. The check is skipped if the
+/// user (taken from `$USER`, falling back to `$LOGNAME`) has no passwd entry.
+///
+/// # Errors
+/// Returns `ShellError::MultipleHomesConflict` if the directories differ, and otherwise
+/// fails like [`get_home_dir`].
+pub fn get_home_dir_strict() -> Result<PathBuf, ShellError> {
+    get_home_dir_strict_with_env(&SystemEnv)
+}
+
+/// Like [`get_home_dir_strict`], but reads `HOME`, `USER` and `LOGNAME` from `env`.
+///
+/// The home directory is still checked against the running system's `/etc/passwd`.
+pub fn get_home_dir_strict_with_env(env: &dyn Env) -> Result<PathBuf, ShellError> {
+    let home = get_home_dir_with_env(env)?;
+    let passwd_home = current_user(env).and_then(|user| home_dir_for_user(&user));
+
+    match passwd_home {
+        Some(passwd) if !same_dir(&home, &passwd) => {
+            Err(ShellError::MultipleHomesConflict { env: home, passwd })
+        }
+        _ => Ok(home),
+    }
+}
+
+/// Returns whether `a` and `b` are the same directory, resolving symlinks when possible.
+fn same_dir(a: &Path, b: &Path) -> bool {
+    let canonical =
+        |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    canonical(a) == canonical(b)
+}
+
+/// Returns the home directory of `username` as recorded in `/etc/passwd`, falling back to
+/// `getpwnam_r` on Unix-like systems for users from NSS or LDAP.
+///
+/// Unlike [`get_home_dir`] this does not consult `$HOME`, so it can be used by admin tools
+/// setting up PATH for other users. Returns `None` if the user isn't found.
+pub fn home_dir_for_user(username: &str) -> Option<PathBuf> {
+    std::fs::read_to_string("/etc/passwd")
+        .ok()
+        .and_then(|passwd| parse_passwd_home(&passwd, username))
+        .or_else(|| getpwnam_home(username))
+}
+
+/// Looks the home directory of `username` up in the user database.
+#[cfg(unix)]
+fn getpwnam_home(username: &str) -> Option<PathBuf> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = CString::new(username).ok()?;
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        // SAFETY: `passwd` is plain old data that getpwnam_r fills in, with its strings
+        // pointing into `buf`, which outlives every use of them below
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let code = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+
+        if code == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if code != 0 || result.is_null() || passwd.pw_dir.is_null() {
+            return None;
+        }
+
+        // SAFETY: getpwnam_r succeeded, so `pw_dir` is a NUL-terminated string in `buf`
+        let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+        return Some(PathBuf::from(OsStr::from_bytes(home.to_bytes())))
+            .filter(|home| !home.as_os_str().is_empty());
+    }
+}
+
+#[cfg(not(unix))]
+fn getpwnam_home(_username: &str) -> Option<PathBuf> {
+    None
+}
+
+/// Extracts the home directory of `username` from passwd-formatted `contents`.
+///
+/// Each entry has the form `name:password:uid:gid:gecos:home:shell`; comments and
+/// malformed lines are skipped.
+pub fn parse_passwd_home(contents: &str, username: &str) -> Option<PathBuf> {
+    passwd_field(contents, username, 5).map(PathBuf::from)
+}
+
+/// Extracts the login shell of `username` from passwd-formatted `contents`.
+pub fn parse_passwd_shell(contents: &str, username: &str) -> Option<String> {
+    passwd_field(contents, username, 6).map(str::to_string)
+}
+
+fn passwd_field<'a>(contents: &'a str, username: &str, field: usize) -> Option<&'a str> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() >= 7 && fields[0] == username)
+        .map(|fields| fields[field])
+        .filter(|value| !value.is_empty())
+}
+
+/// Returns whether `path` is one of the PATH entries.
+///
+/// See [`exists_in_path_within`] to check a PATH value other than the current one.
+pub fn exists_in_path(path: impl AsRef<Path>) -> bool {
+    exists_in_path_with_env(path, &SystemEnv)
+}
+
+/// Like [`exists_in_path`], but reads PATH from `env`.
+pub fn exists_in_path_with_env(path: impl AsRef<Path>, env: &dyn Env) -> bool {
+    exists_in_path_within(path.as_ref(), &path_in(env))
+}
+
+/// Returns whether `dir` is one of the entries of `path_value`.
+///
+/// Entries are compared whole, so `/opt/bin` doesn't match `/opt/bin2` or `/opt`.
+pub fn exists_in_path_within(dir: &Path, path_value: &OsStr) -> bool {
+    env::split_paths(path_value).any(|entry| entry == dir)
+}
+
+/// Returns whether `dir` is on PATH, comparing canonicalized paths.
+///
+/// Unlike [`exists_in_path`] this treats `/usr/local/../bin` and symlinked directories as
+/// the directory they resolve to. Entries that don't exist are compared as-is.
+pub fn exists_in_path_canonical(dir: impl AsRef<Path>) -> bool {
+    exists_in_path_canonical_within(dir.as_ref(), &path_in(&SystemEnv))
+}
+
+/// Like [`exists_in_path_canonical`], but checks the entries of `path_value` instead of the
+/// current PATH.
+pub fn exists_in_path_canonical_within(dir: &Path, path_value: &OsStr) -> bool {
+    let canonical =
+        |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = canonical(dir);
+
+    env::split_paths(path_value).any(|entry| canonical(&entry) == dir)
+}
+
+/// Statistics about an rcfile, as returned by [`Shell::rcfile_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RcFileStats {
+    /// Number of lines, not counting an empty line after the final newline.
+    pub lines: usize,
+    /// Size of the file in bytes.
+    pub bytes: u64,
+    /// Number of lines that modify PATH.
+    pub path_exports: usize,
+    /// Whether the file ends with a newline. `false` for an empty file.
+    pub ends_with_newline: bool,
+}
+
+/// The kind of a single rcfile line, as reported by [`classify_rcfile_lines`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    /// A line starting with `#`.
+    Comment,
+    /// A line that modifies PATH, e.g. `export PATH=...` or `fish_add_path ...`.
+    PathExport,
+    /// A line sourcing another file with `source` or `.`.
+    Source,
+    /// An empty or whitespace-only line.
+    Blank,
+    /// Anything else.
+    Other,
+}
+
+/// Lists the managed blocks (see [`Shell::managed_block`]) in `rcfile`.
+///
+/// Each block is returned with its name and the 0-based range of lines it spans, markers
+/// included, in the order the blocks appear.
+///
+/// # Errors
+/// Returns `ShellError::UnterminatedManagedBlock` if a start marker has no matching end
+/// marker.
+pub fn list_managed_blocks(
+    rcfile: &Path,
+) -> Result<Vec<(String, std::ops::Range<usize>)>, ShellError> {
+    let (_, content) = read_rcfile(rcfile)?;
+    let start_prefix = format!("{COMMENT_PREFIX} >>> ");
+
+    let mut blocks = Vec::new();
+    let mut open: Option<(&str, usize)> = None;
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        match open {
+            None => {
+                let name = line
+                    .strip_prefix(&start_prefix)
+                    .and_then(|rest| rest.strip_suffix(" >>>"))
+                    .filter(|name| !name.is_empty());
+                if let Some(name) = name {
+                    open = Some((name, index));
+                }
+            }
+            Some((name, start)) => {
+                if line == format!("{COMMENT_PREFIX} <<< {name} <<<") {
+                    blocks.push((name.to_string(), start..index + 1));
+                    open = None;
+                }
+            }
+        }
+    }
+
+    if let Some((name, _)) = open {
+        return Err(ShellError::UnterminatedManagedBlock(name.to_string()));
+    }
+
+    Ok(blocks)
+}
+
+/// Reads `rcfile` and returns each of its lines along with its [`LineKind`].
+pub fn classify_rcfile_lines(rcfile: &Path) -> Result<Vec<(String, LineKind)>, ShellError> {
+    let (_, content) = read_rcfile(rcfile)?;
+    Ok(content
+        .lines()
+        .map(|line| (line.to_string(), classify_line(line)))
+        .collect())
+}
+
+fn classify_line(line: &str) -> LineKind {
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        LineKind::Blank
+    } else if trimmed.starts_with('#') {
+        LineKind::Comment
+    } else if is_path_export(trimmed) {
+        LineKind::PathExport
+    } else if trimmed.starts_with("source ") || trimmed.starts_with(". ") {
+        LineKind::Source
+    } else {
+        LineKind::Other
+    }
+}
+
+/// Returns whether `line` modifies PATH in any of the supported shells' syntax.
+fn is_path_export(line: &str) -> bool {
+    path_export_dirs(line).is_some()
+}
+
+/// Returns the directories `line` adds to PATH, or `None` if it doesn't modify PATH.
+///
+/// Understands sh-family assignments (`export PATH="/a:/b:$PATH"`), zsh's `path` array
+/// (`path+=(/a /b)`), fish's `fish_add_path` and `set PATH`/`set fish_user_paths`, and
+/// PowerShell's `$env:PATH = "..."`. References to the existing PATH such as `$PATH` are not
+/// included.
+fn path_export_dirs(line: &str) -> Option<Vec<PathBuf>> {
+    sh_path_export_dirs(line)
+        .or_else(|| zsh_path_array_dirs(line))
+        .or_else(|| fish_path_export_dirs(line))
+        .or_else(|| powershell_path_export_dirs(line))
+}
+
+/// Strips a leading `export` from an sh-family assignment.
+fn sh_assignment(line: &str) -> &str {
+    let line = line.trim();
+    line.strip_prefix("export ")
+        .map(str::trim_start)
+        .unwrap_or(line)
+}
+
+/// Like [`path_export_dirs`], but only for sh-family `PATH=` and `PATH+=` assignments.
+fn sh_path_export_dirs(line: &str) -> Option<Vec<PathBuf>> {
+    let assignment = sh_assignment(line);
+    let value = assignment
+        .strip_prefix("PATH=")
+        .or_else(|| assignment.strip_prefix("PATH+="))?;
+    let dirs = first_word(value)
+        .split(':')
+        .filter(|entry| !is_path_reference(entry))
+        .map(PathBuf::from)
+        .collect();
+    Some(dirs)
+}
+
+/// Like [`path_export_dirs`], but only for assignments to zsh's `path` array.
+fn zsh_path_array_dirs(line: &str) -> Option<Vec<PathBuf>> {
+    let assignment = sh_assignment(line);
+    let value = assignment
+        .strip_prefix("path=(")
+        .or_else(|| assignment.strip_prefix("path+=("))?;
+    let value = value.split(')').next().unwrap_or_default();
+    Some(words_to_dirs(value.split_whitespace()))
+}
+
+/// Like [`path_export_dirs`], but only for fish's `fish_add_path` and `set`.
+fn fish_path_export_dirs(line: &str) -> Option<Vec<PathBuf>> {
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some("fish_add_path") => Some(words_to_dirs(words.filter(|word| !word.starts_with('-')))),
+        Some("set") => {
+            let mut words = words.skip_while(|word| word.starts_with('-'));
+            match words.next() {
+                Some("PATH" | "fish_user_paths") => Some(words_to_dirs(words)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Like [`path_export_dirs`], but only for PowerShell's `$env:PATH = ...` and
+/// `$env:PATH += ...`, whose entries are separated by [`path_separator`].
+///
+/// Variable names are case-insensitive in PowerShell, so `$env:Path` counts too.
+fn powershell_path_export_dirs(line: &str) -> Option<Vec<PathBuf>> {
+    let line = line.trim();
+    let prefix = line.get(..9)?;
+    if !prefix.eq_ignore_ascii_case("$env:path") {
+        return None;
+    }
+    let rest = line[9..].trim_start();
+    let value = rest
+        .strip_prefix("+=")
+        .or_else(|| rest.strip_prefix('='))?
+        .trim_start();
+    // The references contain a `:`, so they go before splitting on the separator
+    let mut value = first_word(value).to_string();
+    for reference in ["${env:path}", "$env:path"] {
+        while let Some(start) = value.to_ascii_lowercase().find(reference) {
+            value.replace_range(start..start + reference.len(), "");
+        }
+    }
+    let dirs = value
+        .split(path_separator())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| PathBuf::from(powershell_unescape(entry)))
+        .collect();
+    Some(dirs)
+}
+
+/// Reverses [`powershell_escape`].
+fn powershell_unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '`' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Converts shell words into directories, stopping at a trailing comment.
+fn words_to_dirs<'a>(words: impl Iterator<Item = &'a str>) -> Vec<PathBuf> {
+    words
+        .take_while(|word| !word.starts_with('#'))
+        .map(first_word)
+        .filter(|word| !is_path_reference(word))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Returns the first shell word of `value` with its surrounding quotes removed.
+fn first_word(value: &str) -> &str {
+    let value = value.trim_start();
+    match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+            let inner = &value[1..];
+            &inner[..inner.find(quote).unwrap_or(inner.len())]
+        }
+        _ => value.split_whitespace().next().unwrap_or_default(),
+    }
+}
+
+/// Finds the last literal `ZDOTDIR` assignment in `zshenv`, expanding a leading `~`, `$HOME`
+/// or `${HOME}` to `home`. See [`Zsh::zdotdir_offline`].
+fn parse_zdotdir_assignment(zshenv: &str, home: &Path) -> Option<PathBuf> {
+    let value = zshenv.lines().rev().find_map(|line| {
+        let line = line.trim();
+        let assignment = line.strip_prefix("export ").map_or(line, str::trim_start);
+        assignment.strip_prefix("ZDOTDIR=").map(first_word)
+    })?;
+
+    expand_home(value, home)
+}
+
+/// Expands a leading `~`, `$HOME` or `${HOME}` in `value` to `home`.
+///
+/// Returns `None` unless the result is a literal absolute path.
+fn expand_home(value: &str, home: &Path) -> Option<PathBuf> {
+    let relative = ["~", "${HOME}", "$HOME"]
+        .iter()
+        .find_map(|prefix| value.strip_prefix(prefix));
+    let path = match relative {
+        Some("") => home.to_path_buf(),
+        Some(rest) => home.join(rest.strip_prefix('/')?),
+        None => PathBuf::from(value),
+    };
+
+    let literal = !path.to_string_lossy().contains(['$', '`']);
+    Some(path).filter(|path| literal && path.is_absolute())
+}
+
+/// Returns the files sourced with `source` or `.` in `content`, in order, with `~` and
+/// `$HOME` expanded to `home`.
+///
+/// Commands anywhere on a line are found, e.g. in `[ -f ~/.bashrc ] && . ~/.bashrc`.
+fn sourced_files(content: &str, home: &Path) -> Vec<PathBuf> {
+    let mut sourced = Vec::new();
+    for line in content.lines() {
+        let mut words = line
+            .split(|c: char| c.is_whitespace() || c == ';')
+            .filter(|word| !word.is_empty());
+        while let Some(word) = words.next() {
+            if word.starts_with('#') {
+                break;
+            }
+            if matches!(word, "." | "source") {
+                let file = words
+                    .next()
+                    .map(first_word)
+                    .and_then(|file| expand_home(file, home));
+                sourced.extend(file);
+            }
+        }
+    }
+    sourced
+}
+
+/// Returns, for each of `lines`, whether it is an unindented sh-family line outside of any
+/// compound command (`if`, `case`, loops and `{ ... }` groups such as function bodies).
+///
+/// Lines inside those only run under some condition, so the functions rewriting PATH exports
+/// across lines leave them alone. Keywords are recognized as whole words only, outside of
+/// comments; quoting isn't taken into account.
+fn sh_top_level_lines(lines: &[&str]) -> Vec<bool> {
+    let mut depth = 0usize;
+    lines
+        .iter()
+        .map(|line| {
+            let top_level = depth == 0 && !line.starts_with(char::is_whitespace);
+
+            let words = line
+                .split(|c: char| c.is_whitespace() || c == ';')
+                .filter(|word| !word.is_empty())
+                .take_while(|word| !word.starts_with('#'));
+            for word in words {
+                match word {
+                    "if" | "case" | "for" | "while" | "until" | "{" => depth += 1,
+                    "fi" | "esac" | "done" | "}" => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+
+            top_level
+        })
+        .collect()
+}
+
+/// Returns whether a PATH entry refers to the existing PATH rather than a directory.
+fn is_path_reference(entry: &str) -> bool {
+    matches!(
+        entry,
+        "" | "$PATH" | "${PATH}" | "$path" | "${path}" | "$fish_user_paths"
+    )
+}
+
+/// Finds PATH-export lines in `rcfile` that repeat each other or add a directory another
+/// PATH-export line already adds.
+///
+/// Returns the 1-based line number and content of every such line, in file order.
+pub fn find_duplicate_path_exports(rcfile: &Path) -> Result<Vec<(usize, String)>, ShellError> {
+    let (_, content) = read_rcfile(rcfile)?;
+
+    let exports: Vec<(usize, &str, Vec<PathBuf>)> = content
+        .lines()
+        .enumerate()
+        .filter_map(|(idx, line)| path_export_dirs(line).map(|dirs| (idx + 1, line, dirs)))
+        .collect();
+
+    let duplicates = exports
+        .iter()
+        .filter(|(number, line, dirs)| {
+            exports
+                .iter()
+                .any(|(other_number, other_line, other_dirs)| {
+                    other_number != number
+                        && (other_line.trim() == line.trim()
+                            || dirs.iter().any(|dir| other_dirs.contains(dir)))
+                })
+        })
+        .map(|(number, line, _)| (*number, line.to_string()))
+        .collect();
+
+    Ok(duplicates)
+}
+
+const UTF8_BOM: &str = "\u{feff}";
+
+/// The variable every supported shell searches for commands. Windows spells it `Path`, but
+/// environment variable names are case-insensitive there, so this refers to the same variable.
+const PATH_VAR: &str = "PATH";
+
+/// What [`Shell::write_path_export`] puts in a `~/.bash_profile` it creates, so that bash
+/// login shells keep reading `~/.profile` and also read `~/.bashrc`.
+const BASH_PROFILE_CHAIN: &str =
+    "[ -f ~/.profile ] && . ~/.profile\n[ -f ~/.bashrc ] && . ~/.bashrc\n";
+
+/// Starts a comment in the rcfiles of every supported shell, e.g. in the markers of
+/// [`Shell::managed_block`].
+const COMMENT_PREFIX: &str = "#";
+
+/// The text encoding of an rcfile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RcFileEncoding {
+    /// Plain UTF-8.
+    Utf8,
+    /// UTF-8 prefixed by a byte order mark, as saved by some Windows editors.
+    Utf8WithBom,
+    /// Little-endian UTF-16 with a byte order mark, as older Windows PowerShell versions
+    /// write `$PROFILE`. Editing requires the `utf16` feature.
+    Utf16Le,
+    /// Big-endian UTF-16 with a byte order mark. Editing requires the `utf16` feature.
+    Utf16Be,
+}
+
+/// Detects the encoding of `rcfile`.
+///
+/// UTF-16 files are detected even without the `utf16` feature.
+pub fn rcfile_encoding(rcfile: &Path) -> Result<RcFileEncoding, ShellError> {
+    if let Some(encoding) = rcfile_utf16_encoding(rcfile)? {
+        return Ok(encoding);
+    }
+    Ok(read_rcfile(rcfile)?.0)
+}
+
+/// The line ending used by an rcfile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, the Unix convention.
+    Lf,
+    /// `\r\n`, as left by Windows editors, e.g. for dotfiles shared with Git Bash.
+    CrLf,
+}
+
+impl LineEnding {
+    /// Returns the line ending itself.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Detects the dominant line ending of `rcfile`, which is [`LineEnding::Lf`] for a file
+/// without any line break or with as many of each kind.
+///
+/// The file is read in chunks rather than all at once.
+pub fn rcfile_line_ending(rcfile: &Path) -> Result<LineEnding, ShellError> {
+    line_ending(rcfile)
+}
+
+fn line_ending(rcfile: &Path) -> Result<LineEnding, ShellError> {
+    use std::io::BufRead;
+
+    if has_utf16_bom(rcfile)? {
+        let (_, content) = read_rcfile(rcfile)?;
+        let crlf = content.matches("\r\n").count();
+        let lf = content.matches('\n').count() - crlf;
+        return Ok(if crlf > lf {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        });
+    }
+
+    let mut reader = std::io::BufReader::new(std::fs::File::open(rcfile)?);
+    let (mut lf, mut crlf) = (0usize, 0usize);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+
+        if buf.ends_with(b"\r\n") {
+            crlf += 1;
+        } else if buf.ends_with(b"\n") {
+            lf += 1;
+        }
+    }
+
+    Ok(if crlf > lf {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Lf
+    })
+}
+
+/// Returns the UTF-16 encoding announced by the byte order mark `content` starts with, if any.
+fn utf16_encoding(content: &[u8]) -> Option<RcFileEncoding> {
+    match content.get(..2)? {
+        [0xff, 0xfe] => Some(RcFileEncoding::Utf16Le),
+        [0xfe, 0xff] => Some(RcFileEncoding::Utf16Be),
+        _ => None,
+    }
+}
+
+/// Returns whether `rcfile` starts with a UTF-16 byte order mark, reading only the mark.
+fn has_utf16_bom(rcfile: &Path) -> std::io::Result<bool> {
+    Ok(rcfile_utf16_encoding(rcfile)?.is_some())
+}
+
+/// Like [`utf16_encoding`], but reads the byte order mark from `rcfile`.
+fn rcfile_utf16_encoding(rcfile: &Path) -> std::io::Result<Option<RcFileEncoding>> {
+    use std::io::Read;
+
+    let mut bom = Vec::with_capacity(2);
+    std::fs::File::open(rcfile)?.take(2).read_to_end(&mut bom)?;
+    Ok(utf16_encoding(&bom))
+}
+
+/// Reads `rcfile`, returning its encoding and its contents without any byte order mark.
+///
+/// A UTF-16 `rcfile` fails with `ShellError::Utf16Unsupported` without the `utf16` feature.
+fn read_rcfile(rcfile: &Path) -> Result<(RcFileEncoding, String), ShellError> {
+    let content = std::fs::read(rcfile)?;
+
+    if let Some(encoding) = utf16_encoding(&content) {
+        if !cfg!(feature = "utf16") {
+            return Err(ShellError::Utf16Unsupported(rcfile.to_path_buf()));
+        }
+
+        let units = content[2..].chunks(2).map(|pair| match (encoding, pair) {
+            (RcFileEncoding::Utf16Le, &[low, high]) => Some(u16::from_le_bytes([low, high])),
+            (_, &[high, low]) => Some(u16::from_be_bytes([high, low])),
+            _ => None,
+        });
+        let units: Option<Vec<u16>> = units.collect();
+        let content = units
+            .and_then(|units| String::from_utf16(&units).ok())
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid UTF-16 rcfile")
+            })?;
+        return Ok((encoding, content));
+    }
+
+    let content = String::from_utf8(content)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    match content.strip_prefix(UTF8_BOM) {
+        Some(content) => Ok((RcFileEncoding::Utf8WithBom, content.to_string())),
+        None => Ok((RcFileEncoding::Utf8, content)),
+    }
+}
+
+/// Atomically writes `content` to `rcfile`, restoring the byte order mark if `encoding` had
+/// one.
+fn write_rcfile(rcfile: &Path, encoding: RcFileEncoding, content: &str) -> Result<(), ShellError> {
+    match encoding {
+        RcFileEncoding::Utf8 => write_atomic(rcfile, content.as_bytes())?,
+        RcFileEncoding::Utf8WithBom => {
+            write_atomic(rcfile, format!("{UTF8_BOM}{content}").as_bytes())?
+        }
+        RcFileEncoding::Utf16Le | RcFileEncoding::Utf16Be => {
+            if !cfg!(feature = "utf16") {
+                return Err(ShellError::Utf16Unsupported(rcfile.to_path_buf()));
+            }
+
+            let mut bytes = Vec::with_capacity(2 * content.len() + 2);
+            for unit in std::iter::once(0xfeff).chain(content.encode_utf16()) {
+                match encoding {
+                    RcFileEncoding::Utf16Le => bytes.extend_from_slice(&unit.to_le_bytes()),
+                    _ => bytes.extend_from_slice(&unit.to_be_bytes()),
+                }
+            }
+            write_atomic(rcfile, &bytes)?
+        }
+    }
+    Ok(())
+}
+
+/// Writes `content` to a temporary file next to `path` and renames it into place, so readers
+/// never observe a partially written file.
+///
+/// Symlinks are resolved first so the link itself is kept, and the original file's
+/// permissions are carried over.
+fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let (target, tmp) = atomic_paths(path);
+    std::fs::write(&tmp, content)?;
+    finish_atomic(&tmp, &target)
+}
+
+/// Returns the file an atomic write to `path` ends up replacing, with symlinks resolved,
+/// and the temporary file to write to first.
+fn atomic_paths(path: &Path) -> (PathBuf, PathBuf) {
+    let target = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp = target.with_file_name(format!(".{file_name}.what-the-path.tmp"));
+    (target, tmp)
+}
+
+/// Moves the fully written `tmp` file over `target`, carrying over its permissions.
+fn finish_atomic(tmp: &Path, target: &Path) -> std::io::Result<()> {
+    if let Ok(metadata) = std::fs::metadata(target) {
+        std::fs::set_permissions(tmp, metadata.permissions())?;
+    }
+
+    std::fs::rename(tmp, target).inspect_err(|_| {
+        let _ = std::fs::remove_file(tmp);
+    })
+}
+
+/// Collapses runs of blank lines in `rcfile` into a single blank line and removes trailing
+/// blank lines, leaving all other content untouched.
+///
+/// Repeated add/remove cycles tend to leave blank lines behind; this is a gentle cleanup to
+/// run afterwards. The file is rewritten atomically, and only if anything changed.
+pub fn normalize_rcfile(rcfile: &Path) -> Result<(), ShellError> {
+    modify_rcfile(rcfile, |file| {
+        let mut content = String::with_capacity(file.len());
+        let mut previous_blank = false;
+        for line in file.lines() {
+            let blank = line.trim().is_empty();
+            if !blank {
+                content.push_str(line);
+                content.push('\n');
+            } else if !previous_blank {
+                content.push('\n');
+            }
+            previous_blank = blank;
+        }
+
+        let content = content.trim_end_matches(|c: char| c.is_whitespace());
+        if content.is_empty() {
+            String::new()
+        } else {
+            format!("{content}\n")
+        }
+    })
+}
+
+/// Whether rcfile edits go through symlinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FollowSymlinks {
+    /// Edit the file a symlinked rcfile points to, keeping the link itself.
+    #[default]
+    Follow,
+    /// Refuse to edit a symlinked rcfile, e.g. one that points into a dotfiles repository.
+    Refuse,
+}
+
+impl FollowSymlinks {
+    /// Returns `ShellError::RcFileIsSymlink` if `rcfile` is a symlink this setting refuses.
+    fn check(self, rcfile: &Path) -> Result<(), ShellError> {
+        let is_symlink = std::fs::symlink_metadata(rcfile)
+            .is_ok_and(|metadata| metadata.file_type().is_symlink());
+        if self == FollowSymlinks::Refuse && is_symlink {
+            return Err(ShellError::RcFileIsSymlink(rcfile.to_path_buf()));
+        }
+        Ok(())
+    }
+}
+
+/// Applies `f` to the contents of `rcfile` and atomically writes back the result.
+///
+/// The file must exist. A byte order mark is stripped before `f` sees the contents and
+/// restored afterwards, and nothing is written when `f` returns the contents unchanged.
+/// Symlinks are followed, see [`modify_rcfile_with`].
+pub fn modify_rcfile(rcfile: &Path, f: impl FnOnce(String) -> String) -> Result<(), ShellError> {
+    modify_rcfile_with(rcfile, FollowSymlinks::Follow, f)
+}
+
+/// Like [`modify_rcfile`], but returns `ShellError::RcFileIsSymlink` instead of editing a
+/// symlinked `rcfile` when `follow` is [`FollowSymlinks::Refuse`].
+pub fn modify_rcfile_with(
+    rcfile: &Path,
+    follow: FollowSymlinks,
+    f: impl FnOnce(String) -> String,
+) -> Result<(), ShellError> {
+    follow.check(rcfile)?;
+    let (encoding, file) = read_rcfile(rcfile)?;
+
+    let content = f(file.clone());
+    if content != file {
+        write_rcfile(rcfile, encoding, &content)?;
+    }
+
+    Ok(())
+}
+
+/// Like [`append_to_rcfile`], but returns `ShellError::RcFileIsSymlink` instead of appending
+/// to a symlinked `rcfile` when `follow` is [`FollowSymlinks::Refuse`].
+pub fn append_to_rcfile_with(
+    rcfile: &Path,
+    line: &str,
+    follow: FollowSymlinks,
+) -> Result<(), ShellError> {
+    follow.check(rcfile)?;
+    append_to_rcfile(rcfile.to_path_buf(), line)
+}
+
+/// Appends `line` to `rcfile`, terminated with the file's dominant line ending (see
+/// [`rcfile_line_ending`]).
+///
+/// `line` must be a single line: the duplicate checks and line removal in this module work
+/// line by line, so they would lose track of text spanning several. Use
+/// [`Shell::append_managed_block`] to add several lines that belong together.
+///
+/// # Errors
+/// Returns `ShellError::MultilineNotAllowed` if `line` contains a newline.
+pub fn append_to_rcfile(rcfile: PathBuf, line: &str) -> Result<(), ShellError> {
+    check_single_line(line)?;
+    append_text(rcfile, line)
+}
+
+/// Fails with `ShellError::MultilineNotAllowed` if `line` isn't a single line.
+fn check_single_line(line: &str) -> Result<(), ShellError> {
+    if line.contains('\n') {
+        return Err(ShellError::MultilineNotAllowed);
+    }
+    Ok(())
+}
+
+/// Appends `text` to `rcfile` like [`append_to_rcfile`], but without checking that it is a
+/// single line.
+fn append_text(rcfile: PathBuf, text: &str) -> Result<(), ShellError> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    trace_event!(rcfile = %rcfile.display(), text, "appending line to rcfile");
+    let ending = line_ending(&rcfile)?;
+    if has_utf16_bom(&rcfile)? {
+        let (encoding, mut content) = read_rcfile(&rcfile)?;
+        content.push_str(text);
+        content.push_str(ending.as_str());
+        return write_rcfile(&rcfile, encoding, &content);
+    }
+
+    let mut file = OpenOptions::new().append(true).open(rcfile)?;
+    write!(file, "{}{}", text, ending.as_str())?;
+    Ok(())
+}
+
+/// Like [`append_to_rcfile`], but separates `line` from the existing content with exactly
+/// one blank line.
+///
+/// Blank lines already at the end of `rcfile` are reused rather than added to, so repeated
+/// runs don't pile them up. Nothing precedes `line` in an empty file.
+pub fn append_to_rcfile_spaced(rcfile: &Path, line: &str) -> Result<(), ShellError> {
+    let ending = line_ending(rcfile)?.as_str();
+    modify_rcfile(rcfile, |file| {
+        let mut lines: Vec<&str> = file.split_inclusive('\n').collect();
+        while lines.last().is_some_and(|last| last.trim().is_empty()) {
+            lines.pop();
+        }
+
+        let mut content = lines.concat();
+        if !content.is_empty() {
+            if !content.ends_with('\n') {
+                content.push_str(ending);
+            }
+            content.push_str(ending);
+        }
+        content.push_str(line);
+        content.push_str(ending);
+        content
+    })
+}
+
+/// Inserts `line` into `rcfile` right after the first line that is exactly `anchor`, e.g. to
+/// add a PATH export below a `# Homebrew` comment.
+///
+/// Returns whether `anchor` was found. The file is left untouched when it wasn't, so the
+/// caller can fall back to [`append_to_rcfile`].
+pub fn insert_after_line(rcfile: &Path, anchor: &str, line: &str) -> Result<bool, ShellError> {
+    insert_at_anchor(rcfile, anchor, line, true)
+}
+
+/// Like [`insert_after_line`], but inserts `line` right before `anchor`.
+pub fn insert_before_line(rcfile: &Path, anchor: &str, line: &str) -> Result<bool, ShellError> {
+    insert_at_anchor(rcfile, anchor, line, false)
+}
+
+fn insert_at_anchor(
+    rcfile: &Path,
+    anchor: &str,
+    line: &str,
+    after: bool,
+) -> Result<bool, ShellError> {
+    let ending = line_ending(rcfile)?.as_str();
+    let mut found = false;
+    modify_rcfile(rcfile, |file| {
+        let mut lines: Vec<String> = file.split_inclusive('\n').map(String::from).collect();
+        let Some(index) = lines
+            .iter()
+            .position(|existing| existing.trim_end_matches(['\n', '\r']) == anchor)
+        else {
+            return file;
+        };
+        found = true;
+
+        if after && !lines[index].ends_with('\n') {
+            lines[index].push_str(ending);
+        }
+        let position = if after { index + 1 } else { index };
+        lines.insert(position, format!("{line}{ending}"));
+        lines.concat()
+    })?;
+
+    Ok(found)
+}
+
+/// What an append to an rcfile did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendOutcome {
+    /// The rcfile didn't exist and was created with the line.
+    Created,
+    /// The line was appended to the existing rcfile.
+    Appended,
+    /// The rcfile already contained the line and was left untouched.
+    AlreadyPresent,
+}
+
+impl AppendOutcome {
+    /// Returns whether the rcfile was written to.
+    pub fn changed(self) -> bool {
+        self != AppendOutcome::AlreadyPresent
+    }
+}
+
+/// Like [`append_to_rcfile`], but creates `rcfile` and its parent directories if needed.
+///
+/// A newly created rcfile is seeded with the [`Shell::rcfile_template`] of the shell it
+/// belongs to (see [`shell_for_rcfile`]), or left empty for an unknown file name. Returns
+/// [`AppendOutcome::Created`] in that case and [`AppendOutcome::Appended`] otherwise.
+pub fn append_to_rcfile_create(rcfile: PathBuf, line: &str) -> Result<AppendOutcome, ShellError> {
+    use std::io::Write;
+
+    check_single_line(line)?;
+    if let Some(parent) = rcfile.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let outcome = match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&rcfile)
+    {
+        Ok(mut file) => {
+            let template = shell_for_rcfile(&rcfile).map_or("", |shell| shell.rcfile_template());
+            file.write_all(template.as_bytes())?;
+            AppendOutcome::Created
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => AppendOutcome::Appended,
+        Err(err) => return Err(err.into()),
+    };
+
+    append_to_rcfile(rcfile, line)?;
+    Ok(outcome)
+}
+
+/// Removes every line of `rcfile` that is exactly `line`.
+///
+/// Returns the number of occurrences removed. Matching lines are dropped entirely, along
+/// with their line break, and lines that merely contain `line` are kept. The file is left
+/// untouched when `line` does not occur in it.
+///
+/// Line endings are ignored when comparing, so `"export PATH=...\n"` also removes a
+/// CRLF-terminated line. A `line` spanning several lines removes each run of consecutive
+/// lines matching it.
+///
+/// Unless `line` spans several lines, the file is streamed one line at a time into a
+/// temporary file that then replaces it, so large rcfiles are never held in memory.
+pub fn remove_from_rcfile(rcfile: PathBuf, line: &str) -> Result<usize, ShellError> {
+    if line.is_empty() {
+        return Ok(0);
+    }
+    let pattern: Vec<&str> = line.lines().collect();
+
+    // UTF-16 files can't be streamed line by line
+    let count = if pattern.len() > 1 || has_utf16_bom(&rcfile)? {
+        let (encoding, file) = read_rcfile(&rcfile)?;
+        let (content, count) = without_lines(&file, &pattern);
+        if count > 0 {
+            write_rcfile(&rcfile, encoding, &content)?;
+        }
+        count
+    } else {
+        remove_streaming(&rcfile, pattern[0])?
+    };
+    trace_event!(rcfile = %rcfile.display(), line, count, "removed line from rcfile");
+
+    Ok(count)
+}
+
+/// Returns `line` without its trailing `\n` or `\r\n`.
+fn without_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// Returns `file` without the runs of lines matching `pattern` (see [`remove_from_rcfile`]),
+/// and the number of runs removed.
+fn without_lines(file: &str, pattern: &[&str]) -> (String, usize) {
+    let lines: Vec<&str> = file.split_inclusive('\n').collect();
+    let mut content = String::new();
+    let mut count = 0;
+
+    let mut idx = 0;
+    while idx < lines.len() {
+        let run = lines[idx..].iter().take(pattern.len());
+        if run.len() == pattern.len()
+            && run
+                .zip(pattern)
+                .all(|(line, pattern)| without_line_ending(line) == *pattern)
+        {
+            count += 1;
+            idx += pattern.len();
+        } else {
+            content.push_str(lines[idx]);
+            idx += 1;
+        }
+    }
+
+    (content, count)
+}
+
+/// Streaming implementation of [`remove_from_rcfile`] for a single `line`, without its line
+/// break.
+fn remove_streaming(rcfile: &Path, line: &str) -> std::io::Result<usize> {
+    use std::fs::File;
+    use std::io::{BufReader, BufWriter, Write};
+
+    let reader = BufReader::new(File::open(rcfile)?);
+    let (target, tmp) = atomic_paths(rcfile);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+
+    let result = copy_without(reader, &mut writer, line).and_then(|count| {
+        writer.flush()?;
+        Ok(count)
+    });
+    drop(writer);
+
+    match result {
+        Ok(count) if count > 0 => {
+            finish_atomic(&tmp, &target)?;
+            Ok(count)
+        }
+        result => {
+            let _ = std::fs::remove_file(&tmp);
+            result
+        }
+    }
+}
+
+/// Copies `reader` to `writer` line by line, leaving out the lines that are `pattern` apart
+/// from their line ending, and returns the number of lines left out.
+fn copy_without(
+    mut reader: impl std::io::BufRead,
+    writer: &mut impl std::io::Write,
+    pattern: &str,
+) -> std::io::Result<usize> {
+    let mut count = 0;
+    let mut buf = String::new();
+    let mut first = true;
+
+    loop {
+        buf.clear();
+        if reader.read_line(&mut buf)? == 0 {
+            break;
+        }
+
+        // Keep the byte order mark out of the matching, like read_rcfile does
+        let (bom, content) = match buf.strip_prefix(UTF8_BOM) {
+            Some(content) if first => (UTF8_BOM, content),
+            _ => ("", buf.as_str()),
+        };
+        first = false;
+
+        if without_line_ending(content) == pattern {
+            count += 1;
+            writer.write_all(bom.as_bytes())?;
+        } else {
+            writer.write_all(buf.as_bytes())?;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Replaces every line of `rcfile` that is exactly `old` with `new`.
+///
+/// This is meant for updating a PATH export in place, e.g. when a tool's install
+/// directory moves between versions. Returns whether any line was replaced; the file is
+/// left untouched otherwise.
+///
+/// Line endings are ignored when comparing, like in [`remove_from_rcfile`], and replaced
+/// lines keep the one they had.
+pub fn replace_line(rcfile: &Path, old: &str, new: &str) -> Result<bool, ShellError> {
+    let (old, new) = (without_line_ending(old), without_line_ending(new));
+    let mut replaced = false;
+    modify_rcfile(rcfile, |file| {
+        file.split_inclusive('\n')
+            .map(|line| {
+                let content = without_line_ending(line);
+                if content == old {
+                    replaced = true;
+                    format!("{new}{}", &line[content.len()..])
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect()
+    })?;
+
+    Ok(replaced)
+}
+
+/// Like [`replace_line`], but appends `new` to `rcfile` when `old` isn't found.
+///
+/// Returns whether a replacement happened, so `false` means `new` was appended.
+pub fn replace_line_or_append(rcfile: &Path, old: &str, new: &str) -> Result<bool, ShellError> {
+    if replace_line(rcfile, old, new)? {
+        return Ok(true);
+    }
+
+    append_to_rcfile(rcfile.to_path_buf(), new)?;
+    Ok(false)
+}
+
+/// Removes `dir` from the sh-family PATH export lines of `rcfile`
+/// (`export PATH="/a:/b:$PATH"` or `PATH=/a:$PATH`), keeping their other entries.
+///
+/// A line left without any directory is deleted entirely. Returns whether any line changed;
+/// the file is left untouched otherwise.
+pub fn remove_dir_from_path_export(rcfile: &Path, dir: &Path) -> Result<bool, ShellError> {
+    let dir = dir.to_string_lossy();
+
+    let mut changed = false;
+    modify_rcfile(rcfile, |file| {
+        file.split_inclusive('\n')
+            .filter_map(|line| {
+                let (content, newline) = match line.strip_suffix('\n') {
+                    Some(content) => (content, "\n"),
+                    None => (line, ""),
+                };
+                match remove_dir_from_export_line(content, &dir) {
+                    None => Some(line.to_string()),
+                    Some(new_line) => {
+                        changed = true;
+                        new_line.map(|new_line| new_line + newline)
+                    }
+                }
+            })
+            .collect()
+    })?;
+
+    Ok(changed)
+}
+
+/// Removes `dir` from the value of a sh-family PATH assignment `line`.
+///
+/// Returns `None` if `line` isn't such an assignment or doesn't contain `dir`, and
+/// `Some(None)` if no directory would be left on the line.
+fn remove_dir_from_export_line(line: &str, dir: &str) -> Option<Option<String>> {
+    let assignment = ShPathAssignment::parse(line)?;
+
+    let entries: Vec<&str> = assignment.value.split(':').collect();
+    let kept: Vec<&str> = entries
+        .iter()
+        .copied()
+        .filter(|entry| *entry != dir)
+        .collect();
+    if kept.len() == entries.len() {
+        return None;
+    }
+
+    if kept.iter().all(|entry| is_path_reference(entry)) {
+        return Some(None);
+    }
+
+    Some(Some(assignment.with_value(&kept.join(":"))))
+}
+
+/// A sh-family PATH assignment line, such as `  export PATH="/a:$PATH" # comment`, split
+/// into its parts.
+struct ShPathAssignment<'a> {
+    /// Leading whitespace.
+    indent: &'a str,
+    /// `export ` including any following whitespace, or nothing.
+    export: &'a str,
+    /// The quote around the value, or nothing.
+    quote: &'a str,
+    /// The value without its quotes.
+    value: &'a str,
+    /// Whatever follows the value, such as a comment.
+    tail: &'a str,
+}
+
+impl<'a> ShPathAssignment<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let body = line.trim_start();
+        let indent = &line[..line.len() - body.len()];
+        let (export, assignment) = match body.strip_prefix("export ") {
+            Some(rest) => {
+                let assignment = rest.trim_start();
+                (&body[..body.len() - assignment.len()], assignment)
+            }
+            None => ("", body),
+        };
+        let value = assignment.strip_prefix("PATH=")?;
+
+        let (quote, value, tail) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = value[1..].find(quote)? + 1;
+                (&value[..1], &value[1..end], &value[end + 1..])
+            }
+            _ => {
+                let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                ("", &value[..end], &value[end..])
+            }
+        };
+
+        Some(Self {
+            indent,
+            export,
+            quote,
+            value,
+            tail,
+        })
+    }
+
+    /// Returns the line with its value replaced by `value`.
+    fn with_value(&self, value: &str) -> String {
+        let Self {
+            indent,
+            export,
+            quote,
+            tail,
+            ..
+        } = self;
+        format!("{indent}{export}PATH={quote}{value}{quote}{tail}")
+    }
+}
+
+/// Merges the sh-family PATH export lines of `rcfile` into a single line, at the position of
+/// the first one.
+///
+/// Only lines that extend the existing PATH, with exactly one `$PATH` reference and nothing
+/// after the value (such as a comment), are merged. The merged line adds every directory
+/// once, in the order the separate lines put them on PATH. Returns whether the file changed,
+/// which requires at least two such lines.
+///
+/// Like [`dedup_rcfile_path_entries`], this only considers unindented lines outside of `if`,
+/// `case`, loops and `{ ... }` groups.
+pub fn consolidate_path_exports(rcfile: &Path) -> Result<bool, ShellError> {
+    let mut changed = false;
+    modify_rcfile(rcfile, |file| {
+        let lines: Vec<&str> = file.split_inclusive('\n').collect();
+        let top_level = sh_top_level_lines(&lines);
+
+        let mut merged = Vec::new();
+        let mut first = None;
+        let mut prepended: Vec<&str> = Vec::new();
+        let mut appended: Vec<&str> = Vec::new();
+        for (idx, line) in lines.iter().enumerate() {
+            if !top_level[idx] {
+                continue;
+            }
+            let Some(assignment) = ShPathAssignment::parse(line.trim_end_matches('\n')) else {
+                continue;
+            };
+            if !assignment.tail.trim().is_empty() {
+                continue;
+            }
+
+            let entries: Vec<&str> = assignment.value.split(':').collect();
+            let references: Vec<usize> = (0..entries.len())
+                .filter(|&idx| matches!(entries[idx], "$PATH" | "${PATH}"))
+                .collect();
+            let [reference] = references[..] else {
+                continue;
+            };
+
+            // A later line's prepended directories end up in front of the earlier ones'
+            let before = entries[..reference]
+                .iter()
+                .filter(|entry| !entry.is_empty());
+            prepended.splice(0..0, before.copied());
+            appended.extend(
+                entries[reference + 1..]
+                    .iter()
+                    .filter(|entry| !entry.is_empty()),
+            );
+            merged.push(idx);
+            first.get_or_insert((idx, assignment));
+        }
+
+        let Some((first, assignment)) = first.filter(|_| merged.len() >= 2) else {
+            return file;
+        };
+        changed = true;
+
+        let mut dirs: Vec<&str> = Vec::new();
+        for dir in prepended.into_iter().chain(["$PATH"]).chain(appended) {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        let newline = if lines[first].ends_with('\n') {
+            "\n"
+        } else {
+            ""
+        };
+        let merged_line = format!(
+            "{}{}PATH=\"{}\"{newline}",
+            assignment.indent,
+            assignment.export,
+            dirs.join(":")
+        );
+
+        lines
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, line)| {
+                if idx == first {
+                    Some(merged_line.clone())
+                } else if merged.contains(&idx) {
+                    None
+                } else {
+                    Some(line.to_string())
+                }
+            })
+            .collect()
+    })?;
+
+    Ok(changed)
+}
+
+/// Removes the directories the sh-family PATH export lines of `rcfile` add more than once,
+/// keeping only their first occurrence.
+///
+/// Unlike [`consolidate_path_exports`] this leaves every line where it is: a line only loses
+/// the directories an earlier line (or an earlier entry of the same line) already added, and
+/// is deleted entirely once no directory is left on it. References to the existing PATH are
+/// never removed. Returns the number of directory entries removed; the file is left
+/// untouched if there were none.
+///
+/// Only unindented lines outside of `if`, `case`, loops and `{ ... }` groups are considered,
+/// since the ones inside may not run at all.
+pub fn dedup_rcfile_path_entries(rcfile: &Path) -> Result<usize, ShellError> {
+    let mut removed = 0;
+    modify_rcfile(rcfile, |file| {
+        let lines: Vec<&str> = file.split_inclusive('\n').collect();
+        let top_level = sh_top_level_lines(&lines);
+
+        let mut seen: Vec<String> = Vec::new();
+        lines
+            .iter()
+            .zip(top_level)
+            .filter_map(|(line, top_level)| {
+                let (content, newline) = match line.strip_suffix('\n') {
+                    Some(content) => (content, "\n"),
+                    None => (*line, ""),
+                };
+                let Some(assignment) = ShPathAssignment::parse(content).filter(|_| top_level)
+                else {
+                    return Some(line.to_string());
+                };
+
+                let entries: Vec<&str> = assignment.value.split(':').collect();
+                let mut kept: Vec<&str> = Vec::new();
+                for entry in &entries {
+                    if is_path_reference(entry) {
+                        kept.push(entry);
+                    } else if !seen.iter().any(|dir| dir == entry) {
+                        seen.push(entry.to_string());
+                        kept.push(entry);
+                    }
+                }
+                if kept.len() == entries.len() {
+                    return Some(line.to_string());
+                }
+
+                removed += entries.len() - kept.len();
+                if kept.iter().all(|entry| is_path_reference(entry)) {
+                    return None;
+                }
+                Some(assignment.with_value(&kept.join(":")) + newline)
+            })
+            .collect()
+    })?;
+
+    Ok(removed)
+}
+
+/// Returns whether `rcfile` has a line matching `line`, ignoring surrounding whitespace on
+/// both sides.
+///
+/// Line endings are ignored too, so a CRLF-terminated line matches. A missing `rcfile`
+/// contains no lines, so this returns `false` for it.
+pub fn rcfile_contains_line(rcfile: &Path, line: &str) -> Result<bool, ShellError> {
+    let file = match read_rcfile(rcfile) {
+        Ok((_, file)) => file,
+        Err(ShellError::RcFileError(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(false)
+        }
+        Err(err) => return Err(err),
+    };
+
+    let line = without_line_ending(line).trim();
+    Ok(file
+        .split_inclusive('\n')
+        .any(|existing| without_line_ending(existing).trim() == line))
+}
+
+/// Appends `line` to `rcfile` unless it already contains it (see [`rcfile_contains_line`]).
+///
+/// Returns [`AppendOutcome::AlreadyPresent`] if it did, and [`AppendOutcome::Appended`]
+/// otherwise. Running an installer twice therefore doesn't add the same PATH export twice.
+pub fn append_to_rcfile_if_missing(rcfile: &Path, line: &str) -> Result<AppendOutcome, ShellError> {
+    if rcfile_contains_line(rcfile, line)? {
+        trace_event!(rcfile = %rcfile.display(), line, "line already in rcfile");
+        return Ok(AppendOutcome::AlreadyPresent);
+    }
+
+    append_to_rcfile(rcfile.to_path_buf(), line)?;
+    Ok(AppendOutcome::Appended)
+}
+
+/// Combines [`append_to_rcfile_if_missing`] and [`append_to_rcfile_create`]: appends `line`
+/// to `rcfile` unless it already contains it, creating the file if needed.
+pub fn append_to_rcfile_create_if_missing(
+    rcfile: &Path,
+    line: &str,
+) -> Result<AppendOutcome, ShellError> {
+    if rcfile_contains_line(rcfile, line)? {
+        return Ok(AppendOutcome::AlreadyPresent);
+    }
+
+    append_to_rcfile_create(rcfile.to_path_buf(), line)
+}
+
+/// Number of backups of each rcfile [`backup_rcfile`] keeps by default.
+pub const DEFAULT_BACKUP_RETENTION: usize = 5;
+
+/// Copies `rcfile` into [`Shell::rcfile_backup_dir`] before it gets edited, as
+/// `<escaped path>.<timestamp>`, keeping the [`DEFAULT_BACKUP_RETENTION`] most recent backups.
+///
+/// The editing functions of this crate don't take backups on their own, so call this before
+/// them when an edit should be undoable. Returns the path of the new backup. See
+/// [`restore_backup`] to roll an edit back.
+pub fn backup_rcfile(rcfile: &Path) -> Result<PathBuf, ShellError> {
+    backup_rcfile_with_env(rcfile, DEFAULT_BACKUP_RETENTION, &SystemEnv)
+}
+
+/// Like [`backup_rcfile`], but keeps the `retention` most recent backups of `rcfile` (at least
+/// the new one), and finds the backup directory through `env`.
+///
+/// Backups are told apart by the absolute path of `rcfile`, so rcfiles sharing a name (such
+/// as the `config.fish` of two fish installations) keep separate histories.
+pub fn backup_rcfile_with_env(
+    rcfile: &Path,
+    retention: usize,
+    env: &dyn Env,
+) -> Result<PathBuf, ShellError> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let file_name = backup_file_name(rcfile)?;
+    let dir = Shell::rcfile_backup_dir_with_env(env)?;
+    std::fs::create_dir_all(&dir)?;
+
+    let mut timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    // Keep backups taken in quick succession, and in order, even on coarse clocks
+    if let Some(latest) = backup_timestamps(&dir, &file_name)?.first() {
+        timestamp = timestamp.max(latest.0 + 1);
+    }
+    let backup = dir.join(format!("{file_name}.{timestamp}"));
+    std::fs::copy(rcfile, &backup)?;
+    trace_event!(rcfile = %rcfile.display(), backup = %backup.display(), "backed up rcfile");
+
+    for (_, old) in backup_timestamps(&dir, &file_name)?
+        .into_iter()
+        .skip(retention.max(1))
+    {
+        std::fs::remove_file(old)?;
+    }
+
+    Ok(backup)
+}
+
+/// Returns the backups [`backup_rcfile`] made of `original`, most recent first.
+pub fn rcfile_backups(original: &Path) -> Result<Vec<PathBuf>, ShellError> {
+    rcfile_backups_with_env(original, &SystemEnv)
+}
+
+/// Like [`rcfile_backups`], but finds the backup directory through `env`.
+pub fn rcfile_backups_with_env(original: &Path, env: &dyn Env) -> Result<Vec<PathBuf>, ShellError> {
+    let dir = Shell::rcfile_backup_dir_with_env(env)?;
+    let backups = backup_timestamps(&dir, &backup_file_name(original)?)?;
+    Ok(backups.into_iter().map(|(_, backup)| backup).collect())
+}
+
+/// Overwrites `original` with one of its backups, `which` counting back from the most recent
+/// one (`0`). Returns the path of the backup that was restored.
+///
+/// The backups are left in place, so restoring can be repeated or undone by restoring a
+/// more recent one.
+///
+/// # Errors
+/// Returns `ShellError::NoSuchBackup` if `original` has no backup `which`.
+pub fn restore_backup(original: &Path, which: usize) -> Result<PathBuf, ShellError> {
+    restore_backup_with_env(original, which, &SystemEnv)
+}
+
+/// Like [`restore_backup`], but finds the backup directory through `env`.
+pub fn restore_backup_with_env(
+    original: &Path,
+    which: usize,
+    env: &dyn Env,
+) -> Result<PathBuf, ShellError> {
+    let backup = rcfile_backups_with_env(original, env)?
+        .into_iter()
+        .nth(which)
+        .ok_or_else(|| ShellError::NoSuchBackup(original.to_path_buf(), which))?;
+    write_atomic(original, &std::fs::read(&backup)?)?;
+    trace_event!(rcfile = %original.display(), backup = %backup.display(), "restored rcfile");
+    Ok(backup)
+}
+
+/// Returns the file name backups of `rcfile` are named after: its absolute path, with `%`,
+/// the path separators and `:` percent-encoded so it fits in a single file name.
+fn backup_file_name(rcfile: &Path) -> Result<String, ShellError> {
+    if rcfile.file_name().is_none() {
+        return Err(ShellError::InvalidPathEntry(rcfile.to_path_buf()));
+    }
+    let path = std::path::absolute(rcfile)?;
+
+    let mut name = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '%' => name.push_str("%25"),
+            '/' => name.push_str("%2F"),
+            '\\' => name.push_str("%5C"),
+            ':' => name.push_str("%3A"),
+            c => name.push(c),
+        }
+    }
+    Ok(name)
+}
+
+/// Returns the backups of `file_name` in `dir` with their timestamps, most recent first.
+fn backup_timestamps(dir: &Path, file_name: &str) -> Result<Vec<(u128, PathBuf)>, ShellError> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let prefix = format!("{file_name}.");
+    let mut backups = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let timestamp = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(&prefix))
+            .filter(|suffix| suffix.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|suffix| suffix.parse().ok());
+        if let Some(timestamp) = timestamp {
+            backups.push((timestamp, path));
+        }
+    }
+    backups.sort_by(|a, b| b.cmp(a));
+    Ok(backups)
+}
+
+/// Appends a line to an rcfile for as long as the guard is alive.
+///
+/// The line is appended when the guard is created and removed again when it is dropped,
+/// including during panic unwinding. This is mostly useful for tests and other scoped
+/// operations that need a temporary PATH entry.
+///
+/// If the rcfile already contains the line, nothing is appended and dropping the guard
+/// leaves the file alone. Otherwise only the last copy of the line is removed, so copies the
+/// user adds meanwhile survive.
+///
+/// # Example
+/// ```no_run
+/// use std::path::PathBuf;
+/// use what_the_path::shell::PathGuard;
+///
+/// let rcfile = PathBuf::from("/home/user/.bashrc");
+/// {
+///     let _guard = PathGuard::new(rcfile, "export PATH=\"/opt/tool/bin:$PATH\"").unwrap();
+///     // The line is present in ~/.bashrc here
+/// }
+/// // ...and gone here
+/// ```
+#[derive(Debug)]
+pub struct PathGuard {
+    rcfile: PathBuf,
+    line: String,
+    appended: bool,
+}
+
+impl PathGuard {
+    pub fn new(rcfile: PathBuf, line: &str) -> Result<PathGuard, ShellError> {
+        let appended = !rcfile_contains_line(&rcfile, line)?;
+        if appended {
+            append_to_rcfile(rcfile.clone(), line)?;
+        }
+        Ok(PathGuard {
+            rcfile,
+            line: line.to_string(),
+            appended,
+        })
+    }
+
+    pub fn rcfile(&self) -> &Path {
+        &self.rcfile
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+}
+
+impl Drop for PathGuard {
+    fn drop(&mut self) {
+        if !self.appended {
+            return;
+        }
+
+        let line = self.line.as_str();
+        let _ = modify_rcfile(&self.rcfile, |file| {
+            let mut lines: Vec<&str> = file.split_inclusive('\n').collect();
+            if let Some(last) = lines
+                .iter()
+                .rposition(|existing| without_line_ending(existing) == line)
+            {
+                lines.remove(last);
+            }
+            lines.concat()
+        });
+    }
 }
@@ -1,43 +1,96 @@
 #[cfg(test)]
 mod tests {
     use std::{
+        collections::HashMap,
         env,
+        ffi::OsString,
         path::{Path, PathBuf},
+        process::Command,
+        sync::{Mutex, MutexGuard},
     };
 
-    use what_the_path::shell::{exists_in_path, Bash, Fish, Zsh, POSIX};
+    use what_the_path::dirs::{cache_dir_with_env, runtime_dir_with_env};
+    use what_the_path::env::Env;
+    use what_the_path::error::ShellError;
+    use what_the_path::path::{
+        add_to_path, command_with_path, dedup_path, path_separator, path_would_exceed_limit,
+        remove_from_path, sanitize_path, Placement,
+    };
+    use what_the_path::report::path_report_with_env;
+    use what_the_path::shell::{
+        all_rcfiles_from_base, append_to_rcfile, append_to_rcfile_create,
+        append_to_rcfile_create_if_missing, append_to_rcfile_if_missing, append_to_rcfile_spaced,
+        append_to_rcfile_with, backup_rcfile_with_env, classify_rcfile_lines, clear_shell_aliases,
+        consolidate_path_exports, current_shell_primary_rcfile_with_env, dedup_rcfile_path_entries,
+        exists_in_path_with_env, exists_in_path_within, find_duplicate_path_exports,
+        fish_universal_var_from, fish_universal_var_with_env, get_home_dir_strict_with_env,
+        get_home_dir_with_env, home_dir_for_user, insert_after_line, insert_before_line,
+        invalidate_detection_cache, list_managed_blocks, modify_rcfile, modify_rcfile_with,
+        most_likely_shell_from, normalize_rcfile, parse_passwd_home, parse_passwd_shell,
+        powershell_for_module_path, rcfile_backups_with_env, rcfile_contains_line, rcfile_encoding,
+        rcfile_line_ending, register_shell_alias, remove_dir_from_path_export, remove_from_rcfile,
+        replace_line, replace_line_or_append, restore_backup_with_env, shell_for_rcfile,
+        unregister_shell_alias, AppendOutcome, Bash, DetectionProvider, DetectionSource,
+        EnvDetection, Fish, FollowSymlinks, LineEnding, LineKind, PathGuard, PowerShell,
+        RcFileEncoding, RcFileStats, Shell, WindowsPowerShell, Zsh, POSIX, SHELL_OVERRIDE_VAR,
+    };
+
+    // Tests share the process-wide detection cache, so any test invalidating it must hold this
+    // lock.
+    static DETECTION_CACHE_LOCK: Mutex<()> = Mutex::new(());
+
+    fn lock_detection_cache() -> MutexGuard<'static, ()> {
+        DETECTION_CACHE_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("what-the-path-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 
     #[test]
     fn test_does_path_exist() {
-        env::set_var("PATH", "/brother:/man");
+        let path = OsString::from("/brother:/man");
+
+        assert!(exists_in_path_within(Path::new("/brother"), &path));
+        assert!(exists_in_path_within(Path::new("/man"), &path));
 
-        assert!(exists_in_path(Path::new("/brother")));
-        assert!(exists_in_path(Path::new("/man")));
+        assert!(!exists_in_path_within(Path::new("/nonexistent"), &path));
 
-        assert!(!exists_in_path(Path::new("/nonexistent")));
+        assert!(!exists_in_path_within(
+            Path::new("/usr/bin"),
+            &OsString::new()
+        ));
 
-        env::set_var("PATH", "");
-        assert!(!exists_in_path(Path::new("/usr/bin")));
+        let env = HashMap::from([("PATH", "/brother:/man")]);
+        assert!(exists_in_path_with_env("/brother", &env));
+        assert!(exists_in_path_with_env("/man", &env));
+        assert!(!exists_in_path_with_env("/nonexistent", &env));
+
+        let env = HashMap::from([("PATH", "")]);
+        assert!(!exists_in_path_with_env("/usr/bin", &env));
     }
 
     #[test]
     fn test_posix_get_rcfiles() {
-        // Set test home
-        env::set_var("HOME", "/home/test");
+        let env = HashMap::from([("HOME", "/home/test")]);
 
         let posix = POSIX;
-        let rcfiles = posix.get_rcfiles().unwrap();
+        let rcfiles = posix.get_rcfiles_with_env(&env).unwrap();
         assert_eq!(rcfiles.len(), 1);
         assert_eq!(rcfiles[0], PathBuf::from("/home/test/.profile"));
     }
 
     #[test]
     fn test_bash_get_rcfiles() {
-        // Set test home
-        env::set_var("HOME", "/home/test");
+        let env = HashMap::from([("HOME", "/home/test")]);
 
         let bash = Bash;
-        let rcfiles = bash.get_rcfiles().unwrap();
+        let rcfiles = bash.get_rcfiles_with_env(&env).unwrap();
         assert_eq!(rcfiles.len(), 3);
         assert!(rcfiles.contains(&PathBuf::from("/home/test/.bash_profile")));
         assert!(rcfiles.contains(&PathBuf::from("/home/test/.bash_login")));
@@ -47,43 +100,3396 @@ mod tests {
     #[test]
     fn test_fish_rcfiles() {
         // Test with XDG_CONFIG_HOME
-        env::set_var("XDG_CONFIG_HOME", "/custom/xdg");
+        let env = HashMap::from([("XDG_CONFIG_HOME", "/custom/xdg"), ("HOME", "/home/test")]);
         let fish = Fish;
-        let rcfiles = fish.get_rcfiles().unwrap();
-        assert!(rcfiles.contains(&PathBuf::from("/custom/xdg/.config/fish/conf.d")));
+        let rcfiles = fish.get_rcfiles_with_env(&env).unwrap();
+        assert!(rcfiles.contains(&PathBuf::from("/custom/xdg/fish/conf.d")));
+        assert!(rcfiles.contains(&PathBuf::from("/custom/xdg/fish/config.fish")));
 
         // Test with HOME only
-        env::remove_var("XDG_CONFIG_HOME");
-        env::set_var("HOME", "/home/test");
-        let rcfiles = fish.get_rcfiles().unwrap();
-        
+        let env = HashMap::from([("HOME", "/home/test")]);
+        let rcfiles = fish.get_rcfiles_with_env(&env).unwrap();
+
         assert!(rcfiles.contains(&PathBuf::from("/home/test/.config/fish/conf.d")));
+        assert!(rcfiles.contains(&PathBuf::from("/home/test/.config/fish/config.fish")));
     }
 
     #[test]
     fn test_zsh_rcfiles() {
-        // Skip if zsh not available
         let zsh = Zsh;
-        if !zsh.does_exist() {
-            return;
-        }
 
         // Test with custom ZDOTDIR
-        let test_dir = "/custom/zsh/dir";
-        env::set_var("ZDOTDIR", test_dir);
-        let rcfiles = zsh.get_rcfiles().unwrap();
-        assert!(rcfiles.contains(&PathBuf::from("/custom/zsh/dir/.zshenv")));
+        let test_dir = temp_dir("zsh-rcfiles");
+        std::fs::write(test_dir.join(".zshenv"), "").unwrap();
+        let env = HashMap::from([("ZDOTDIR", &test_dir)]);
+        let rcfiles = zsh.get_rcfiles_with_env(&env).unwrap();
+        assert!(rcfiles.contains(&test_dir.join(".zshenv")));
 
+        let env = HashMap::from([("ZDOTDIR", "/nonexistent/zsh/dir")]);
+        assert!(matches!(
+            zsh.get_rcfiles_with_env(&env),
+            Err(ShellError::EmptyHomeAndZdotdir)
+        ));
     }
 
     #[test]
     fn test_rcfiles_with_no_home() {
-        // Remove HOME var
-        env::remove_var("HOME");
+        let env = HashMap::<&str, &str>::new();
 
         let bash = Bash;
         let posix = POSIX;
-        assert!(posix.get_rcfiles().is_err());
-        assert!(bash.get_rcfiles().is_err());
+        assert!(posix.get_rcfiles_with_env(&env).is_err());
+        assert!(bash.get_rcfiles_with_env(&env).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_spawn_shell() {
+        let posix = Shell::POSIX(POSIX);
+        let mut child = posix.spawn_with_args(false, &["-c", "exit 0"]).unwrap();
+        assert!(child.wait().unwrap().success());
+
+        let mut child = posix.spawn_with_args(false, &["-c", "exit 3"]).unwrap();
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+
+        let bash = Shell::Bash(Bash);
+        if bash.does_exist() {
+            assert_eq!(bash.binary_name(), "bash");
+            let mut child = bash.spawn_with_args(false, &["-c", "exit 0"]).unwrap();
+            assert!(child.wait().unwrap().success());
+        }
+    }
+
+    #[test]
+    fn test_remove_all_duplicate_lines() {
+        let dir = temp_dir("remove-duplicates");
+        let rcfile = dir.join(".bashrc");
+        let line = "export PATH=\"$HOME/.tool/bin:$PATH\"";
+        std::fs::write(
+            &rcfile,
+            format!("alias ll='ls -l'\n{line}\n{line}\nset -o vi\n{line}\n"),
+        )
+        .unwrap();
+
+        assert_eq!(remove_from_rcfile(rcfile.clone(), line).unwrap(), 3);
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "alias ll='ls -l'\nset -o vi\n"
+        );
+        assert_eq!(remove_from_rcfile(rcfile.clone(), line).unwrap(), 0);
+
+        // Lines that merely contain `line` are kept whole
+        let longer = format!("[ -d ~/.tool ] && {line} # tool");
+        std::fs::write(&rcfile, format!("{longer}\n{line}\r\n# {line}\n")).unwrap();
+        assert_eq!(remove_from_rcfile(rcfile.clone(), line).unwrap(), 1);
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("{longer}\n# {line}\n")
+        );
+    }
+
+    #[test]
+    fn test_path_guard() {
+        let dir = temp_dir("path-guard");
+        let rcfile = dir.join(".profile");
+        let line = "export PATH=\"/opt/guard/bin:$PATH\"";
+        std::fs::write(&rcfile, "umask 022\n").unwrap();
+
+        {
+            let guard = PathGuard::new(rcfile.clone(), line).unwrap();
+            assert_eq!(guard.rcfile(), rcfile);
+            assert!(std::fs::read_to_string(&rcfile).unwrap().contains(line));
+        }
+        assert_eq!(std::fs::read_to_string(&rcfile).unwrap(), "umask 022\n");
+
+        let result = std::panic::catch_unwind(|| {
+            let _guard = PathGuard::new(rcfile.clone(), line).unwrap();
+            assert!(std::fs::read_to_string(&rcfile).unwrap().contains(line));
+            panic!("unwinding with an active guard");
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&rcfile).unwrap(), "umask 022\n");
+
+        // A line the user already had is left alone
+        let existing = format!("{line}\numask 022\n");
+        std::fs::write(&rcfile, &existing).unwrap();
+        drop(PathGuard::new(rcfile.clone(), line).unwrap());
+        assert_eq!(std::fs::read_to_string(&rcfile).unwrap(), existing);
+
+        // Only the guard's own copy goes, even if the user adds another meanwhile
+        std::fs::write(&rcfile, "umask 022\n").unwrap();
+        {
+            let _guard = PathGuard::new(rcfile.clone(), line).unwrap();
+            std::fs::write(&rcfile, format!("{line}\numask 022\n{line}\n")).unwrap();
+        }
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("{line}\numask 022\n")
+        );
+    }
+
+    #[test]
+    fn test_append_managed_block() {
+        let dir = temp_dir("managed-block");
+        let rcfile = dir.join("config.fish");
+        std::fs::write(&rcfile, "").unwrap();
+
+        let shell = Shell::Fish(Fish);
+        shell
+            .append_managed_block(&rcfile, "mytool", "fish_add_path /opt/mytool/bin\n")
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "# >>> mytool >>>\nfish_add_path /opt/mytool/bin\n# <<< mytool <<<\n"
+        );
+    }
+
+    #[test]
+    fn test_detect_by_shell_var_matches_file_name() {
+        let detect = |shell| {
+            Shell::detect_by_shell_var_with_env(&HashMap::from([("SHELL", shell)])).unwrap()
+        };
+
+        assert!(matches!(
+            detect("/opt/bin/bash-completion-helper"),
+            Shell::POSIX(_)
+        ));
+        assert!(matches!(detect("/usr/local/bin/bash"), Shell::Bash(_)));
+        assert!(matches!(detect("/usr/bin/bash5"), Shell::Bash(_)));
+        assert!(matches!(detect("/home/me/bash-tools/zsh"), Shell::Zsh(_)));
+    }
+
+    #[test]
+    fn test_install_snippet() {
+        let dirs = [
+            PathBuf::from("/opt/tool/bin"),
+            PathBuf::from("/opt/tool/sbin"),
+        ];
+
+        assert_eq!(
+            Shell::Bash(Bash).install_snippet(&dirs, Placement::Prepend, None),
+            [
+                "case \":${PATH}:\" in",
+                "    *:\"/opt/tool/sbin\":*) ;;",
+                "    *) export PATH=\"/opt/tool/sbin:$PATH\" ;;",
+                "esac",
+                "case \":${PATH}:\" in",
+                "    *:\"/opt/tool/bin\":*) ;;",
+                "    *) export PATH=\"/opt/tool/bin:$PATH\" ;;",
+                "esac",
+            ]
+            .join("\n")
+        );
+
+        assert_eq!(
+            Shell::POSIX(POSIX).install_snippet(
+                &dirs[..1],
+                Placement::Prepend,
+                Some(Path::new("/opt/tool/env"))
+            ),
+            [
+                "case \":${PATH}:\" in",
+                "    *:\"/opt/tool/bin\":*) ;;",
+                "    *) export PATH=\"/opt/tool/bin:$PATH\" ;;",
+                "esac",
+                ". \"/opt/tool/env\"",
+            ]
+            .join("\n")
+        );
+
+        assert_eq!(
+            Shell::Fish(Fish).install_snippet(
+                &dirs,
+                Placement::Prepend,
+                Some(Path::new("/opt/tool/env.fish"))
+            ),
+            "fish_add_path /opt/tool/bin /opt/tool/sbin\nsource /opt/tool/env.fish"
+        );
+    }
+
+    #[test]
+    fn test_parse_passwd_home() {
+        let passwd = "\
+# local accounts
+root:x:0:0:root:/root:/bin/bash
+alice:x:1000:1000:Alice,,,:/home/alice:/usr/bin/zsh
+bob:x:1001:1001::/srv/bob:/usr/bin/fish
+broken:line
+";
+
+        assert_eq!(
+            parse_passwd_home(passwd, "alice"),
+            Some(PathBuf::from("/home/alice"))
+        );
+        assert_eq!(
+            parse_passwd_home(passwd, "bob"),
+            Some(PathBuf::from("/srv/bob"))
+        );
+        assert_eq!(parse_passwd_home(passwd, "broken"), None);
+        assert_eq!(parse_passwd_home(passwd, "carol"), None);
+    }
+
+    #[test]
+    fn test_home_dir_for_user() {
+        if !Path::new("/etc/passwd").exists() {
+            return;
+        }
+
+        assert!(home_dir_for_user("root").is_some());
+        assert_eq!(home_dir_for_user("no-such-user-what-the-path"), None);
+    }
+
+    #[test]
+    fn test_get_home_dir_fallback() {
+        struct UserDatabase;
+
+        impl Env for UserDatabase {
+            fn var_os(&self, _key: &str) -> Option<OsString> {
+                None
+            }
+
+            fn fallback_home_dir(&self) -> Option<PathBuf> {
+                Some(PathBuf::from("/srv/homes/alice"))
+            }
+        }
+
+        assert_eq!(
+            get_home_dir_with_env(&UserDatabase).unwrap(),
+            PathBuf::from("/srv/homes/alice")
+        );
+        assert!(matches!(
+            get_home_dir_with_env(&HashMap::<&str, &str>::new()),
+            Err(ShellError::NoHomeDir)
+        ));
+        // $HOME still wins
+        assert_eq!(
+            get_home_dir_with_env(&HashMap::from([("HOME", "/home/alice")])).unwrap(),
+            PathBuf::from("/home/alice")
+        );
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_add_to_path() {
+        let path = OsString::from("/usr/bin:/bin");
+
+        assert_eq!(
+            add_to_path(&path, Path::new("/opt/tool/bin"), Placement::Prepend).unwrap(),
+            "/opt/tool/bin:/usr/bin:/bin"
+        );
+        assert_eq!(
+            add_to_path(&path, Path::new("/opt/tool/bin"), Placement::Append).unwrap(),
+            "/usr/bin:/bin:/opt/tool/bin"
+        );
+        assert!(!path_would_exceed_limit(&OsString::from(
+            "/a".repeat(50_000)
+        )));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_add_to_path_too_long() {
+        use what_the_path::path::WINDOWS_PATH_LIMIT;
+
+        // `C:\` and `;C:\b` take up 8 units, so appending `C:\b` reaches the limit exactly
+        let path = OsString::from(format!("C:\\{}", "a".repeat(WINDOWS_PATH_LIMIT - 8)));
+
+        assert!(add_to_path(&path, Path::new("C:\\b"), Placement::Append).is_ok());
+        assert!(matches!(
+            add_to_path(&path, Path::new("C:\\tool\\bin"), Placement::Append),
+            Err(ShellError::PathTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_exceeds_windows_path_limit() {
+        use what_the_path::path::{exceeds_windows_path_limit, WINDOWS_PATH_LIMIT};
+
+        assert!(!exceeds_windows_path_limit(&OsString::from(
+            "a".repeat(WINDOWS_PATH_LIMIT)
+        )));
+        assert!(exceeds_windows_path_limit(&OsString::from(
+            "a".repeat(WINDOWS_PATH_LIMIT + 1)
+        )));
+
+        // Characters outside the BMP count as two units, those inside it as one
+        assert!(exceeds_windows_path_limit(&OsString::from(
+            "\u{1d11e}".repeat(WINDOWS_PATH_LIMIT / 2 + 1)
+        )));
+        assert!(!exceeds_windows_path_limit(&OsString::from(
+            "\u{e9}".repeat(WINDOWS_PATH_LIMIT)
+        )));
+    }
+
+    #[test]
+    fn test_all_rcfiles_from_base() {
+        let base = temp_dir("all-rcfiles");
+        std::fs::write(base.join(".profile"), "").unwrap();
+        std::fs::write(base.join(".bashrc"), "").unwrap();
+        std::fs::write(base.join(".zshenv"), "").unwrap();
+        std::fs::create_dir_all(base.join(".config/fish/conf.d")).unwrap();
+
+        let shells = [Shell::Bash(Bash), Shell::POSIX(POSIX)];
+        let rcfiles = all_rcfiles_from_base(&shells, &base);
+
+        assert_eq!(rcfiles.len(), 2);
+        assert!(matches!(rcfiles[0].0, Shell::Bash(_)));
+        assert_eq!(rcfiles[0].1, base.join(".bashrc"));
+        assert!(matches!(rcfiles[1].0, Shell::POSIX(_)));
+        assert_eq!(rcfiles[1].1, base.join(".profile"));
+
+        let rcfiles = all_rcfiles_from_base(&Shell::all(), &base);
+        assert_eq!(rcfiles.len(), 4);
+        assert!(rcfiles
+            .iter()
+            .any(|(shell, rcfile)| matches!(shell, Shell::Fish(_))
+                && *rcfile == base.join(".config/fish/conf.d")));
+    }
+
+    #[test]
+    fn test_current_shell_primary_rcfile() {
+        let cases = [
+            ("/bin/sh", "/home/test/.profile"),
+            ("/bin/bash", "/home/test/.bashrc"),
+            ("/usr/bin/zsh", "/home/test/.zshenv"),
+            ("/usr/bin/fish", "/home/test/.config/fish/config.fish"),
+        ];
+        for (shell, expected) in cases {
+            let env = HashMap::from([
+                ("SHELL", shell),
+                ("HOME", "/home/test"),
+                ("XDG_CONFIG_HOME", "/home/test/.config"),
+            ]);
+            assert_eq!(
+                current_shell_primary_rcfile_with_env(&env).unwrap(),
+                PathBuf::from(expected)
+            );
+        }
+
+        let env = HashMap::from([
+            ("SHELL", "/usr/bin/zsh"),
+            ("HOME", "/home/test"),
+            ("ZDOTDIR", "/home/test/.config/zsh"),
+        ]);
+        assert_eq!(
+            current_shell_primary_rcfile_with_env(&env).unwrap(),
+            PathBuf::from("/home/test/.config/zsh/.zshenv")
+        );
+    }
+
+    #[test]
+    fn test_script_env_files() {
+        let mut env = HashMap::from([
+            ("HOME", "/home/test"),
+            ("BASH_ENV", "/home/test/.bash_env"),
+            ("ENV", "/home/test/.shrc"),
+        ]);
+
+        let bash = Shell::Bash(Bash);
+        assert_eq!(bash.get_rcfiles_with_env(&env).unwrap().len(), 3);
+        assert_eq!(
+            bash.script_env_file_with_env(&env),
+            Some(PathBuf::from("/home/test/.bash_env"))
+        );
+        let rcfiles = bash.get_rcfiles_with_script_env_with_env(&env).unwrap();
+        assert_eq!(rcfiles.len(), 4);
+        assert_eq!(rcfiles[3], PathBuf::from("/home/test/.bash_env"));
+
+        let posix = Shell::POSIX(POSIX);
+        assert_eq!(
+            posix.get_rcfiles_with_script_env_with_env(&env).unwrap(),
+            [
+                PathBuf::from("/home/test/.profile"),
+                PathBuf::from("/home/test/.shrc")
+            ]
+        );
+
+        assert_eq!(
+            posix.init_order_with_env(&env).unwrap(),
+            [
+                PathBuf::from("/home/test/.profile"),
+                PathBuf::from("/home/test/.shrc")
+            ]
+        );
+
+        env.remove("BASH_ENV");
+        assert_eq!(bash.script_env_file_with_env(&env), None);
+        assert_eq!(Shell::Fish(Fish).script_env_file_with_env(&env), None);
+    }
+
+    #[test]
+    fn test_replace_line() {
+        let dir = temp_dir("replace-line");
+        let rcfile = dir.join(".bashrc");
+        let old = "export PATH=\"/opt/tool-1.0/bin:$PATH\"";
+        let new = "export PATH=\"/opt/tool-2.0/bin:$PATH\"";
+
+        std::fs::write(&rcfile, format!("set -o vi\n{old}\nalias ll='ls -l'\n")).unwrap();
+        assert!(replace_line(&rcfile, old, new).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("set -o vi\n{new}\nalias ll='ls -l'\n")
+        );
+
+        // Not found, nothing appended
+        assert!(!replace_line(&rcfile, old, new).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("set -o vi\n{new}\nalias ll='ls -l'\n")
+        );
+
+        // Not found, appended on miss
+        let newer = "export PATH=\"/opt/tool-3.0/bin:$PATH\"";
+        assert!(!replace_line_or_append(&rcfile, old, newer).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("set -o vi\n{new}\nalias ll='ls -l'\n{newer}\n")
+        );
+
+        // CRLF lines match too, and keep their line ending
+        std::fs::write(&rcfile, format!("set -o vi\r\n{old}\r\n")).unwrap();
+        assert!(rcfile_contains_line(&rcfile, old).unwrap());
+        assert!(rcfile_contains_line(&rcfile, &format!("{old}\r\n")).unwrap());
+        assert!(replace_line(&rcfile, &format!("{old}\n"), new).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("set -o vi\r\n{new}\r\n")
+        );
+    }
+
+    #[test]
+    fn test_rcfile_bom_is_preserved() {
+        let dir = temp_dir("bom");
+        let rcfile = dir.join(".profile");
+        let line = "export PATH=\"/opt/tool/bin:$PATH\"";
+        std::fs::write(&rcfile, format!("\u{feff}{line}\numask 022\n")).unwrap();
+
+        assert_eq!(
+            rcfile_encoding(&rcfile).unwrap(),
+            RcFileEncoding::Utf8WithBom
+        );
+
+        let new = "export PATH=\"/opt/tool-2/bin:$PATH\"";
+        assert!(replace_line(&rcfile, line, new).unwrap());
+        assert_eq!(
+            std::fs::read(&rcfile).unwrap(),
+            format!("\u{feff}{new}\numask 022\n").as_bytes()
+        );
+
+        append_to_rcfile(rcfile.clone(), line).unwrap();
+        assert_eq!(
+            remove_from_rcfile(rcfile.clone(), &format!("{new}\n")).unwrap(),
+            1
+        );
+        assert_eq!(
+            std::fs::read(&rcfile).unwrap(),
+            format!("\u{feff}umask 022\n{line}\n").as_bytes()
+        );
+
+        std::fs::write(&rcfile, "umask 022\n").unwrap();
+        assert_eq!(rcfile_encoding(&rcfile).unwrap(), RcFileEncoding::Utf8);
+    }
+
+    #[test]
+    fn test_detect_from_env_map() {
+        let detect = |shell: &str| {
+            let env = HashMap::from([("SHELL".to_string(), shell.to_string())]);
+            Shell::detect_from_env_map(&env).unwrap()
+        };
+
+        assert!(matches!(detect("/usr/bin/zsh"), Shell::Zsh(_)));
+        assert!(matches!(detect("/bin/bash"), Shell::Bash(_)));
+        assert!(matches!(detect("/opt/homebrew/bin/fish"), Shell::Fish(_)));
+        assert!(matches!(detect("/bin/dash"), Shell::POSIX(_)));
+
+        // Login shells carry a leading dash
+        assert!(matches!(detect("-bash"), Shell::Bash(_)));
+        assert!(matches!(detect("-zsh"), Shell::Zsh(_)));
+        assert!(matches!(detect("-fish"), Shell::Fish(_)));
+        assert!(matches!(detect("-sh"), Shell::POSIX(_)));
+
+        assert!(matches!(
+            Shell::detect_from_env_map(&HashMap::new()),
+            Err(ShellError::NoShellVar)
+        ));
+    }
+
+    #[test]
+    fn test_classify_rcfile_lines() {
+        let dir = temp_dir("classify-lines");
+        let rcfile = dir.join(".zshrc");
+        std::fs::write(
+            &rcfile,
+            "# my zshrc\n\
+             export PATH=\"$HOME/.local/bin:$PATH\"\n\
+             \n\
+             source ~/.aliases\n\
+             . \"$HOME/.cargo/env\"\n\
+             path+=(/opt/tool/bin)\n\
+             set -gx PATH /opt/fish/bin $PATH\n\
+             fish_add_path /opt/fish/bin\n\
+             alias ll='ls -l'\n",
+        )
+        .unwrap();
+
+        let kinds: Vec<LineKind> = classify_rcfile_lines(&rcfile)
+            .unwrap()
+            .into_iter()
+            .map(|(_, kind)| kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                LineKind::Comment,
+                LineKind::PathExport,
+                LineKind::Blank,
+                LineKind::Source,
+                LineKind::Source,
+                LineKind::PathExport,
+                LineKind::PathExport,
+                LineKind::PathExport,
+                LineKind::Other,
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn test_append_emits_tracing_event() {
+        use std::sync::Arc;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        struct Fields<'a>(&'a mut Vec<String>);
+
+        impl Visit for Fields<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0.push(format!("{}={:?}", field.name(), value));
+            }
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _: &Attributes<'_>) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Vec::new();
+                event.record(&mut Fields(&mut fields));
+                self.0.lock().unwrap().push(fields.join(" "));
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let dir = temp_dir("tracing");
+        let rcfile = dir.join(".profile");
+        std::fs::write(&rcfile, "").unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Recorder(events.clone()), || {
+            append_to_rcfile(rcfile.clone(), "export PATH=\"/opt/bin:$PATH\"").unwrap();
+        });
+
+        {
+            let events = events.lock().unwrap();
+            assert_eq!(events.len(), 1);
+            assert!(events[0].contains("message=appending line to rcfile"));
+            assert!(events[0].contains(&format!("rcfile={}", rcfile.display())));
+        }
+
+        let env = HashMap::from([("SHELL", "/usr/bin/fish")]);
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Recorder(recorded.clone()), || {
+            Shell::detect_with(&EnvDetection::new(&env)).unwrap();
+            append_to_rcfile_if_missing(&rcfile, "export PATH=\"/opt/bin:$PATH\"").unwrap();
+            assert_eq!(remove_from_rcfile(rcfile.clone(), "missing").unwrap(), 0);
+        });
+
+        let recorded = recorded.lock().unwrap();
+        assert!(recorded
+            .iter()
+            .any(|event| event.contains("message=detected shell") && event.contains("ShellVar")));
+        assert!(recorded
+            .iter()
+            .any(|event| event.contains("message=line already in rcfile")));
+        assert!(recorded
+            .iter()
+            .any(|event| event.contains("message=removed line from rcfile")
+                && event.contains("count=0")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_offline_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let env = HashMap::from([("SHELL", "/usr/bin/zsh")]);
+        assert!(matches!(
+            Shell::detect_offline_with_env(&env).unwrap(),
+            Shell::Zsh(_)
+        ));
+        assert!(matches!(
+            Shell::detect_offline_with_env(&HashMap::from([(SHELL_OVERRIDE_VAR, "fish")])),
+            Ok(Shell::Fish(_))
+        ));
+
+        // Shells installed on PATH aren't probed
+        let path = HashMap::from([("PATH", "/bin:/usr/bin")]);
+        assert!(matches!(
+            Shell::detect_offline_with_env(&path),
+            Err(ShellError::NoShellVar)
+        ));
+        assert!(Shell::Zsh(Zsh).requires_subprocess_for_detection());
+        assert!(!Shell::Bash(Bash).requires_subprocess_for_detection());
+
+        let bin = temp_dir("offline-bin");
+        let fish = bin.join("fish");
+        std::fs::write(&fish, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&fish, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let env = HashMap::from([("SHELL", Path::new("/bin/sh")), ("PATH", bin.as_path())]);
+        assert!(Shell::Fish(Fish).does_exist_with_env(&env));
+        assert!(!Shell::Bash(Bash).does_exist_with_env(&env));
+        assert!(Shell::POSIX(POSIX).does_exist_with_env(&env));
+
+        // $SHELL has to name the binary itself, not just contain its name
+        let empty = temp_dir("offline-empty-bin");
+        for shell in [
+            "/usr/bin/zsh-wrapper",
+            "/opt/bashful/sh",
+            "/usr/bin/catfish",
+        ] {
+            let env = HashMap::from([("SHELL", Path::new(shell)), ("PATH", empty.as_path())]);
+            assert!(!Shell::Zsh(Zsh).does_exist_with_env(&env));
+            assert!(!Shell::Bash(Bash).does_exist_with_env(&env));
+            assert!(!Shell::Fish(Fish).does_exist_with_env(&env));
+        }
+        let env = HashMap::from([
+            ("SHELL", Path::new("/usr/bin/zsh")),
+            ("PATH", empty.as_path()),
+        ]);
+        assert!(Shell::Zsh(Zsh).does_exist_with_env(&env));
+    }
+
+    #[test]
+    fn test_annotated_lines() {
+        let dir = temp_dir("annotated");
+        let rcfile = dir.join(".bashrc");
+        std::fs::write(&rcfile, "set -o vi\n").unwrap();
+
+        let shell = Shell::Bash(Bash);
+        shell
+            .append_to_rcfile_annotated(&rcfile, "export PATH=\"/opt/a:$PATH\"", "added by mytool")
+            .unwrap();
+        append_to_rcfile(rcfile.clone(), "alias ll='ls -l'").unwrap();
+        shell
+            .append_to_rcfile_annotated(&rcfile, "export PATH=\"/opt/b:$PATH\"", "added by mytool")
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "set -o vi\n\
+             export PATH=\"/opt/a:$PATH\" # added by mytool\n\
+             alias ll='ls -l'\n\
+             export PATH=\"/opt/b:$PATH\" # added by mytool\n"
+        );
+
+        assert_eq!(
+            shell
+                .remove_annotated_lines(&rcfile, "added by mytool")
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "set -o vi\nalias ll='ls -l'\n"
+        );
+        assert_eq!(
+            shell
+                .remove_annotated_lines(&rcfile, "added by mytool")
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_find_duplicate_path_exports() {
+        let dir = temp_dir("duplicate-exports");
+        let rcfile = dir.join(".bashrc");
+        std::fs::write(
+            &rcfile,
+            "export PATH=\"$HOME/.cargo/bin:$PATH\"\n\
+             export PATH=\"/opt/go/bin:$PATH\"\n\
+             alias ll='ls -l'\n\
+             export PATH=\"$HOME/.local/bin:$HOME/.cargo/bin:$PATH\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            find_duplicate_path_exports(&rcfile).unwrap(),
+            [
+                (1, "export PATH=\"$HOME/.cargo/bin:$PATH\"".to_string()),
+                (
+                    4,
+                    "export PATH=\"$HOME/.local/bin:$HOME/.cargo/bin:$PATH\"".to_string()
+                ),
+            ]
+        );
+
+        std::fs::write(&rcfile, "export PATH=\"/opt/go/bin:$PATH\"\n").unwrap();
+        assert!(find_duplicate_path_exports(&rcfile).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_config_home() {
+        let mut env = HashMap::from([
+            ("HOME", "/home/test"),
+            ("XDG_CONFIG_HOME", "/home/test/.xdg"),
+        ]);
+
+        let home = PathBuf::from("/home/test");
+        assert_eq!(
+            Shell::POSIX(POSIX).config_home_with_env(&env).unwrap(),
+            home
+        );
+        assert_eq!(Shell::Bash(Bash).config_home_with_env(&env).unwrap(), home);
+        assert_eq!(Shell::Zsh(Zsh).config_home_with_env(&env).unwrap(), home);
+        assert_eq!(
+            Shell::Fish(Fish).config_home_with_env(&env).unwrap(),
+            PathBuf::from("/home/test/.xdg/fish")
+        );
+
+        env.insert("ZDOTDIR", "/home/test/.xdg/zsh");
+        assert_eq!(
+            Shell::Zsh(Zsh).config_home_with_env(&env).unwrap(),
+            PathBuf::from("/home/test/.xdg/zsh")
+        );
+
+        env.remove("HOME");
+        assert!(matches!(
+            Shell::Bash(Bash).config_home_with_env(&env),
+            Err(ShellError::NoHomeDir)
+        ));
+    }
+
+    #[test]
+    fn test_fish_config_fish_path() {
+        let config = temp_dir("config-fish");
+        let env = HashMap::from([("XDG_CONFIG_HOME", &config)]);
+
+        let path = Fish.config_fish_path_with_env(&env).unwrap();
+        assert_eq!(path, config.join("fish/config.fish"));
+
+        append_to_rcfile_create(path.clone(), "fish_add_path /opt/tool/bin").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!(
+                "{}fish_add_path /opt/tool/bin\n",
+                Shell::Fish(Fish).rcfile_template()
+            )
+        );
+    }
+
+    #[test]
+    fn test_normalize_rcfile() {
+        let dir = temp_dir("normalize");
+        let rcfile = dir.join(".bashrc");
+        std::fs::write(
+            &rcfile,
+            "set -o vi\n\n\n\nalias ll='ls -l'\n\n  \nexport EDITOR=vim\n\n\n",
+        )
+        .unwrap();
+
+        normalize_rcfile(&rcfile).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "set -o vi\n\nalias ll='ls -l'\n\nexport EDITOR=vim\n"
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        normalize_rcfile(&rcfile).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "set -o vi\n\nalias ll='ls -l'\n\nexport EDITOR=vim\n"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_path_report() {
+        let home = temp_dir("path-report");
+        std::fs::write(home.join(".bashrc"), "").unwrap();
+        std::fs::create_dir_all(home.join("bin")).unwrap();
+
+        let path = OsString::from(format!("/usr/bin:{}/bin:/bin:/usr/bin", home.display()));
+        let env = HashMap::from([
+            ("SHELL", OsString::from("/bin/bash")),
+            ("HOME", home.clone().into_os_string()),
+            ("PATH", path.clone()),
+        ]);
+
+        let report = path_report_with_env(&home.join("bin/../bin"), &env);
+        assert!(matches!(report.shell, Some(Shell::Bash(_))));
+        assert_eq!(report.rcfiles, [home.join(".bashrc")]);
+        assert!(report.target_on_path);
+        assert_eq!(report.duplicate_entries, [PathBuf::from("/usr/bin")]);
+
+        assert!(!path_report_with_env(Path::new("/nonexistent/bin"), &env).target_on_path);
+
+        // Empty entries are dropped rather than reported as duplicates
+        let env = HashMap::from([
+            ("SHELL", OsString::from("/bin/bash")),
+            ("HOME", home.clone().into_os_string()),
+            ("PATH", OsString::from("/a::/b::/a")),
+        ]);
+        assert_eq!(
+            path_report_with_env(&home, &env).duplicate_entries,
+            [PathBuf::from("/a")]
+        );
+        assert_eq!(
+            dedup_path(&path).unwrap(),
+            OsString::from(format!("/usr/bin:{}/bin:/bin", home.display()))
+        );
+    }
+
+    #[test]
+    fn test_fish_user_paths_from_variables() {
+        let dir = temp_dir("fish-user-paths-variables");
+        let env = HashMap::from([
+            ("HOME", dir.clone()),
+            ("XDG_CONFIG_HOME", dir.join("config")),
+        ]);
+        assert_eq!(
+            Fish.user_paths_with_env(&env).unwrap(),
+            Vec::<PathBuf>::new()
+        );
+
+        std::fs::create_dir_all(dir.join("config/fish")).unwrap();
+        std::fs::write(
+            dir.join("config/fish/fish_variables"),
+            "# VERSION: 3.0\nSETUVAR --path fish_user_paths:/opt/tool/bin\\x1e/home/me/my\\x20bin\n",
+        )
+        .unwrap();
+        assert_eq!(
+            Fish.user_paths_with_env(&env).unwrap(),
+            vec![
+                PathBuf::from("/opt/tool/bin"),
+                PathBuf::from("/home/me/my bin")
+            ]
+        );
+        assert_eq!(
+            fish_universal_var_with_env("fish_user_paths", &env)
+                .unwrap()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_fish_user_paths() {
+        let fish = Fish;
+        if Command::new("fish").arg("--version").output().is_err() {
+            return;
+        }
+
+        // Keep the universal variables away from the real configuration
+        let dir = temp_dir("fish-user-paths");
+        let env = HashMap::from([
+            ("HOME", dir.clone()),
+            ("XDG_CONFIG_HOME", dir.join("config")),
+        ]);
+        fish.add_user_path_with_env(&dir, &env).unwrap();
+        fish.add_user_path_with_env(&dir, &env).unwrap();
+        let user_paths = fish.user_paths_with_env(&env).unwrap();
+        assert_eq!(user_paths.iter().filter(|path| **path == dir).count(), 1);
+
+        fish.remove_user_path_with_env(&dir, &env).unwrap();
+        assert!(!fish.user_paths_with_env(&env).unwrap().contains(&dir));
+    }
+
+    #[test]
+    fn test_unsupported_operation() {
+        let error = Shell::Bash(Bash).user_paths().unwrap_err();
+        assert!(matches!(
+            error,
+            ShellError::UnsupportedOperation {
+                shell: "bash",
+                operation: "user_paths"
+            }
+        ));
+        assert_eq!(error.to_string(), "bash does not support user_paths");
+        assert!(error.hint().unwrap().contains("write_path_export"));
+
+        assert!(matches!(
+            Shell::POSIX(POSIX).add_user_path(Path::new("/opt/bin")),
+            Err(ShellError::UnsupportedOperation { shell: "sh", .. })
+        ));
+    }
+
+    #[test]
+    fn test_modify_rcfile() {
+        let dir = temp_dir("modify");
+        let rcfile = dir.join(".profile");
+        std::fs::write(&rcfile, "umask 022\nexport editor=vim\n").unwrap();
+
+        modify_rcfile(&rcfile, |content| content.to_uppercase()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "UMASK 022\nEXPORT EDITOR=VIM\n"
+        );
+        // Only the rcfile remains, the temporary file was renamed into place
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        assert!(matches!(
+            modify_rcfile(&dir.join("missing"), |content| content),
+            Err(ShellError::RcFileError(_))
+        ));
+    }
+
+    #[derive(Default)]
+    struct FakeDetection {
+        parent_process: Option<&'static str>,
+        shell_var: Option<&'static str>,
+        login_shell: Option<&'static str>,
+        files: &'static [&'static str],
+        installed: &'static [Shell],
+    }
+
+    impl DetectionProvider for FakeDetection {
+        fn parent_process(&self) -> Option<String> {
+            self.parent_process.map(String::from)
+        }
+
+        fn shell_var(&self) -> Option<String> {
+            self.shell_var.map(String::from)
+        }
+
+        fn login_shell(&self) -> Option<String> {
+            self.login_shell.map(String::from)
+        }
+
+        fn installed_shells(&self) -> Vec<Shell> {
+            self.installed.to_vec()
+        }
+
+        fn file_exists(&self, path: &Path) -> bool {
+            self.files.iter().any(|file| Path::new(file) == path)
+        }
+    }
+
+    #[test]
+    fn test_detect_verbose_sources() {
+        let all = FakeDetection {
+            parent_process: Some("-fish"),
+            shell_var: Some("/bin/zsh"),
+            login_shell: Some("/bin/bash"),
+            files: &[],
+            installed: &[],
+        };
+        let (shell, source) = Shell::detect_with(&all).unwrap();
+        assert!(matches!(shell, Shell::Fish(_)));
+        assert_eq!(source, DetectionSource::ParentProcess);
+
+        // A parent that isn't a shell is skipped
+        let not_a_shell = FakeDetection {
+            parent_process: Some("cargo"),
+            ..all
+        };
+        let (shell, source) = Shell::detect_with(&not_a_shell).unwrap();
+        assert!(matches!(shell, Shell::Zsh(_)));
+        assert_eq!(source, DetectionSource::ShellVar);
+
+        let login_only = FakeDetection {
+            login_shell: Some("/bin/bash"),
+            ..Default::default()
+        };
+        let (shell, source) = Shell::detect_with(&login_only).unwrap();
+        assert!(matches!(shell, Shell::Bash(_)));
+        assert_eq!(source, DetectionSource::LoginShell);
+
+        let (shell, source) = Shell::detect_with(&FakeDetection::default()).unwrap();
+        assert!(matches!(shell, Shell::POSIX(_)));
+        assert_eq!(source, DetectionSource::Fallback);
+
+        let passwd = "alice:x:1000:1000::/home/alice:/usr/bin/zsh\n";
+        assert_eq!(
+            parse_passwd_shell(passwd, "alice"),
+            Some("/usr/bin/zsh".to_string())
+        );
+    }
+
+    #[test]
+    fn test_remove_managed_block() {
+        let dir = temp_dir("remove-managed-block");
+        let rcfile = dir.join(".bashrc");
+        let shell = Shell::Bash(Bash);
+
+        std::fs::write(&rcfile, "set -o vi\n").unwrap();
+        shell
+            .append_managed_block(&rcfile, "mytool", "export PATH=\"/opt/a:$PATH\"")
+            .unwrap();
+        append_to_rcfile(rcfile.clone(), "alias ll='ls -l'").unwrap();
+        shell
+            .append_managed_block(&rcfile, "mytool", "export PATH=\"/opt/b:$PATH\"")
+            .unwrap();
+        shell
+            .append_managed_block(&rcfile, "other", "export PATH=\"/opt/c:$PATH\"")
+            .unwrap();
+
+        // The user edited the body of the first block
+        let edited = std::fs::read_to_string(&rcfile)
+            .unwrap()
+            .replace("/opt/a", "/home/me/opt/a\nexport EDITOR=vim");
+        std::fs::write(&rcfile, edited).unwrap();
+
+        assert_eq!(shell.remove_managed_block(&rcfile, "mytool").unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "set -o vi\n\
+             alias ll='ls -l'\n\
+             # >>> other >>>\n\
+             export PATH=\"/opt/c:$PATH\"\n\
+             # <<< other <<<\n"
+        );
+        assert_eq!(shell.remove_managed_block(&rcfile, "mytool").unwrap(), 0);
+
+        let dangling = "set -o vi\n# >>> mytool >>>\nexport PATH=\"/opt/a:$PATH\"\n";
+        std::fs::write(&rcfile, dangling).unwrap();
+        assert!(matches!(
+            shell.remove_managed_block(&rcfile, "mytool"),
+            Err(ShellError::UnterminatedManagedBlock(name)) if name == "mytool"
+        ));
+        assert_eq!(std::fs::read_to_string(&rcfile).unwrap(), dangling);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_binary_exists_in() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = temp_dir("binary-exists-in");
+        let fish = bin.join("fish");
+        std::fs::write(&fish, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&fish, std::fs::Permissions::from_mode(0o755)).unwrap();
+        // Not executable, so not a usable zsh
+        std::fs::write(bin.join("zsh"), "").unwrap();
+
+        let path = env::join_paths([Path::new("/nonexistent"), &bin]).unwrap();
+        assert!(Shell::Fish(Fish).binary_exists_in(&path));
+        assert!(!Shell::Zsh(Zsh).binary_exists_in(&path));
+        assert!(!Shell::Bash(Bash).binary_exists_in(&path));
+        assert!(!Shell::Fish(Fish).binary_exists_in(&OsString::new()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_sanitize_path() {
+        let sanitize = |value: &str| sanitize_path(&OsString::from(value));
+
+        assert_eq!(sanitize(":/usr/bin"), "/usr/bin");
+        assert_eq!(sanitize("/usr/bin:"), "/usr/bin");
+        assert_eq!(sanitize("/usr/bin::/bin"), "/usr/bin:/bin");
+        assert_eq!(sanitize("::/usr/bin:::/bin::"), "/usr/bin:/bin");
+        assert_eq!(sanitize(":"), "");
+
+        assert_eq!(
+            add_to_path(
+                &OsString::from("/usr/bin:"),
+                Path::new("/opt/bin"),
+                Placement::Append
+            )
+            .unwrap(),
+            "/usr/bin:/opt/bin"
+        );
+        assert_eq!(
+            add_to_path(
+                &OsString::from(":"),
+                Path::new("/opt/bin"),
+                Placement::Prepend
+            )
+            .unwrap(),
+            "/opt/bin"
+        );
+        assert_eq!(dedup_path(&OsString::from("/bin::/bin:")).unwrap(), "/bin");
+    }
+
+    #[test]
+    fn test_init_order() {
+        let home = temp_dir("init-order");
+        let env = HashMap::from([
+            ("HOME", home.clone()),
+            ("XDG_CONFIG_HOME", home.join(".config")),
+            ("ENV", home.join(".shrc")),
+        ]);
+
+        assert_eq!(
+            Shell::POSIX(POSIX).init_order_with_env(&env).unwrap(),
+            vec![home.join(".profile"), home.join(".shrc")]
+        );
+        assert_eq!(
+            Shell::Zsh(Zsh).init_order_with_env(&env).unwrap(),
+            vec![
+                home.join(".zshenv"),
+                home.join(".zprofile"),
+                home.join(".zshrc"),
+                home.join(".zlogin"),
+            ]
+        );
+
+        let bash = Shell::Bash(Bash);
+        assert_eq!(
+            bash.init_order_with_env(&env).unwrap(),
+            vec![home.join(".bash_profile")]
+        );
+        std::fs::write(home.join(".profile"), "").unwrap();
+        assert_eq!(
+            bash.init_order_with_env(&env).unwrap(),
+            vec![home.join(".profile")]
+        );
+        std::fs::write(home.join(".bash_login"), "").unwrap();
+        assert_eq!(
+            bash.init_order_with_env(&env).unwrap(),
+            vec![home.join(".bash_login")]
+        );
+
+        let fish = home.join(".config/fish");
+        std::fs::create_dir_all(fish.join("conf.d")).unwrap();
+        for file in ["b.fish", "a.fish", "notes.txt"] {
+            std::fs::write(fish.join("conf.d").join(file), "").unwrap();
+        }
+        assert_eq!(
+            Shell::Fish(Fish).init_order_with_env(&env).unwrap(),
+            vec![
+                fish.join("conf.d/a.fish"),
+                fish.join("conf.d/b.fish"),
+                fish.join("config.fish"),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_invalid_path_entry() {
+        let path = OsString::from("/usr/bin:/opt/bin:/usr/bin");
+        let invalid = Path::new("/opt/my:tool");
+
+        assert!(matches!(
+            add_to_path(&path, invalid, Placement::Prepend),
+            Err(ShellError::InvalidPathEntry(entry)) if entry == invalid
+        ));
+        assert!(matches!(
+            add_to_path(&path, invalid, Placement::Append),
+            Err(ShellError::InvalidPathEntry(entry)) if entry == invalid
+        ));
+
+        assert_eq!(
+            remove_from_path(&path, Path::new("/usr/bin")).unwrap(),
+            "/opt/bin"
+        );
+        assert_eq!(
+            remove_from_path(&path, invalid).unwrap(),
+            "/usr/bin:/opt/bin:/usr/bin"
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        for shell in [Shell::POSIX(POSIX), Shell::Bash(Bash), Shell::Zsh(Zsh)] {
+            assert_eq!(shell.quote("/usr/bin"), "'/usr/bin'");
+            assert_eq!(shell.quote("my dir"), "'my dir'");
+            assert_eq!(shell.quote("it's"), r"'it'\''s'");
+            assert_eq!(shell.quote("a\nb"), "'a\nb'");
+            assert_eq!(shell.quote("$HOME \"x\""), "'$HOME \"x\"'");
+            assert_eq!(shell.quote(""), "''");
+        }
+
+        let fish = Shell::Fish(Fish);
+        assert_eq!(fish.quote("/usr/bin"), "/usr/bin");
+        assert_eq!(fish.quote("my dir"), "'my dir'");
+        assert_eq!(fish.quote("it's"), r"'it\'s'");
+        assert_eq!(fish.quote(r"back\slash"), r"'back\\slash'");
+        assert_eq!(fish.quote("a\nb"), "'a\nb'");
+        assert_eq!(fish.quote(""), "''");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_command_with_path() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = temp_dir("command-with-path");
+        let script = bin.join("what-the-path-hello");
+        std::fs::write(&script, "#!/bin/sh\necho \"hello from $0\"\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let output = command_with_path("what-the-path-hello", &bin, Placement::Append)
+            .unwrap()
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("hello from {}\n", script.display())
+        );
+    }
+
+    #[test]
+    fn test_rcfile_contains_line() {
+        let dir = temp_dir("contains-line");
+        let rcfile = dir.join(".bashrc");
+        let line = r#"export PATH="/opt/tool/bin:$PATH""#;
+
+        assert!(!rcfile_contains_line(&rcfile, line).unwrap());
+
+        std::fs::write(&rcfile, format!("alias ll='ls -l'\n  {line}\t\n")).unwrap();
+        assert!(rcfile_contains_line(&rcfile, line).unwrap());
+        assert!(rcfile_contains_line(&rcfile, &format!(" {line} ")).unwrap());
+        assert!(!rcfile_contains_line(&rcfile, r#"export PATH="/opt/tool/bin""#).unwrap());
+        assert!(!rcfile_contains_line(&rcfile, r#"export  PATH="/opt/tool/bin:$PATH""#).unwrap());
+
+        assert_eq!(
+            append_to_rcfile_if_missing(&rcfile, line).unwrap(),
+            AppendOutcome::AlreadyPresent
+        );
+        assert_eq!(
+            append_to_rcfile_if_missing(&rcfile, "export EDITOR=vi").unwrap(),
+            AppendOutcome::Appended
+        );
+        assert_eq!(
+            append_to_rcfile_if_missing(&rcfile, "export EDITOR=vi").unwrap(),
+            AppendOutcome::AlreadyPresent
+        );
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("alias ll='ls -l'\n  {line}\t\nexport EDITOR=vi\n")
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_detect_by_shell_var_or_default() {
+        assert!(matches!(
+            Shell::detect_by_shell_var_or_default_with_env(&HashMap::<&str, &str>::new()),
+            Shell::POSIX(_)
+        ));
+
+        let env = HashMap::from([("SHELL", "/usr/bin/fish")]);
+        assert!(matches!(
+            Shell::detect_by_shell_var_or_default_with_env(&env),
+            Shell::Fish(_)
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_most_likely_shell() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = temp_dir("most-likely-shell");
+        for binary in ["zsh", "fish"] {
+            let path = bin.join(binary);
+            std::fs::write(&path, "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let path = bin.clone().into_os_string();
+
+        let etc_shells = "# /etc/shells: valid login shells\n/bin/sh\n/bin/bash\n/usr/bin/zsh\n";
+        assert!(matches!(
+            most_likely_shell_from(etc_shells, &path),
+            Shell::Zsh(_)
+        ));
+
+        let etc_shells = "/bin/sh\n/usr/bin/fish\n";
+        assert!(matches!(
+            most_likely_shell_from(etc_shells, &path),
+            Shell::Fish(_)
+        ));
+
+        // Without a usable /etc/shells, any installed shell qualifies
+        assert!(matches!(most_likely_shell_from("", &path), Shell::Zsh(_)));
+
+        assert!(matches!(
+            most_likely_shell_from("/bin/sh\n/bin/bash\n", &path),
+            Shell::POSIX(_)
+        ));
+        assert!(matches!(
+            most_likely_shell_from(etc_shells, &OsString::new()),
+            Shell::POSIX(_)
+        ));
+    }
+
+    #[test]
+    fn test_runtime_dir() {
+        let env = HashMap::from([("XDG_RUNTIME_DIR", "/custom/runtime")]);
+        assert_eq!(
+            runtime_dir_with_env(&env),
+            Some(PathBuf::from("/custom/runtime"))
+        );
+
+        // Relative values are ignored, like an unset variable
+        let env = HashMap::from([("XDG_RUNTIME_DIR", "relative/runtime")]);
+        let fallback = runtime_dir_with_env(&env);
+        assert_eq!(
+            runtime_dir_with_env(&HashMap::<&str, &str>::new()),
+            fallback
+        );
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let uid = std::fs::metadata("/proc/self").unwrap().uid();
+            let expected = PathBuf::from(format!("/run/user/{uid}"));
+            assert_eq!(fallback, Some(expected.clone()).filter(|dir| dir.is_dir()));
+        }
+    }
+
+    #[test]
+    fn test_remove_dir_from_path_export() {
+        let dir = temp_dir("remove-dir-from-export");
+        let rcfile = dir.join(".bashrc");
+        std::fs::write(
+            &rcfile,
+            concat!(
+                "export PATH=\"/a:/b:/c:$PATH\" # tools\n",
+                "  PATH=/b:$PATH\n",
+                "export PATH=\"$PATH:/d\"\n",
+                "echo /b\n",
+            ),
+        )
+        .unwrap();
+
+        // A middle entry, and the only entry of another line
+        assert!(remove_dir_from_path_export(&rcfile, Path::new("/b")).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            concat!(
+                "export PATH=\"/a:/c:$PATH\" # tools\n",
+                "export PATH=\"$PATH:/d\"\n",
+                "echo /b\n",
+            )
+        );
+
+        // The only directory of a line that appends
+        assert!(remove_dir_from_path_export(&rcfile, Path::new("/d")).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "export PATH=\"/a:/c:$PATH\" # tools\necho /b\n"
+        );
+
+        // An absent entry
+        assert!(!remove_dir_from_path_export(&rcfile, Path::new("/e")).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "export PATH=\"/a:/c:$PATH\" # tools\necho /b\n"
+        );
+    }
+
+    #[test]
+    fn test_shell_for_rcfile() {
+        let shell_for =
+            |path: &str| shell_for_rcfile(Path::new(path)).map(|shell| shell.binary_name());
+
+        for rcfile in [
+            ".zshenv",
+            ".zprofile",
+            "/home/user/.zshrc",
+            ".zlogin",
+            ".zlogout",
+        ] {
+            assert_eq!(shell_for(rcfile), Some("zsh"), "{rcfile}");
+        }
+        for rcfile in [
+            ".bashrc",
+            "/home/user/.bash_profile",
+            ".bash_login",
+            ".bash_logout",
+        ] {
+            assert_eq!(shell_for(rcfile), Some("bash"), "{rcfile}");
+        }
+        assert_eq!(
+            shell_for("/home/user/.config/fish/config.fish"),
+            Some("fish")
+        );
+        assert_eq!(shell_for(".config/fish/conf.d/mytool.fish"), Some("fish"));
+        assert_eq!(shell_for("/home/user/.profile"), Some("sh"));
+
+        assert_eq!(shell_for(".vimrc"), None);
+        assert_eq!(shell_for("/home/user/zshrc.bak"), None);
+        assert_eq!(shell_for("/"), None);
+    }
+
+    #[test]
+    fn test_remove_from_large_rcfile() {
+        let dir = temp_dir("remove-large");
+        let rcfile = dir.join(".bashrc");
+        let line = "export PATH=\"/opt/tool/bin:$PATH\"";
+
+        let filler: String = (0..100_000)
+            .map(|idx| format!("alias a{idx}='echo {idx}'\n"))
+            .collect();
+        let content = format!("\u{feff}{line}\n{filler}{line}\n{filler}{line}");
+        assert!(content.len() > 4_000_000);
+        std::fs::write(&rcfile, &content).unwrap();
+
+        assert_eq!(remove_from_rcfile(rcfile.clone(), line).unwrap(), 3);
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("\u{feff}{filler}{filler}")
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        assert_eq!(
+            remove_from_rcfile(rcfile.clone(), &format!("{line}\n")).unwrap(),
+            0
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_source_line() {
+        let script = Path::new("/opt/tool/my env");
+        assert_eq!(
+            Shell::Bash(Bash).source_line(script),
+            ". \"/opt/tool/my env\""
+        );
+        assert_eq!(
+            Shell::Fish(Fish).source_line(script),
+            "source '/opt/tool/my env'"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_detect_by_shell_var_strict() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = temp_dir("strict-shell-var");
+        let zsh = bin.join("zsh");
+        std::fs::write(&zsh, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&zsh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let env = HashMap::from([("SHELL", PathBuf::from("bin/zsh"))]);
+        assert!(matches!(
+            Shell::detect_by_shell_var_strict_with_env(&env),
+            Err(ShellError::DetectionFailed(_))
+        ));
+        assert!(matches!(
+            Shell::detect_by_shell_var_with_env(&env),
+            Ok(Shell::Zsh(_))
+        ));
+
+        let env = HashMap::from([("SHELL", bin.join("fish"))]);
+        assert!(matches!(
+            Shell::detect_by_shell_var_strict_with_env(&env),
+            Err(ShellError::DetectionFailed(_))
+        ));
+
+        let env = HashMap::from([("SHELL", zsh)]);
+        assert!(matches!(
+            Shell::detect_by_shell_var_strict_with_env(&env),
+            Ok(Shell::Zsh(_))
+        ));
+    }
+
+    #[test]
+    fn test_consolidate_path_exports() {
+        let dir = temp_dir("consolidate");
+        let rcfile = dir.join(".bashrc");
+        std::fs::write(
+            &rcfile,
+            concat!(
+                "alias ll='ls -l'\n",
+                "export PATH=\"/a:$PATH\"\n",
+                "set -o vi\n",
+                "PATH=$PATH:/b\n",
+                "export PATH=\"/c:/a:$PATH\"\n",
+                "export EDITOR=vi\n",
+            ),
+        )
+        .unwrap();
+
+        assert!(consolidate_path_exports(&rcfile).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            concat!(
+                "alias ll='ls -l'\n",
+                "export PATH=\"/c:/a:$PATH:/b\"\n",
+                "set -o vi\n",
+                "export EDITOR=vi\n",
+            )
+        );
+
+        // A single export is already consolidated
+        assert!(!consolidate_path_exports(&rcfile).unwrap());
+
+        // Conditional and indented lines are left where they are
+        let conditional = concat!(
+            "export PATH=\"/a:$PATH\"\n",
+            "if [ -d /b ]; then\n",
+            "export PATH=\"/b:$PATH\"\n",
+            "fi\n",
+            "setup() {\n",
+            "  export PATH=\"/c:$PATH\"\n",
+            "}\n",
+            "  export PATH=\"/d:$PATH\"\n",
+            "export PATH=\"$PATH:/e\"\n",
+        );
+        std::fs::write(&rcfile, conditional).unwrap();
+        assert!(consolidate_path_exports(&rcfile).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            conditional
+                .replacen("/a:$PATH", "/a:$PATH:/e", 1)
+                .replace("export PATH=\"$PATH:/e\"\n", "")
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_dir_registered() {
+        let env = HashMap::from([("PATH", "/usr/bin:/opt/tool/bin")]);
+        for shell in [Shell::POSIX(POSIX), Shell::Bash(Bash), Shell::Zsh(Zsh)] {
+            assert!(shell
+                .dir_registered_with_env(Path::new("/opt/tool/bin"), &env)
+                .unwrap());
+            assert!(!shell
+                .dir_registered_with_env(Path::new("/opt/other/bin"), &env)
+                .unwrap());
+        }
+
+        if Fish.does_exist() {
+            let fish = Shell::Fish(Fish);
+            assert!(!fish
+                .dir_registered(Path::new("/nonexistent/what-the-path/bin"))
+                .unwrap());
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_symlinked_rcfile() {
+        let dir = temp_dir("symlinked-rcfile");
+        let target = dir.join("dotfiles-bashrc");
+        let rcfile = dir.join(".bashrc");
+        std::fs::write(&target, "umask 022\n").unwrap();
+        std::os::unix::fs::symlink(&target, &rcfile).unwrap();
+
+        assert!(matches!(
+            append_to_rcfile_with(&rcfile, "set -o vi", FollowSymlinks::Refuse),
+            Err(ShellError::RcFileIsSymlink(path)) if path == rcfile
+        ));
+        assert!(matches!(
+            modify_rcfile_with(&rcfile, FollowSymlinks::Refuse, |file| file + "set -o vi\n"),
+            Err(ShellError::RcFileIsSymlink(_))
+        ));
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "umask 022\n");
+
+        append_to_rcfile_with(&rcfile, "set -o vi", FollowSymlinks::default()).unwrap();
+        modify_rcfile_with(&rcfile, FollowSymlinks::Follow, |file| {
+            file + "export EDITOR=vi\n"
+        })
+        .unwrap();
+        assert!(std::fs::symlink_metadata(&rcfile)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            std::fs::read_to_string(&target).unwrap(),
+            "umask 022\nset -o vi\nexport EDITOR=vi\n"
+        );
+
+        // Regular files are edited either way
+        append_to_rcfile_with(&target, "alias ll='ls -l'", FollowSymlinks::Refuse).unwrap();
+    }
+
+    #[test]
+    fn test_zdotdir_from_zshenv() {
+        let home = temp_dir("zdotdir-from-zshenv");
+        let mut env = HashMap::from([("HOME", home.clone())]);
+        assert_eq!(Zsh.zdotdir_offline_with_env(&env), None);
+
+        std::fs::write(
+            home.join(".zshenv"),
+            "# zsh config lives in ~/.config/zsh\nexport ZDOTDIR=\"$HOME/.config/zsh\"\n",
+        )
+        .unwrap();
+        let zdotdir = home.join(".config/zsh");
+        assert_eq!(Zsh.zdotdir_offline_with_env(&env), Some(zdotdir.clone()));
+        let zsh = Shell::Zsh(Zsh);
+        assert_eq!(zsh.config_home_with_env(&env).unwrap(), zdotdir);
+        // ~/.zshenv is still the file every zsh reads
+        assert_eq!(
+            zsh.rc_file_for_path_edit_with_env(&env).unwrap(),
+            home.join(".zshenv")
+        );
+        assert_eq!(
+            zsh.init_order_with_env(&env).unwrap()[..2],
+            [home.join(".zshenv"), zdotdir.join(".zprofile")]
+        );
+
+        // Computed values aren't understood
+        std::fs::write(home.join(".zshenv"), "ZDOTDIR=$XDG_CONFIG_HOME/zsh\n").unwrap();
+        assert_eq!(Zsh.zdotdir_offline_with_env(&env), None);
+
+        // The environment wins
+        env.insert("ZDOTDIR", PathBuf::from("/custom/zsh"));
+        assert_eq!(
+            Zsh.zdotdir_offline_with_env(&env),
+            Some(PathBuf::from("/custom/zsh"))
+        );
+    }
+
+    #[test]
+    fn test_rcfile_writable() {
+        let dir = temp_dir("rcfile-writable");
+        let shell = Shell::Bash(Bash);
+
+        let writable = dir.join(".bashrc");
+        std::fs::write(&writable, "umask 022\n").unwrap();
+        assert!(shell.rcfile_writable(&writable));
+        assert!(shell.rcfile_writable(&dir.join(".bash_profile")));
+        // Missing directories are created along with the file
+        assert!(shell.rcfile_writable(&dir.join("missing/.bashrc")));
+        assert!(!shell.rcfile_writable(&writable.join(".bashrc")));
+
+        let read_only = dir.join(".profile");
+        std::fs::write(&read_only, "umask 022\n").unwrap();
+        let mut permissions = std::fs::metadata(&read_only).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&read_only, permissions).unwrap();
+        assert!(!shell.rcfile_writable(&read_only));
+
+        let err = shell
+            .append_to_rcfile_annotated(&read_only, "set -o vi", "added by test")
+            .unwrap_err();
+        assert!(
+            matches!(err, ShellError::RcFileError(err) if err.kind() == std::io::ErrorKind::PermissionDenied)
+        );
+        assert_eq!(std::fs::read_to_string(&read_only).unwrap(), "umask 022\n");
+    }
+
+    #[test]
+    fn test_crlf_rcfile() {
+        let dir = temp_dir("crlf-rcfile");
+        let rcfile = dir.join(".bashrc");
+        std::fs::write(&rcfile, "umask 022\r\nset -o vi\r\n").unwrap();
+        assert_eq!(rcfile_line_ending(&rcfile).unwrap(), LineEnding::CrLf);
+
+        append_to_rcfile(rcfile.clone(), "export EDITOR=vi").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "umask 022\r\nset -o vi\r\nexport EDITOR=vi\r\n"
+        );
+
+        assert_eq!(
+            remove_from_rcfile(rcfile.clone(), "set -o vi\n").unwrap(),
+            1
+        );
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "umask 022\r\nexport EDITOR=vi\r\n"
+        );
+
+        std::fs::write(&rcfile, "umask 022\nset -o vi\r\n").unwrap();
+        assert_eq!(rcfile_line_ending(&rcfile).unwrap(), LineEnding::Lf);
+    }
+
+    #[test]
+    fn test_default_for_os() {
+        let default = Shell::default_for_os();
+
+        #[cfg(target_os = "macos")]
+        assert!(matches!(default, Shell::Zsh(_)));
+        #[cfg(all(unix, not(target_os = "macos")))]
+        assert!(matches!(default, Shell::Bash(_)));
+        #[cfg(windows)]
+        assert!(matches!(default, Shell::POSIX(_)));
+    }
+
+    #[test]
+    fn test_bash_profile_chain() {
+        let home = temp_dir("bash-profile-chain");
+        let env = HashMap::from([("HOME", &home)]);
+        assert_eq!(
+            Bash.profile_chain_with_env(&env).unwrap(),
+            Vec::<PathBuf>::new()
+        );
+
+        std::fs::write(
+            home.join(".profile"),
+            "if [ -n \"$BASH_VERSION\" ]; then\n    if [ -f \"$HOME/.bashrc\" ]; then\n        . \"$HOME/.bashrc\"\n    fi\nfi\n",
+        )
+        .unwrap();
+        std::fs::write(home.join(".bashrc"), "# . ~/.profile\nalias ll='ls -l'\n").unwrap();
+        assert_eq!(
+            Bash.profile_chain_with_env(&env).unwrap(),
+            vec![home.join(".profile"), home.join(".bashrc")]
+        );
+
+        std::fs::write(
+            home.join(".bash_profile"),
+            "[ -f ~/.profile ] && source ~/.profile; . /etc/bash_completion\n",
+        )
+        .unwrap();
+        assert_eq!(
+            Bash.profile_chain_with_env(&env).unwrap(),
+            vec![
+                home.join(".bash_profile"),
+                home.join(".profile"),
+                home.join(".bashrc"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_path_separator() {
+        #[cfg(unix)]
+        {
+            assert_eq!(path_separator(), ':');
+            assert_eq!(
+                add_to_path(
+                    &OsString::from("/usr/bin"),
+                    Path::new("/opt/bin"),
+                    Placement::Append
+                )
+                .unwrap(),
+                "/usr/bin:/opt/bin"
+            );
+        }
+        #[cfg(windows)]
+        {
+            assert_eq!(path_separator(), ';');
+            assert_eq!(
+                add_to_path(
+                    &OsString::from("C:\\bin"),
+                    Path::new("C:\\tool"),
+                    Placement::Append
+                )
+                .unwrap(),
+                "C:\\bin;C:\\tool"
+            );
+        }
+
+        let path = OsString::from(format!(
+            "/usr/local/bin{sep}/usr/bin",
+            sep = path_separator()
+        ));
+        assert!(exists_in_path_within(Path::new("/usr/bin"), &path));
+        // Entries are compared whole, not as substrings
+        assert!(!exists_in_path_within(Path::new("/usr"), &path));
+        assert!(!exists_in_path_within(Path::new(&path), &path));
+    }
+
+    #[test]
+    fn test_append_to_rcfile_spaced() {
+        let dir = temp_dir("append-spaced");
+        let rcfile = dir.join(".bashrc");
+        let line = "export PATH=\"/opt/tool/bin:$PATH\"";
+
+        for (content, expected) in [
+            ("umask 022\n", format!("umask 022\n\n{line}\n")),
+            ("umask 022", format!("umask 022\n\n{line}\n")),
+            ("umask 022\n\n", format!("umask 022\n\n{line}\n")),
+            ("umask 022\n\n  \n\n", format!("umask 022\n\n{line}\n")),
+            ("", format!("{line}\n")),
+        ] {
+            std::fs::write(&rcfile, content).unwrap();
+            append_to_rcfile_spaced(&rcfile, line).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&rcfile).unwrap(),
+                expected,
+                "{content:?}"
+            );
+        }
+
+        append_to_rcfile_spaced(&rcfile, "set -o vi").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("{line}\n\nset -o vi\n")
+        );
+    }
+
+    #[test]
+    fn test_get_home_dir_strict() {
+        // Uses the root entry of the real /etc/passwd
+        let Some(root_home) = home_dir_for_user("root") else {
+            return;
+        };
+
+        let mut env = HashMap::from([("USER", PathBuf::from("root")), ("HOME", root_home.clone())]);
+        assert_eq!(get_home_dir_strict_with_env(&env).unwrap(), root_home);
+
+        let other_home = temp_dir("strict-home");
+        env.insert("HOME", other_home.clone());
+        assert!(matches!(
+            get_home_dir_strict_with_env(&env),
+            Err(ShellError::MultipleHomesConflict { env, passwd })
+                if env == other_home && passwd == root_home
+        ));
+        // The lenient lookup doesn't check
+        assert_eq!(get_home_dir_with_env(&env).unwrap(), other_home);
+
+        // Without a passwd entry there's nothing to conflict with
+        env.insert("USER", PathBuf::from("what-the-path-nonexistent-user"));
+        assert_eq!(get_home_dir_strict_with_env(&env).unwrap(), other_home);
+    }
+
+    #[test]
+    fn test_detect_cached() {
+        // No other test detects the running process's shell, so the override only affects
+        // this one
+        let _guard = lock_detection_cache();
+        env::set_var(SHELL_OVERRIDE_VAR, "zsh");
+
+        invalidate_detection_cache();
+        assert!(matches!(Shell::detect_cached(), Ok(Shell::Zsh(_))));
+        env::set_var(SHELL_OVERRIDE_VAR, "fish");
+        assert!(matches!(Shell::detect_cached(), Ok(Shell::Zsh(_))));
+
+        // Detecting another environment neither uses nor replaces the cached shell
+        let bash = HashMap::from([("SHELL", "/bin/bash")]);
+        assert!(matches!(
+            Shell::detect_with(&EnvDetection::new(&bash)),
+            Ok((Shell::Bash(_), _))
+        ));
+        assert!(matches!(Shell::detect_cached(), Ok(Shell::Zsh(_))));
+
+        invalidate_detection_cache();
+        assert!(matches!(Shell::detect_cached(), Ok(Shell::Fish(_))));
+
+        env::remove_var(SHELL_OVERRIDE_VAR);
+        invalidate_detection_cache();
+    }
+
+    #[test]
+    fn test_path_add_lines() {
+        let dirs = [
+            PathBuf::from("/opt/tool/bin"),
+            PathBuf::from("/opt/tool/sbin"),
+        ];
+
+        assert_eq!(
+            Shell::POSIX(POSIX).path_add_lines(&dirs, Placement::Prepend),
+            "export PATH=\"/opt/tool/bin:/opt/tool/sbin:$PATH\""
+        );
+        assert_eq!(
+            Shell::Bash(Bash).path_add_lines(&dirs, Placement::Append),
+            "export PATH=\"$PATH:/opt/tool/bin:/opt/tool/sbin\""
+        );
+        assert_eq!(
+            Shell::Fish(Fish).path_add_lines(&dirs, Placement::Prepend),
+            "fish_add_path /opt/tool/bin /opt/tool/sbin"
+        );
+        assert_eq!(
+            Shell::Fish(Fish).path_add_lines(&dirs, Placement::Append),
+            "fish_add_path --append /opt/tool/bin /opt/tool/sbin"
+        );
+        assert_eq!(
+            Shell::Zsh(Zsh).path_add_lines(&dirs[..1], Placement::Prepend),
+            Shell::Zsh(Zsh).path_add_line(&dirs[0], Placement::Prepend)
+        );
+        assert_eq!(Shell::Zsh(Zsh).path_add_lines(&[], Placement::Prepend), "");
+    }
+
+    #[test]
+    fn test_rcfile_template() {
+        for shell in Shell::all() {
+            let template = shell.rcfile_template();
+            assert!(template.starts_with("# "), "{template:?}");
+            assert!(template.ends_with("\n\n"), "{template:?}");
+        }
+        assert!(Shell::Zsh(Zsh).rcfile_template().contains("zsh"));
+
+        let dir = temp_dir("rcfile-template");
+        let rcfile = dir.join(".zshenv");
+        append_to_rcfile_create(rcfile.clone(), "export EDITOR=vi").unwrap();
+        append_to_rcfile_create(rcfile.clone(), "set -o vi").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!(
+                "{}export EDITOR=vi\nset -o vi\n",
+                Shell::Zsh(Zsh).rcfile_template()
+            )
+        );
+
+        // Unknown files aren't seeded
+        let other = dir.join("tool.env");
+        append_to_rcfile_create(other.clone(), "export EDITOR=vi").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&other).unwrap(),
+            "export EDITOR=vi\n"
+        );
+    }
+
+    #[test]
+    fn test_exists_in_path_within() {
+        let path_value = std::ffi::OsStr::new("/usr/bin:/opt/tool/bin:/bin");
+        assert!(exists_in_path_within(
+            Path::new("/opt/tool/bin"),
+            path_value
+        ));
+        assert!(exists_in_path_within(Path::new("/bin"), path_value));
+        assert!(!exists_in_path_within(Path::new("/opt/tool"), path_value));
+        assert!(!exists_in_path_within(
+            Path::new("/opt/tool/bin2"),
+            path_value
+        ));
+        assert!(!exists_in_path_within(
+            Path::new("/usr/bin"),
+            std::ffi::OsStr::new("")
+        ));
+    }
+
+    #[test]
+    fn test_fish_universal_var() {
+        let dir = temp_dir("fish-variables");
+        let file = dir.join("fish_variables");
+        std::fs::write(
+            &file,
+            "# This file contains fish universal variable definitions.\n\
+             # VERSION: 3.0\n\
+             SETUVAR __fish_initialized:3400\n\
+             SETUVAR --export EDITOR:vim\n\
+             SETUVAR fish_user_paths:/opt/tool/bin\\x1e/home/me/my\\x20bin\n\
+             SETUVAR empty_list:\\x1d\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            fish_universal_var_from(&file, "fish_user_paths").unwrap(),
+            Some(vec![
+                "/opt/tool/bin".to_string(),
+                "/home/me/my bin".to_string()
+            ])
+        );
+        assert_eq!(
+            fish_universal_var_from(&file, "EDITOR").unwrap(),
+            Some(vec!["vim".to_string()])
+        );
+        assert_eq!(
+            fish_universal_var_from(&file, "empty_list").unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(fish_universal_var_from(&file, "missing").unwrap(), None);
+        assert_eq!(
+            fish_universal_var_from(&dir.join("nonexistent"), "fish_user_paths").unwrap(),
+            None
+        );
+
+        for malformed in [
+            // No version header
+            "SETUVAR fish_user_paths:/opt/tool/bin\n",
+            // Unknown version
+            "# VERSION: 9.9\nSETUVAR fish_user_paths:/opt/tool/bin\n",
+            // Not a SETUVAR line
+            "# VERSION: 3.0\nset -U fish_user_paths /opt/tool/bin\n",
+            // Missing value separator
+            "# VERSION: 3.0\nSETUVAR fish_user_paths\n",
+            // Truncated escape sequence
+            "# VERSION: 3.0\nSETUVAR fish_user_paths:/opt\\x2\n",
+        ] {
+            std::fs::write(&file, malformed).unwrap();
+            assert!(
+                matches!(
+                    fish_universal_var_from(&file, "fish_user_paths"),
+                    Err(ShellError::CorruptFishVariables(_))
+                ),
+                "{malformed:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_path_remove_line() {
+        let dir = Path::new("/opt/my tool/bin");
+        assert_eq!(
+            Shell::Fish(Fish).path_remove_line(dir),
+            "while set -l index (contains -i -- '/opt/my tool/bin' $PATH); set -e PATH[$index]; end"
+        );
+        assert_eq!(
+            Shell::Zsh(Zsh).path_remove_line(dir),
+            "path=(${path:#\"/opt/my tool/bin\"})"
+        );
+        let posix = Shell::POSIX(POSIX).path_remove_line(dir);
+        assert_eq!(
+            posix,
+            "export PATH=\"$(set -f; IFS=:; p=; for d in $PATH; do [ \"$d\" = \"/opt/my tool/bin\" ] || p=\"${p:+$p:}$d\"; done; printf '%s' \"$p\")\""
+        );
+        assert_eq!(Shell::Bash(Bash).path_remove_line(dir), posix);
+
+        if Path::new("/bin/sh").exists() {
+            let output = Command::new("/bin/sh")
+                .args(["-c", &format!("{posix}; printf '%s' \"$PATH\"")])
+                .env("PATH", "/usr/bin:/opt/my tool/bin:/bin:/opt/my tool/bin")
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), "/usr/bin:/bin");
+        }
+    }
+
+    #[test]
+    fn test_detect_system_shell() {
+        let sh_only = FakeDetection {
+            files: &["/bin/sh"],
+            ..Default::default()
+        };
+        let (shell, source) = Shell::detect_with(&sh_only).unwrap();
+        assert!(matches!(shell, Shell::POSIX(_)));
+        assert_eq!(source, DetectionSource::SystemShell);
+
+        let with_bash = FakeDetection {
+            files: &["/bin/sh", "/bin/bash"],
+            ..Default::default()
+        };
+        let (shell, source) = Shell::detect_with(&with_bash).unwrap();
+        assert!(matches!(shell, Shell::Bash(_)));
+        assert_eq!(source, DetectionSource::SystemShell);
+
+        // Any other source takes precedence
+        let login = FakeDetection {
+            login_shell: Some("/usr/bin/zsh"),
+            ..with_bash
+        };
+        let (shell, source) = Shell::detect_with(&login).unwrap();
+        assert!(matches!(shell, Shell::Zsh(_)));
+        assert_eq!(source, DetectionSource::LoginShell);
+    }
+
+    #[test]
+    fn test_rcfile_stats() {
+        let dir = temp_dir("rcfile-stats");
+        let rcfile = dir.join(".bashrc");
+        let content = "# bashrc\nexport PATH=\"/opt/tool/bin:$PATH\"\nalias ll='ls -l'\n\nexport PATH=\"$PATH:$HOME/.cargo/bin\"";
+        std::fs::write(&rcfile, content).unwrap();
+
+        let stats = Shell::Bash(Bash).rcfile_stats(&rcfile).unwrap();
+        assert_eq!(
+            stats,
+            RcFileStats {
+                lines: 5,
+                bytes: content.len() as u64,
+                path_exports: 2,
+                ends_with_newline: false,
+            }
+        );
+
+        std::fs::write(&rcfile, "fish_add_path /opt/tool/bin\n").unwrap();
+        let stats = Shell::Bash(Bash).rcfile_stats(&rcfile).unwrap();
+        assert_eq!((stats.lines, stats.path_exports), (1, 1));
+        assert!(stats.ends_with_newline);
+
+        assert!(matches!(
+            Shell::Bash(Bash).rcfile_stats(&dir.join("missing")),
+            Err(ShellError::RcFileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_install_snippet_placement() {
+        let dirs = [
+            PathBuf::from("/opt/tool/bin"),
+            PathBuf::from("/opt/tool/sbin"),
+        ];
+
+        assert_eq!(
+            Shell::Zsh(Zsh).install_snippet(&dirs, Placement::Append, None),
+            [
+                "case \":${PATH}:\" in",
+                "    *:\"/opt/tool/bin\":*) ;;",
+                "    *) export PATH=\"$PATH:/opt/tool/bin\" ;;",
+                "esac",
+                "case \":${PATH}:\" in",
+                "    *:\"/opt/tool/sbin\":*) ;;",
+                "    *) export PATH=\"$PATH:/opt/tool/sbin\" ;;",
+                "esac",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            Shell::Fish(Fish).install_snippet(&dirs, Placement::Append, None),
+            "fish_add_path --append /opt/tool/bin /opt/tool/sbin"
+        );
+
+        let dir = Path::new("/opt/tool/bin");
+        for shell in [Shell::POSIX(POSIX), Shell::Bash(Bash), Shell::Zsh(Zsh)] {
+            assert_eq!(
+                shell.path_add_line(dir, Placement::Prepend),
+                "export PATH=\"/opt/tool/bin:$PATH\""
+            );
+            assert_eq!(
+                shell.path_add_line(dir, Placement::Append),
+                "export PATH=\"$PATH:/opt/tool/bin\""
+            );
+        }
+        assert_eq!(
+            Shell::Fish(Fish).path_add_line(dir, Placement::Prepend),
+            "fish_add_path /opt/tool/bin"
+        );
+        assert_eq!(
+            Shell::Fish(Fish).path_add_line(dir, Placement::Append),
+            "fish_add_path --append /opt/tool/bin"
+        );
+        assert_eq!(Placement::default(), Placement::Prepend);
+    }
+
+    #[test]
+    fn test_register_shell_alias() {
+        let unknown = FakeDetection {
+            parent_process: Some("mysh"),
+            ..Default::default()
+        };
+        let (_, source) = Shell::detect_with(&unknown).unwrap();
+        assert_eq!(source, DetectionSource::Fallback);
+
+        // Registering aliases forgets the cached shell
+        let _guard = lock_detection_cache();
+        register_shell_alias("mysh", Shell::POSIX(POSIX));
+        register_shell_alias("mybash", Shell::Zsh(Zsh));
+        register_shell_alias("mybash", Shell::Bash(Bash));
+
+        let (shell, source) = Shell::detect_with(&unknown).unwrap();
+        assert!(matches!(shell, Shell::POSIX(_)));
+        assert_eq!(source, DetectionSource::ParentProcess);
+
+        let env = HashMap::from([("SHELL", "/bin/mybash")]);
+        assert!(matches!(
+            Shell::detect_by_shell_var_with_env(&env),
+            Ok(Shell::Bash(_))
+        ));
+
+        assert!(unregister_shell_alias("mysh"));
+        assert!(!unregister_shell_alias("mysh"));
+        let (_, source) = Shell::detect_with(&unknown).unwrap();
+        assert_eq!(source, DetectionSource::Fallback);
+
+        clear_shell_aliases();
+        assert!(!unregister_shell_alias("mybash"));
+    }
+
+    #[test]
+    fn test_list_managed_blocks() {
+        let dir = temp_dir("list-managed-blocks");
+        let rcfile = dir.join(".bashrc");
+        std::fs::write(&rcfile, "alias ll='ls -l'\n").unwrap();
+
+        let shell = Shell::Bash(Bash);
+        shell
+            .append_managed_block(&rcfile, "mytool", "export PATH=\"/opt/mytool/bin:$PATH\"")
+            .unwrap();
+        append_to_rcfile(rcfile.clone(), "set -o vi").unwrap();
+        shell
+            .append_managed_block(&rcfile, "other tool", "export A=1\nexport B=2")
+            .unwrap();
+
+        assert_eq!(
+            list_managed_blocks(&rcfile).unwrap(),
+            vec![
+                ("mytool".to_string(), 1..4),
+                ("other tool".to_string(), 5..9)
+            ]
+        );
+
+        std::fs::write(&rcfile, "# >>> broken >>>\nexport A=1\n").unwrap();
+        assert!(matches!(
+            list_managed_blocks(&rcfile),
+            Err(ShellError::UnterminatedManagedBlock(name)) if name == "broken"
+        ));
+    }
+
+    /// Encodes `content` as little-endian UTF-16 with a byte order mark.
+    fn utf16_le(content: &str) -> Vec<u8> {
+        std::iter::once(0xfeff)
+            .chain(content.encode_utf16())
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    #[cfg(feature = "utf16")]
+    #[test]
+    fn test_utf16_rcfile() {
+        let dir = temp_dir("utf16-rcfile");
+        let profile = dir.join("Microsoft.PowerShell_profile.ps1");
+        std::fs::write(&profile, utf16_le("Set-Alias ll Get-ChildItem\r\n")).unwrap();
+        assert_eq!(rcfile_encoding(&profile).unwrap(), RcFileEncoding::Utf16Le);
+
+        append_to_rcfile(profile.clone(), "$env:Path += ';C:\\tool\\bin'").unwrap();
+        assert_eq!(
+            std::fs::read(&profile).unwrap(),
+            utf16_le("Set-Alias ll Get-ChildItem\r\n$env:Path += ';C:\\tool\\bin'\r\n")
+        );
+        assert!(rcfile_contains_line(&profile, "$env:Path += ';C:\\tool\\bin'").unwrap());
+
+        assert_eq!(
+            remove_from_rcfile(profile.clone(), "Set-Alias ll Get-ChildItem\r\n").unwrap(),
+            1
+        );
+        assert_eq!(
+            std::fs::read(&profile).unwrap(),
+            utf16_le("$env:Path += ';C:\\tool\\bin'\r\n")
+        );
+        assert_eq!(rcfile_encoding(&profile).unwrap(), RcFileEncoding::Utf16Le);
+    }
+
+    #[cfg(not(feature = "utf16"))]
+    #[test]
+    fn test_utf16_rcfile_unsupported() {
+        let dir = temp_dir("utf16-rcfile-unsupported");
+        let profile = dir.join("Microsoft.PowerShell_profile.ps1");
+        let content = utf16_le("Set-Alias ll Get-ChildItem\r\n");
+        std::fs::write(&profile, &content).unwrap();
+
+        assert_eq!(rcfile_encoding(&profile).unwrap(), RcFileEncoding::Utf16Le);
+        assert!(matches!(
+            modify_rcfile(&profile, |file| file + "# edited\r\n"),
+            Err(ShellError::Utf16Unsupported(path)) if path == profile
+        ));
+        assert!(matches!(
+            append_to_rcfile(profile.clone(), "# edited"),
+            Err(ShellError::Utf16Unsupported(_))
+        ));
+        assert_eq!(std::fs::read(&profile).unwrap(), content);
+    }
+
+    #[test]
+    fn test_detect_from_str() {
+        assert!(matches!(
+            Shell::detect_from_str("/usr/bin/fish"),
+            Ok(Shell::Fish(_))
+        ));
+        assert!(matches!(
+            Shell::detect_from_str("/usr/local/bin/zsh-5.9"),
+            Ok(Shell::Zsh(_))
+        ));
+        assert!(matches!(Shell::detect_from_str("bash"), Ok(Shell::Bash(_))));
+        assert!(matches!(Shell::detect_from_str("-zsh"), Ok(Shell::Zsh(_))));
+        assert!(matches!(
+            Shell::detect_from_str("/bin/unknownsh"),
+            Ok(Shell::POSIX(_))
+        ));
+        assert!(matches!(
+            Shell::detect_from_str("  "),
+            Err(ShellError::DetectionFailed(_))
+        ));
+
+        assert!(matches!("fish".parse::<Shell>(), Ok(Shell::Fish(_))));
+        assert!(matches!("sh".parse::<Shell>(), Ok(Shell::POSIX(_))));
+        assert!(matches!(
+            "/usr/bin/fish".parse::<Shell>(),
+            Err(ShellError::DetectionFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_insert_at_anchor() {
+        let dir = temp_dir("insert-at-anchor");
+        let rcfile = dir.join(".zshrc");
+        std::fs::write(
+            &rcfile,
+            "# Homebrew\neval \"$(brew shellenv)\"\nalias ll='ls -l'",
+        )
+        .unwrap();
+
+        assert!(insert_after_line(&rcfile, "# Homebrew", "export PATH=\"/opt/a:$PATH\"").unwrap());
+        assert!(insert_before_line(&rcfile, "alias ll='ls -l'", "export B=1").unwrap());
+        assert!(insert_after_line(&rcfile, "alias ll='ls -l'", "export C=1").unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "# Homebrew\nexport PATH=\"/opt/a:$PATH\"\neval \"$(brew shellenv)\"\nexport B=1\nalias ll='ls -l'\nexport C=1\n"
+        );
+
+        // Only whole lines match, and nothing changes without a match
+        let before = std::fs::read_to_string(&rcfile).unwrap();
+        assert!(!insert_after_line(&rcfile, "# Home", "export D=1").unwrap());
+        assert!(!insert_before_line(&rcfile, "# Missing", "export D=1").unwrap());
+        assert_eq!(std::fs::read_to_string(&rcfile).unwrap(), before);
+
+        // CRLF files keep their line endings
+        std::fs::write(&rcfile, "# Homebrew\r\nalias ll='ls -l'\r\n").unwrap();
+        assert!(insert_after_line(&rcfile, "# Homebrew", "export A=1").unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "# Homebrew\r\nexport A=1\r\nalias ll='ls -l'\r\n"
+        );
+    }
+
+    #[test]
+    fn test_path_needs_login_file() {
+        assert!(Shell::POSIX(POSIX).path_needs_login_file());
+        assert!(Shell::Bash(Bash).path_needs_login_file());
+        assert!(!Shell::Zsh(Zsh).path_needs_login_file());
+        assert!(!Shell::Fish(Fish).path_needs_login_file());
+    }
+
+    #[test]
+    fn test_cache_dir() {
+        let (custom, home_var, home) = if cfg!(windows) {
+            (
+                "C:\\custom\\cache",
+                "LOCALAPPDATA",
+                "C:\\Users\\me\\AppData\\Local",
+            )
+        } else {
+            ("/custom/cache", "HOME", "/home/me")
+        };
+
+        let mut env = HashMap::from([("XDG_CACHE_HOME", custom), (home_var, home)]);
+        assert_eq!(cache_dir_with_env(&env), Some(PathBuf::from(custom)));
+
+        // Relative values are ignored, like an unset variable
+        env.insert("XDG_CACHE_HOME", "relative/cache");
+        let fallback = cache_dir_with_env(&env);
+        env.remove("XDG_CACHE_HOME");
+        assert_eq!(cache_dir_with_env(&env), fallback);
+        if cfg!(windows) {
+            assert_eq!(fallback, Some(PathBuf::from(home)));
+        } else {
+            assert_eq!(fallback, Some(PathBuf::from("/home/me/.cache")));
+        }
+
+        env.remove(home_var);
+        assert_eq!(cache_dir_with_env(&env), None);
+    }
+
+    #[test]
+    fn test_detect_unsupported_shell() {
+        let env = HashMap::from([("SHELL", "/usr/bin/nu")]);
+        match Shell::detect_by_shell_var_with_env(&env) {
+            Err(ShellError::DetectionFailed(message)) => {
+                assert!(message.contains("nu"), "{message}")
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        // Unknown shells are still assumed to be POSIX-compliant
+        assert!(matches!(
+            Shell::detect_from_str("/bin/yash"),
+            Ok(Shell::POSIX(_))
+        ));
+        assert!(matches!(
+            Shell::detect_from_str("/usr/local/bin/tcsh"),
+            Err(ShellError::DetectionFailed(_))
+        ));
+
+        let login_xonsh = FakeDetection {
+            login_shell: Some("/usr/bin/xonsh"),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Shell::detect_with(&login_xonsh),
+            Err(ShellError::DetectionFailed(message)) if message.contains("xonsh")
+        ));
+        let parent_nu = FakeDetection {
+            parent_process: Some("nu"),
+            shell_var: Some("/bin/bash"),
+            ..Default::default()
+        };
+        assert!(Shell::detect_with(&parent_nu).is_err());
+    }
+
+    #[test]
+    fn test_all_rcfile_candidates() {
+        let home = temp_dir("all-rcfile-candidates");
+        let mut env = HashMap::from([
+            ("HOME", home.clone()),
+            ("XDG_CONFIG_HOME", home.join(".config")),
+            ("ZDOTDIR", home.join(".config/zsh")),
+        ]);
+
+        assert_eq!(
+            Shell::POSIX(POSIX)
+                .all_rcfile_candidates_with_env(&env)
+                .unwrap(),
+            vec![home.join(".profile")]
+        );
+        assert_eq!(
+            Shell::Bash(Bash)
+                .all_rcfile_candidates_with_env(&env)
+                .unwrap(),
+            vec![
+                home.join(".bash_profile"),
+                home.join(".bash_login"),
+                home.join(".bashrc")
+            ]
+        );
+        let zsh = Shell::Zsh(Zsh);
+        assert_eq!(
+            zsh.all_rcfile_candidates_with_env(&env).unwrap(),
+            vec![home.join(".config/zsh/.zshenv"), home.join(".zshenv")]
+        );
+        assert_eq!(
+            Shell::Fish(Fish)
+                .all_rcfile_candidates_with_env(&env)
+                .unwrap(),
+            vec![
+                home.join(".config/fish/conf.d"),
+                home.join(".config/fish/config.fish")
+            ]
+        );
+
+        // None of them exist, which get_existing_rcfiles reflects
+        assert!(zsh.get_existing_rcfiles_with_env(&env).unwrap().is_empty());
+        std::fs::write(home.join(".zshenv"), "").unwrap();
+        assert_eq!(
+            zsh.get_existing_rcfiles_with_env(&env).unwrap(),
+            vec![home.join(".zshenv")]
+        );
+
+        env.remove("ZDOTDIR");
+        assert_eq!(
+            zsh.all_rcfile_candidates_with_env(&env).unwrap(),
+            vec![home.join(".zshenv")]
+        );
+    }
+
+    #[test]
+    fn test_detect_installed_tiebreak() {
+        let installed = FakeDetection {
+            installed: &[Shell::Fish(Fish), Shell::Zsh(Zsh), Shell::Bash(Bash)],
+            files: &["/bin/sh"],
+            ..Default::default()
+        };
+        let (shell, source) = Shell::detect_with(&installed).unwrap();
+        assert!(matches!(shell, Shell::Bash(_)));
+        assert_eq!(source, DetectionSource::InstalledShell);
+
+        let no_bash = FakeDetection {
+            installed: &[Shell::Fish(Fish), Shell::Zsh(Zsh)],
+            ..installed
+        };
+        let (shell, _) = Shell::detect_with(&no_bash).unwrap();
+        assert!(matches!(shell, Shell::Zsh(_)));
+
+        // An unrecognized $SHELL mentioning an installed shell picks that one
+        let ambiguous = FakeDetection {
+            shell_var: Some("/usr/bin/fish-nightly"),
+            ..installed
+        };
+        let (shell, source) = Shell::detect_with(&ambiguous).unwrap();
+        assert!(matches!(shell, Shell::Fish(_)));
+        assert_eq!(source, DetectionSource::InstalledShell);
+
+        // ... but only if it is installed
+        let not_installed = FakeDetection {
+            shell_var: Some("/usr/bin/fish-nightly"),
+            installed: &[Shell::Bash(Bash)],
+            ..Default::default()
+        };
+        let (shell, source) = Shell::detect_with(&not_installed).unwrap();
+        assert!(matches!(shell, Shell::POSIX(_)));
+        assert_eq!(source, DetectionSource::ShellVar);
+
+        // The installed shell has to start the name, not just appear in it
+        let embedded = FakeDetection {
+            shell_var: Some("/opt/bin/notbash-wrapper"),
+            ..installed
+        };
+        let (shell, source) = Shell::detect_with(&embedded).unwrap();
+        assert!(matches!(shell, Shell::POSIX(_)));
+        assert_eq!(source, DetectionSource::ShellVar);
+
+        // Unsupported shells are reported rather than replaced by an installed one
+        let unsupported = FakeDetection {
+            shell_var: Some("/usr/bin/tcsh"),
+            installed: &[Shell::Bash(Bash), Shell::Zsh(Zsh)],
+            ..Default::default()
+        };
+        assert!(matches!(
+            Shell::detect_with(&unsupported),
+            Err(ShellError::DetectionFailed(_))
+        ));
+
+        // A recognized $SHELL wins over the installed shells
+        let recognized = FakeDetection {
+            shell_var: Some("/bin/zsh"),
+            ..installed
+        };
+        let (shell, source) = Shell::detect_with(&recognized).unwrap();
+        assert!(matches!(shell, Shell::Zsh(_)));
+        assert_eq!(source, DetectionSource::ShellVar);
+    }
+
+    #[test]
+    fn test_write_path_export() {
+        let home = temp_dir("write-path-export");
+        let env = HashMap::from([
+            ("HOME", home.clone()),
+            ("XDG_CONFIG_HOME", home.join(".config")),
+        ]);
+
+        let dir = Path::new("/opt/tool/bin");
+        let expected = [
+            (Shell::POSIX(POSIX), home.join(".profile")),
+            (Shell::Bash(Bash), home.join(".bashrc")),
+            (Shell::Zsh(Zsh), home.join(".zshenv")),
+            (Shell::Fish(Fish), home.join(".config/fish/config.fish")),
+        ];
+
+        for (shell, rcfile) in expected {
+            let line = shell.path_add_line(dir, Placement::Prepend);
+            assert_eq!(
+                shell
+                    .write_path_export_with_env(dir, Placement::Prepend, &env)
+                    .unwrap(),
+                rcfile
+            );
+            let content = std::fs::read_to_string(&rcfile).unwrap();
+            assert_eq!(content, format!("{}{line}\n", shell.rcfile_template()));
+
+            // Running again doesn't add the line twice
+            shell
+                .write_path_export_with_env(dir, Placement::Prepend, &env)
+                .unwrap();
+            assert_eq!(std::fs::read_to_string(&rcfile).unwrap(), content);
+        }
+
+        // Bash login shells get the line through their own file...
+        let home = temp_dir("write-path-export-login");
+        let env = HashMap::from([("HOME", home.clone())]);
+        let bash = Shell::Bash(Bash);
+        let line = bash.path_add_line(dir, Placement::Prepend);
+        std::fs::write(home.join(".profile"), "umask 022\n").unwrap();
+        bash.write_path_export_with_env(dir, Placement::Prepend, &env)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(home.join(".profile")).unwrap(),
+            format!("umask 022\n{line}\n")
+        );
+        assert!(!home.join(".bash_profile").exists());
+
+        // ...or through a new ~/.bash_profile that keeps reading ~/.profile and ~/.bashrc
+        let home = temp_dir("write-path-export-new-login");
+        let env = HashMap::from([("HOME", home.clone())]);
+        bash.write_path_export_with_env(dir, Placement::Prepend, &env)
+            .unwrap();
+        let bash_profile = std::fs::read_to_string(home.join(".bash_profile")).unwrap();
+        assert!(bash_profile.starts_with(bash.rcfile_template()));
+        assert!(bash_profile.contains("[ -f ~/.profile ] && . ~/.profile\n"));
+        assert!(bash_profile.contains("[ -f ~/.bashrc ] && . ~/.bashrc\n"));
+        assert!(!bash_profile.contains(&line));
+        bash.write_path_export_with_env(dir, Placement::Prepend, &env)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(home.join(".bash_profile")).unwrap(),
+            bash_profile
+        );
+        assert!(std::fs::read_to_string(home.join(".bashrc"))
+            .unwrap()
+            .contains(&line));
+        assert_eq!(
+            Bash.profile_chain_with_env(&env).unwrap(),
+            vec![home.join(".bash_profile"), home.join(".bashrc")]
+        );
+
+        // ...unless it sources ~/.bashrc
+        let home = temp_dir("write-path-export-sourced");
+        let env = HashMap::from([("HOME", home.clone())]);
+        let bash_profile = home.join(".bash_profile");
+        std::fs::write(&bash_profile, ". ~/.bashrc\n").unwrap();
+        bash.write_path_export_with_env(dir, Placement::Prepend, &env)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&bash_profile).unwrap(),
+            ". ~/.bashrc\n"
+        );
+        assert!(std::fs::read_to_string(home.join(".bashrc"))
+            .unwrap()
+            .contains(&line));
+    }
+
+    #[test]
+    fn test_error_hint() {
+        assert_eq!(
+            ShellError::NoHomeDir.hint(),
+            Some("set HOME or pass an explicit base directory")
+        );
+        let not_found = ShellError::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert_eq!(
+            not_found.hint(),
+            Some("create the rcfile first, or use a function that creates it")
+        );
+        let denied = ShellError::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert!(denied.hint().unwrap().contains("permissions"));
+        assert!(
+            ShellError::from(std::io::Error::from(std::io::ErrorKind::Other))
+                .hint()
+                .is_none()
+        );
+        assert!(ShellError::InvalidUtf8Output.hint().is_none());
+
+        // The message itself doesn't include the hint
+        assert_eq!(
+            ShellError::NoHomeDir.to_string(),
+            "Home directory not found"
+        );
+    }
+
+    #[test]
+    fn test_remove_managed_line() {
+        let dir = temp_dir("remove-managed-line");
+        let rcfile = dir.join(".bashrc");
+        let line = "export PATH=\"/opt/mytool/bin:$PATH\"";
+        std::fs::write(&rcfile, format!("{line}\n")).unwrap();
+
+        let shell = Shell::Bash(Bash);
+        shell
+            .append_to_rcfile_annotated(&rcfile, line, "mytool")
+            .unwrap();
+        shell
+            .append_managed_block(&rcfile, "mytool", &format!("{line}\nexport A=1"))
+            .unwrap();
+        shell.append_managed_block(&rcfile, "other", line).unwrap();
+
+        assert_eq!(
+            shell.remove_managed_line(&rcfile, line, "mytool").unwrap(),
+            2
+        );
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!(
+                "{line}\n# >>> mytool >>>\nexport A=1\n# <<< mytool <<<\n# >>> other >>>\n{line}\n# <<< other <<<\n"
+            )
+        );
+
+        // The user's own line and the other tool's block are left alone
+        assert_eq!(
+            shell.remove_managed_line(&rcfile, line, "mytool").unwrap(),
+            0
+        );
+        assert_eq!(
+            remove_from_rcfile(rcfile.clone(), &format!("{line}\n")).unwrap(),
+            2
+        );
+
+        std::fs::write(&rcfile, format!("# >>> mytool >>>\n{line}\n")).unwrap();
+        assert!(matches!(
+            shell.remove_managed_line(&rcfile, line, "mytool"),
+            Err(ShellError::UnterminatedManagedBlock(_))
+        ));
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            format!("# >>> mytool >>>\n{line}\n")
+        );
+    }
+
+    #[test]
+    fn test_export_template() {
+        for shell in [Shell::POSIX(POSIX), Shell::Bash(Bash), Shell::Zsh(Zsh)] {
+            assert_eq!(shell.export_template(), "export {name}=\"{value}\"");
+        }
+        assert_eq!(
+            Shell::Fish(Fish).export_template(),
+            "set -gx {name} {value}"
+        );
+
+        let line = Shell::Zsh(Zsh)
+            .export_template()
+            .replace("{name}", "EDITOR")
+            .replace("{value}", "vim");
+        assert_eq!(line, "export EDITOR=\"vim\"");
+    }
+
+    #[test]
+    fn test_resolve_var() {
+        let bash = Shell::Bash(Bash);
+        if !bash.does_exist() {
+            return;
+        }
+
+        let home = temp_dir("resolve-var");
+        std::fs::write(
+            home.join(".bashrc"),
+            "echo noise\nexport WTP_RESOLVED=\"from rc\"\nWTP_EMPTY=\n",
+        )
+        .unwrap();
+        let env = HashMap::from([
+            ("HOME", home.into_os_string()),
+            ("PATH", env::var_os("PATH").unwrap_or_default()),
+        ]);
+        let resolve = |name| bash.resolve_var_with_env(name, &env).unwrap();
+
+        assert_eq!(resolve("WTP_RESOLVED"), Some("from rc".to_string()));
+        assert_eq!(resolve("WTP_EMPTY"), Some(String::new()));
+        assert_eq!(resolve("WTP_UNSET"), None);
+        assert_eq!(resolve("WTP; echo injected"), None);
+    }
+
+    #[test]
+    fn test_local_rcfile() {
+        let home = temp_dir("local-rcfile");
+        let mut env = HashMap::from([("HOME", home.clone())]);
+        let zsh = Shell::Zsh(Zsh);
+        assert_eq!(zsh.local_rcfile_with_env(&env), None);
+
+        std::fs::write(
+            home.join(".zshrc"),
+            "# machine-specific settings\n[ -f ~/.zshrc.local ] && source ~/.zshrc.local\n",
+        )
+        .unwrap();
+        assert_eq!(
+            zsh.local_rcfile_with_env(&env),
+            Some(home.join(".zshrc.local"))
+        );
+
+        // A commented-out include doesn't count
+        std::fs::write(home.join(".bashrc"), "# . \"$HOME/.bashrc.local\"\n").unwrap();
+        let bash = Shell::Bash(Bash);
+        assert_eq!(bash.local_rcfile_with_env(&env), None);
+        std::fs::write(home.join(".bashrc"), ". \"$HOME/.bashrc.local\"\n").unwrap();
+        assert_eq!(
+            bash.local_rcfile_with_env(&env),
+            Some(home.join(".bashrc.local"))
+        );
+
+        // The include is looked for next to .zshrc in ZDOTDIR
+        let zdotdir = home.join(".config/zsh");
+        env.insert("ZDOTDIR", zdotdir.clone());
+        assert_eq!(zsh.local_rcfile_with_env(&env), None);
+        std::fs::create_dir_all(&zdotdir).unwrap();
+        std::fs::write(
+            zdotdir.join(".zshrc"),
+            "source ~/.config/zsh/.zshrc.local\n",
+        )
+        .unwrap();
+        assert_eq!(
+            zsh.local_rcfile_with_env(&env),
+            Some(zdotdir.join(".zshrc.local"))
+        );
+
+        assert_eq!(Shell::Fish(Fish).local_rcfile_with_env(&env), None);
+    }
+
+    #[test]
+    fn test_powershell_for_module_path() {
+        let pwsh = "C:\\Users\\me\\Documents\\PowerShell\\Modules;C:\\Program Files\\PowerShell\\Modules;c:\\program files\\powershell\\7\\Modules;C:\\Program Files\\WindowsPowerShell\\Modules;C:\\WINDOWS\\system32\\WindowsPowerShell\\v1.0\\Modules";
+        assert!(matches!(
+            powershell_for_module_path(pwsh),
+            Some(Shell::PowerShell(_))
+        ));
+
+        let windows_powershell = "C:\\Users\\me\\Documents\\WindowsPowerShell\\Modules;C:\\Program Files\\WindowsPowerShell\\Modules;C:\\WINDOWS\\system32\\WindowsPowerShell\\v1.0\\Modules";
+        assert!(matches!(
+            powershell_for_module_path(windows_powershell),
+            Some(Shell::WindowsPowerShell(_))
+        ));
+
+        // pwsh on Linux separates the entries with `:`
+        assert!(matches!(
+            powershell_for_module_path(
+                "/home/me/.local/share/powershell/Modules:/opt/microsoft/powershell/7/Modules"
+            ),
+            Some(Shell::PowerShell(_))
+        ));
+        assert!(powershell_for_module_path("C:\\tools\\Modules").is_none());
+        assert!(powershell_for_module_path("").is_none());
+
+        let profile = Path::new("/home/me/Documents/WindowsPowerShell/profile.ps1");
+        assert!(matches!(
+            shell_for_rcfile(profile),
+            Some(Shell::WindowsPowerShell(_))
+        ));
+        assert!(matches!(
+            shell_for_rcfile(Path::new("/home/me/.config/powershell/profile.ps1")),
+            Some(Shell::PowerShell(_))
+        ));
+        assert!(matches!("pwsh".parse(), Ok(Shell::PowerShell(_))));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_detect_powershell_windows() {
+        let env = HashMap::from([
+            ("USERPROFILE", "C:\\Users\\me"),
+            ("PATH", ""),
+            (
+                "PSModulePath",
+                "C:\\Users\\me\\Documents\\PowerShell\\Modules;C:\\Program Files\\PowerShell\\7\\Modules",
+            ),
+        ]);
+        let shell = Shell::detect_by_shell_var_with_env(&env).unwrap();
+        assert!(matches!(shell, Shell::PowerShell(_)));
+        assert_eq!(
+            shell.rc_file_for_path_edit_with_env(&env).unwrap(),
+            Path::new("C:\\Users\\me\\Documents\\PowerShell\\Microsoft.PowerShell_profile.ps1")
+        );
+
+        let provider = EnvDetection::new(&env);
+        assert!(matches!(
+            Shell::detect_with(&provider),
+            Ok((Shell::PowerShell(_), DetectionSource::PsModulePath))
+        ));
+
+        // Git Bash still sets SHELL
+        let git_bash = HashMap::from([("SHELL", "/usr/bin/bash")]);
+        assert!(matches!(
+            Shell::detect_by_shell_var_with_env(&git_bash),
+            Ok(Shell::Bash(_))
+        ));
+
+        // Nothing to go on
+        let bare = HashMap::from([("PATH", "")]);
+        assert!(matches!(
+            Shell::detect_by_shell_var_with_env(&bare),
+            Err(ShellError::UnsupportedPlatform)
+        ));
+    }
+
+    #[test]
+    fn test_append_multiline_rejected() {
+        let dir = temp_dir("append-multiline");
+        let rcfile = dir.join(".bashrc");
+        std::fs::write(&rcfile, "alias ll='ls -l'\n").unwrap();
+
+        let lines = "export PATH=\"/opt/a/bin:$PATH\"\nexport PATH=\"/opt/b/bin:$PATH\"";
+        assert!(matches!(
+            append_to_rcfile(rcfile.clone(), lines),
+            Err(ShellError::MultilineNotAllowed)
+        ));
+        assert!(matches!(
+            append_to_rcfile_if_missing(&rcfile, lines),
+            Err(ShellError::MultilineNotAllowed)
+        ));
+        assert!(append_to_rcfile_create(dir.join("new/.bashrc"), lines).is_err());
+        assert!(!dir.join("new/.bashrc").exists());
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "alias ll='ls -l'\n"
+        );
+
+        // Several lines belong in a managed block
+        Shell::Bash(Bash)
+            .append_managed_block(&rcfile, "tool", lines)
+            .unwrap();
+        assert!(rcfile_contains_line(&rcfile, "export PATH=\"/opt/b/bin:$PATH\"").unwrap());
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_rotating_backups() {
+        let home = temp_dir("rotating-backups");
+        let env = HashMap::from([("HOME", home.clone())]);
+        let backup_dir = Shell::rcfile_backup_dir_with_env(&env).unwrap();
+        assert!(backup_dir.starts_with(&home));
+        assert!(backup_dir.ends_with("what-the-path/backups"));
+
+        let rcfile = home.join(".bashrc");
+        for version in 1..=5 {
+            std::fs::write(&rcfile, format!("# version {version}\n")).unwrap();
+            let backup = backup_rcfile_with_env(&rcfile, 3, &env).unwrap();
+            assert!(backup.starts_with(&backup_dir));
+        }
+        std::fs::write(&rcfile, "# broken edit\n").unwrap();
+
+        let backups = rcfile_backups_with_env(&rcfile, &env).unwrap();
+        assert_eq!(backups.len(), 3);
+        assert_eq!(std::fs::read_dir(&backup_dir).unwrap().count(), 3);
+        assert_eq!(
+            std::fs::read_to_string(&backups[0]).unwrap(),
+            "# version 5\n"
+        );
+
+        let restored = restore_backup_with_env(&rcfile, 1, &env).unwrap();
+        assert_eq!(restored, backups[1]);
+        assert_eq!(std::fs::read_to_string(&rcfile).unwrap(), "# version 4\n");
+        assert_eq!(rcfile_backups_with_env(&rcfile, &env).unwrap(), backups);
+
+        assert!(matches!(
+            restore_backup_with_env(&rcfile, 3, &env),
+            Err(ShellError::NoSuchBackup(path, 3)) if path == rcfile
+        ));
+        assert!(rcfile_backups_with_env(&home.join(".zshrc"), &env)
+            .unwrap()
+            .is_empty());
+
+        // Same file name in another directory keeps its own history
+        let other = home.join("other");
+        std::fs::create_dir_all(&other).unwrap();
+        let other_rcfile = other.join(".bashrc");
+        std::fs::write(&other_rcfile, "# other\n").unwrap();
+        backup_rcfile_with_env(&other_rcfile, 3, &env).unwrap();
+        assert_eq!(rcfile_backups_with_env(&rcfile, &env).unwrap(), backups);
+        assert_eq!(
+            rcfile_backups_with_env(&other_rcfile, &env).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_parse_path_export() {
+        let bash = Shell::Bash(Bash);
+        assert_eq!(
+            bash.parse_path_export("export PATH=\"/opt/a/bin:$HOME/.local/bin:$PATH\""),
+            Some(vec![
+                PathBuf::from("/opt/a/bin"),
+                PathBuf::from("$HOME/.local/bin")
+            ])
+        );
+        assert_eq!(
+            bash.parse_path_export("PATH=$PATH:/opt/b/bin"),
+            Some(vec![PathBuf::from("/opt/b/bin")])
+        );
+        assert_eq!(
+            bash.parse_path_export("export PATH=\"$PATH\""),
+            Some(vec![])
+        );
+        assert_eq!(bash.parse_path_export("export EDITOR=vim"), None);
+        assert_eq!(bash.parse_path_export("alias ll='ls -l'"), None);
+        // Fish syntax isn't bash's
+        assert_eq!(bash.parse_path_export("fish_add_path /opt/a/bin"), None);
+
+        let fish = Shell::Fish(Fish);
+        assert_eq!(
+            fish.parse_path_export("set -gx PATH /opt/a/bin $PATH"),
+            Some(vec![PathBuf::from("/opt/a/bin")])
+        );
+        assert_eq!(
+            fish.parse_path_export("fish_add_path --append /opt/a/bin '/opt/b/bin'"),
+            Some(vec![
+                PathBuf::from("/opt/a/bin"),
+                PathBuf::from("/opt/b/bin")
+            ])
+        );
+        assert_eq!(fish.parse_path_export("set -gx EDITOR vim"), None);
+        assert_eq!(
+            fish.parse_path_export("export PATH=\"/opt/a/bin:$PATH\""),
+            None
+        );
+
+        let zsh = Shell::Zsh(Zsh);
+        assert_eq!(
+            zsh.parse_path_export("path+=(/opt/a/bin $path)"),
+            Some(vec![PathBuf::from("/opt/a/bin")])
+        );
+
+        let pwsh = Shell::PowerShell(PowerShell);
+        let dir = Path::new("/opt/a/bin");
+        for placement in [Placement::Prepend, Placement::Append] {
+            let line = pwsh.path_add_line(dir, placement);
+            assert_eq!(pwsh.parse_path_export(&line), Some(vec![dir.to_path_buf()]));
+            assert_eq!(bash.parse_path_export(&line), None);
+        }
+        assert_eq!(pwsh.parse_path_export("$env:EDITOR = \"code\""), None);
+    }
+
+    #[test]
+    fn test_every_shell_has_rcfile() {
+        let home = temp_dir("editable-rcfile");
+        let env = HashMap::from([("HOME", home.clone()), ("USERPROFILE", home)]);
+        for shell in Shell::all() {
+            assert!(
+                shell.rc_file_for_path_edit_with_env(&env).is_ok(),
+                "{shell:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_activation_instructions() {
+        assert_eq!(
+            Shell::Zsh(Zsh).activation_instructions(Path::new("/home/me/.zshrc")),
+            "Run `. \"/home/me/.zshrc\"` to update this session, or open a new terminal for the \
+             change to take effect."
+        );
+        assert_eq!(
+            Shell::Bash(Bash).activation_instructions(Path::new("/home/me/.bash_profile")),
+            "Run `. \"/home/me/.bash_profile\"` to update this session, or log out and back in \
+             for the change to take effect."
+        );
+        assert_eq!(
+            Shell::POSIX(POSIX).activation_instructions(Path::new("/home/me/.profile")),
+            "Run `. \"/home/me/.profile\"` to update this session, or log out and back in for \
+             the change to take effect."
+        );
+        assert_eq!(
+            Shell::Fish(Fish)
+                .activation_instructions(Path::new("/home/me/.config/fish/config.fish")),
+            "Run `source /home/me/.config/fish/config.fish` to update this session, or open a \
+             new terminal for the change to take effect."
+        );
+        assert_eq!(
+            Shell::PowerShell(PowerShell)
+                .activation_instructions(Path::new("/home/me/.config/powershell/profile.ps1")),
+            "Run `. '/home/me/.config/powershell/profile.ps1'` to update this session, or open a \
+             new terminal for the change to take effect."
+        );
+    }
+
+    #[test]
+    fn test_dedup_rcfile_path_entries() {
+        let rcfile = temp_dir("dedup-path-entries").join(".bashrc");
+        std::fs::write(
+            &rcfile,
+            "export PATH=\"/opt/a/bin:$PATH\"\n\
+             export EDITOR=vim\n\
+             export PATH=\"/opt/a/bin:/opt/b/bin:$PATH\" # tool b\n\
+             PATH=$PATH:/opt/b/bin:/opt/c/bin:/opt/c/bin\n\
+             export PATH=\"/opt/a/bin:$PATH\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(dedup_rcfile_path_entries(&rcfile).unwrap(), 4);
+        assert_eq!(
+            std::fs::read_to_string(&rcfile).unwrap(),
+            "export PATH=\"/opt/a/bin:$PATH\"\n\
+             export EDITOR=vim\n\
+             export PATH=\"/opt/b/bin:$PATH\" # tool b\n\
+             PATH=$PATH:/opt/c/bin\n"
+        );
+
+        // Nothing left to remove
+        assert_eq!(dedup_rcfile_path_entries(&rcfile).unwrap(), 0);
+
+        // Lines that may not run are neither deduplicated nor deduplicated against
+        let conditional = "if [ -d /opt/a/bin ]; then\n\
+                           export PATH=\"/opt/a/bin:$PATH\"\n\
+                           fi\n\
+                           case $TERM in\n\
+                           \x20 xterm) export PATH=\"/opt/b/bin:$PATH\" ;;\n\
+                           esac\n\
+                           export PATH=\"/opt/a/bin:/opt/b/bin:$PATH\"\n\
+                           \x20 export PATH=\"/opt/b/bin:$PATH\"\n";
+        std::fs::write(&rcfile, conditional).unwrap();
+        assert_eq!(dedup_rcfile_path_entries(&rcfile).unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&rcfile).unwrap(), conditional);
+    }
+
+    #[test]
+    fn test_from_name_lenient() {
+        let cases = [
+            ("sh", "sh"),
+            ("posix", "sh"),
+            ("dash", "sh"),
+            ("ash", "sh"),
+            ("Bash", "bash"),
+            ("bourne-again", "bash"),
+            ("bash_shell", "bash"),
+            ("zsh", "zsh"),
+            ("zshell", "zsh"),
+            ("Z Shell", "zsh"),
+            ("zsh-shell", "zsh"),
+            ("fish", "fish"),
+            ("fishshell", "fish"),
+            ("fish-shell", "fish"),
+            ("pwsh", "pwsh"),
+            ("pwsh.exe", "pwsh"),
+            ("PowerShell-Core", "pwsh"),
+            ("pwsh core", "pwsh"),
+            ("ps7", "pwsh"),
+            ("powershell", "powershell"),
+            (" PowerShell.exe ", "powershell"),
+            ("Windows PowerShell", "powershell"),
+            ("ps", "powershell"),
+        ];
+        for (name, binary) in cases {
+            let shell = Shell::from_name_lenient(name).unwrap_or_else(|| panic!("{name}"));
+            assert_eq!(shell.binary_name(), binary, "{name}");
+        }
+
+        for unknown in ["", "nu", "cmd", "zshrc", "fishy", "bash5"] {
+            assert!(Shell::from_name_lenient(unknown).is_none(), "{unknown}");
+        }
+        // The strict parser still only takes binary names
+        assert!("zshell".parse::<Shell>().is_err());
+        assert!("Bash".parse::<Shell>().is_err());
+    }
+
+    #[test]
+    fn test_append_outcome() {
+        let dir = temp_dir("append-outcome");
+        let line = "export PATH=\"/opt/tool/bin:$PATH\"";
+
+        let missing = dir.join("missing/.bashrc");
+        assert_eq!(
+            append_to_rcfile_create_if_missing(&missing, line).unwrap(),
+            AppendOutcome::Created
+        );
+        assert_eq!(
+            append_to_rcfile_create_if_missing(&missing, line).unwrap(),
+            AppendOutcome::AlreadyPresent
+        );
+        assert!(!AppendOutcome::AlreadyPresent.changed());
+
+        let empty = dir.join(".profile");
+        std::fs::write(&empty, "").unwrap();
+        assert_eq!(
+            append_to_rcfile_create(empty.clone(), line).unwrap(),
+            AppendOutcome::Appended
+        );
+        assert_eq!(
+            std::fs::read_to_string(&empty).unwrap(),
+            format!("{line}\n")
+        );
+
+        let existing = dir.join(".zshrc");
+        std::fs::write(&existing, format!("alias ll='ls -l'\n{line}\n")).unwrap();
+        assert_eq!(
+            append_to_rcfile_if_missing(&existing, line).unwrap(),
+            AppendOutcome::AlreadyPresent
+        );
+        assert_eq!(
+            append_to_rcfile_create_if_missing(&existing, "export EDITOR=vi").unwrap(),
+            AppendOutcome::Appended
+        );
+        assert!(AppendOutcome::Appended.changed());
+    }
+
+    #[test]
+    fn test_shell_override() {
+        for shell in Shell::all() {
+            let env = HashMap::from([
+                ("SHELL", "/bin/zsh"),
+                (SHELL_OVERRIDE_VAR, shell.binary_name()),
+            ]);
+            let (detected, source) = Shell::detect_with(&EnvDetection::new(&env)).unwrap();
+            assert_eq!(detected.binary_name(), shell.binary_name());
+            assert_eq!(source, DetectionSource::Override);
+        }
+
+        // Aliases work, unknown names fall back to regular detection
+        let env = HashMap::from([("SHELL", "/bin/zsh"), (SHELL_OVERRIDE_VAR, "Fish-Shell")]);
+        let provider = EnvDetection::new(&env);
+        assert!(matches!(
+            Shell::detect_with(&provider),
+            Ok((Shell::Fish(_), DetectionSource::Override))
+        ));
+        let env = HashMap::from([("SHELL", "/bin/zsh"), (SHELL_OVERRIDE_VAR, "nushell")]);
+        let provider = EnvDetection::new(&env);
+        assert!(!matches!(
+            Shell::detect_with(&provider),
+            Ok((_, DetectionSource::Override))
+        ));
+    }
+
+    #[test]
+    fn test_iter_rcfiles_with_contents() {
+        let home = temp_dir("rcfiles-with-contents");
+        let env = HashMap::from([("HOME", &home)]);
+        std::fs::write(home.join(".bash_profile"), ". ~/.bashrc\n").unwrap();
+        std::fs::write(home.join(".bashrc"), "export EDITOR=vi\n").unwrap();
+
+        assert_eq!(
+            Shell::Bash(Bash)
+                .iter_rcfiles_with_contents_with_env(&env)
+                .unwrap(),
+            vec![
+                (home.join(".bash_profile"), ". ~/.bashrc\n".to_string()),
+                (home.join(".bashrc"), "export EDITOR=vi\n".to_string()),
+            ]
+        );
+        assert!(Shell::POSIX(POSIX)
+            .iter_rcfiles_with_contents_with_env(&env)
+            .unwrap()
+            .is_empty());
+
+        // conf.d is read file by file, and files that aren't text are skipped
+        let config = home.join(".config");
+        let env = HashMap::from([("HOME", &home), ("XDG_CONFIG_HOME", &config)]);
+        let conf_d = config.join("fish/conf.d");
+        std::fs::create_dir_all(&conf_d).unwrap();
+        std::fs::write(conf_d.join("b.fish"), "set -gx B 1\n").unwrap();
+        std::fs::write(conf_d.join("a.fish"), "set -gx A 1\n").unwrap();
+        std::fs::write(conf_d.join("binary"), [0x80, 0x81, 0xc3]).unwrap();
+        std::fs::write(config.join("fish/config.fish"), "fish_add_path /opt/bin\n").unwrap();
+        let files: Vec<PathBuf> = Shell::Fish(Fish)
+            .iter_rcfiles_with_contents_with_env(&env)
+            .unwrap()
+            .into_iter()
+            .map(|(file, _)| file)
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                conf_d.join("a.fish"),
+                conf_d.join("b.fish"),
+                config.join("fish/config.fish")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shell_equality() {
+        let sh = Shell::detect_from_str("/bin/sh").unwrap();
+        let dash = Shell::detect_from_str("/usr/bin/dash").unwrap();
+        assert_eq!(sh, dash);
+        assert!(sh.eq_semantic(&dash));
+        assert_eq!(sh, Shell::POSIX(POSIX));
+
+        assert_eq!(Shell::detect_from_str("zsh-5.9").unwrap(), Shell::Zsh(Zsh));
+        assert_ne!(Shell::Bash(Bash), Shell::POSIX(POSIX));
+        assert!(!Shell::Bash(Bash).eq_semantic(&Shell::POSIX(POSIX)));
+        assert_ne!(
+            Shell::PowerShell(PowerShell),
+            Shell::WindowsPowerShell(WindowsPowerShell)
+        );
+
+        let all = Shell::all();
+        for (i, shell) in all.iter().enumerate() {
+            for (j, other) in all.iter().enumerate() {
+                assert_eq!(shell == other, i == j);
+                assert_eq!(shell.eq_semantic(other), i == j);
+            }
+        }
+        assert_eq!(Fish, Fish);
+    }
+
+    #[test]
+    fn test_xdg_rcfile() {
+        let home = temp_dir("xdg-rcfile");
+        let config = home.join("config");
+        let zdotdir = config.join("zsh");
+        let mut env = HashMap::from([
+            ("HOME", home.clone()),
+            ("XDG_CONFIG_HOME", config.clone()),
+            ("ZDOTDIR", zdotdir.clone()),
+        ]);
+        let zsh = Shell::Zsh(Zsh);
+        assert_eq!(zsh.xdg_rcfile_with_env(&env), Some(zdotdir.join(".zshenv")));
+        assert_eq!(
+            zsh.xdg_rcfile_with_env(&env),
+            zsh.rc_file_for_path_edit_with_env(&env).ok()
+        );
+
+        // A ZDOTDIR elsewhere isn't an XDG setup
+        env.insert("ZDOTDIR", home.join(".zsh"));
+        assert_eq!(zsh.xdg_rcfile_with_env(&env), None);
+        env.remove("ZDOTDIR");
+        assert_eq!(zsh.xdg_rcfile_with_env(&env), None);
+
+        // Bash sourcing its configuration from the XDG config directory
+        let bash = Shell::Bash(Bash);
+        assert_eq!(bash.xdg_rcfile_with_env(&env), None);
+        std::fs::write(
+            home.join(".bashrc"),
+            format!(". {}\n", config.join("bash/bashrc").display()),
+        )
+        .unwrap();
+        assert_eq!(
+            bash.xdg_rcfile_with_env(&env),
+            Some(config.join("bash/bashrc"))
+        );
+    }
+
+    #[test]
+    fn test_no_writable_rcfile() {
+        fn set_readonly(path: &Path, readonly: bool) {
+            let mut permissions = std::fs::metadata(path).unwrap().permissions();
+            permissions.set_readonly(readonly);
+            std::fs::set_permissions(path, permissions).unwrap();
+        }
+
+        let home = temp_dir("no-writable-rcfile");
+        let env = HashMap::from([("HOME", &home)]);
+        let bash = Shell::Bash(Bash);
+        let line = "export PATH=\"/opt/tool/bin:$PATH\"";
+        let rcfiles = [home.join(".bash_profile"), home.join(".bashrc")];
+        for rcfile in &rcfiles {
+            std::fs::write(rcfile, "# managed elsewhere\n").unwrap();
+            set_readonly(rcfile, true);
+        }
+
+        match bash.append_to_all_existing_with_env(line, &env) {
+            Err(ShellError::NoWritableRcfile { shell, tried }) => {
+                assert_eq!(shell, "bash");
+                assert_eq!(tried, rcfiles);
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        assert!(matches!(
+            bash.write_path_export_with_env(Path::new("/opt/tool/bin"), Placement::Prepend, &env),
+            Err(ShellError::NoWritableRcfile { tried, .. }) if tried == [home.join(".bashrc")]
+        ));
+
+        // One writable rcfile is enough
+        set_readonly(&rcfiles[1], false);
+        assert_eq!(
+            bash.append_to_all_existing_with_env(line, &env).unwrap(),
+            vec![(rcfiles[1].clone(), AppendOutcome::Appended)]
+        );
+        set_readonly(&rcfiles[0], false);
+    }
+
+    #[test]
+    fn test_dropin_filename() {
+        for (shell, extension) in [
+            (Shell::POSIX(POSIX), Some("sh")),
+            (Shell::Bash(Bash), Some("sh")),
+            (Shell::Zsh(Zsh), None),
+            (Shell::Fish(Fish), Some("fish")),
+            (Shell::PowerShell(PowerShell), None),
+            (Shell::WindowsPowerShell(WindowsPowerShell), None),
+        ] {
+            assert_eq!(shell.dropin_extension(), extension, "{shell:?}");
+            assert_eq!(
+                shell.dropin_filename("mytool"),
+                extension.map(|extension| format!("mytool.{extension}")),
+                "{shell:?}"
+            );
+        }
+
+        // init_order only picks up conf.d files with the drop-in extension
+        let config = temp_dir("dropin-filename");
+        let env = HashMap::from([("HOME", &config), ("XDG_CONFIG_HOME", &config)]);
+        let fish = Shell::Fish(Fish);
+        let conf_d = config.join("fish/conf.d");
+        std::fs::create_dir_all(&conf_d).unwrap();
+        std::fs::write(conf_d.join(fish.dropin_filename("mytool").unwrap()), "").unwrap();
+        std::fs::write(conf_d.join("mytool.sh"), "").unwrap();
+        assert_eq!(
+            fish.init_order_with_env(&env).unwrap(),
+            vec![conf_d.join("mytool.fish"), config.join("fish/config.fish")]
+        );
+
+        // Fish::write_dropin writes there too
+        let snippet =
+            fish.install_snippet(&[PathBuf::from("/opt/other/bin")], Placement::Prepend, None);
+        let dropin = Fish.write_dropin_with_env("other", &snippet, &env).unwrap();
+        assert_eq!(dropin, conf_d.join("other.fish"));
+        assert_eq!(
+            std::fs::read_to_string(&dropin).unwrap(),
+            "fish_add_path /opt/other/bin\n"
+        );
+        assert!(matches!(
+            Fish.write_dropin_with_env("../escape", "", &env),
+            Err(ShellError::InvalidPathEntry(_))
+        ));
+    }
+
+    #[test]
+    fn test_detect_by_shell_var_raw() {
+        let env = HashMap::from([("SHELL", "/opt/homebrew/bin/fish")]);
+        let (shell, raw) = Shell::detect_by_shell_var_raw_with_env(&env).unwrap();
+        assert_eq!(shell, Shell::Fish(Fish));
+        assert_eq!(raw, "/opt/homebrew/bin/fish");
+        assert_eq!(Shell::detect_by_shell_var_with_env(&env).unwrap(), shell);
+
+        let env = HashMap::from([("SHELL", "/usr/local/bin/bash5")]);
+        assert_eq!(
+            Shell::detect_by_shell_var_raw_with_env(&env).unwrap(),
+            (Shell::Bash(Bash), "/usr/local/bin/bash5".to_string())
+        );
+        assert!(matches!(
+            Shell::detect_by_shell_var_raw_with_env(&HashMap::<&str, &str>::new()),
+            Err(ShellError::NoShellVar)
+        ));
+    }
+
+    #[test]
+    fn test_env_detection_ignores_parent_process() {
+        // Whatever shell runs the tests, only the env decides
+        for shell in ["/bin/zsh", "/usr/bin/fish", "/bin/bash", "/bin/sh"] {
+            let env = HashMap::from([("SHELL", shell)]);
+            let (detected, source) = Shell::detect_with(&EnvDetection::new(&env)).unwrap();
+            assert_eq!(detected, Shell::detect_from_str(shell).unwrap());
+            assert_eq!(source, DetectionSource::ShellVar);
+        }
     }
-}
\ No newline at end of file
+}